@@ -2,7 +2,7 @@ use std::{
     convert::Infallible,
     net::SocketAddr,
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
     time::Duration,
 };
 
@@ -11,6 +11,7 @@ use axum_core::{extract::Request, response::Response};
 use bytes::BytesMut;
 use thiserror::Error;
 use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
     net::{TcpListener, TcpStream},
     sync::{
         broadcast,
@@ -18,18 +19,35 @@ use tokio::{
     },
     time::Instant,
 };
+use tokio_rustls::TlsAcceptor;
 use tower::Service;
 use tracing::{debug, info, warn};
 
 use crate::{
+    ban::BanList,
     config::Config,
+    connection_registry::{ConnectionLimits, ConnectionRegistry, Protocol},
+    error::{HandshakeError, TlsError},
+    event::ConnectionKind,
+    flood::{AcceptPermit, FloodConfig, FloodGuard},
     http::{HttpSvc, MyConnectInfo, ShutdownAndNotifySet},
-    pcp::{procedure::PcpHandshake, ChannelManager, GnuId},
+    listener_token, metrics,
+    pcp::{
+        builder::HostBuilder,
+        decode::HostFlags1,
+        procedure::{
+            read_channel_request, reject_banned, reject_relay_full, relay_out, PcpHandshake,
+        },
+        ChannelManager, GnuId,
+    },
+    recorder::{RecorderConfig, RecorderManager},
     rtmp::{
         connection,
         stream_manager::{self, StreamManagerMessage},
     },
-    util::{identify_protocol, ConnectionProtocol, Shutdown},
+    tls::TlsConfig,
+    util::{identify_protocol_timeout, ConnectionProtocol, IdentifierError, Shutdown},
+    yp::{YpBrowser, YpConfig},
     ConnectionId,
 };
 
@@ -45,6 +63,9 @@ pub enum CuiError {
 
     #[error("IoError: {0}")]
     Io(#[from] std::io::Error),
+
+    #[error("failed to setup TLS: {0}")]
+    Tls(#[from] TlsError),
 }
 pub struct CuiApp {
     config_path: PathBuf,
@@ -149,14 +170,34 @@ impl CuiApp {
 
     // TODO: ApplicationをRestartする機能を付けるならResult<ShutdownOrRestart, TuiError>っていう変数を返せばよさそう
     async fn main(&mut self) -> Result<(), CuiError> {
+        // 配信チャンネルのIDは配信者ID(BCID)から作るので、無ければ作って保存しておく
+        if self.config.broadcast_id.is_none() {
+            self.config.broadcast_id = Some(GnuId::new_random());
+            match self.config.save_file(&self.config_path) {
+                Ok(()) => info!("broadcast id saved to {:?}", self.config_path),
+                Err(e) => warn!("failed to save broadcast id to {:?}: {e}", self.config_path),
+            }
+        }
         let self_session_id = GnuId::new();
         let channel_manager = ChannelManager::new(&self_session_id);
         let manager_sender = stream_manager::start();
+        let recorder_manager = RecorderManager::new(RecorderConfig::from_config(&self.config));
+        let yp_browser = YpBrowser::new(
+            YpConfig::from_config(&self.config),
+            channel_manager.connections(),
+        );
+        let _yp_handle = yp_browser.start();
+        let ban_list = BanList::new(self.config.ban_list.clone());
+        // APIから変更された内容をPCPの受付でも見る
+        let shared_config = Arc::new(RwLock::new(self.config.clone()));
         let http_svc = HttpSvc::new(
             self.config_path.clone(),
-            self.config.clone(),
+            Arc::clone(&shared_config),
             self_session_id,
             Arc::clone(&channel_manager),
+            recorder_manager,
+            yp_browser,
+            Arc::clone(&ban_list),
             Arc::new(manager_sender.clone()),
         );
 
@@ -173,10 +214,38 @@ impl CuiApp {
         let rtmp_listener = tokio::net::TcpListener::bind(rtmp_addr.clone()).await?;
         let _rtmp_handle = tokio::spawn(Self::spawn_rtmp_server(
             manager_sender.clone(),
+            channel_manager.connections(),
+            Arc::clone(&ban_list),
             rtmp_listener,
             rtmp_addr,
         ));
 
+        // HTTPS
+        // tls_portが0ならPCP/HTTPと同じポートでClientHelloを判別して受け付ける
+        let flood_guard = FloodGuard::new(FloodConfig::from_config(&self.config));
+        let tls_acceptor = match TlsConfig::from_config(&self.config, &self.config_path) {
+            None => None,
+            Some(tls) if tls.port == 0 => {
+                info!("https      -> https://localhost:{}/ui/", c.server_port);
+                Some(tls.acceptor()?)
+            }
+            Some(tls) => {
+                let https_addr = format!("{}:{}", c.server_address.to_ipaddr(), tls.port);
+                info!("https      -> https://localhost:{}/ui/", tls.port);
+                let https_listener = tokio::net::TcpListener::bind(https_addr).await?;
+                let _https_handle = tokio::spawn(Self::spawn_https_listener(
+                    https_listener,
+                    tls.acceptor()?,
+                    http_svc.clone(),
+                    Arc::clone(&flood_guard),
+                    Arc::clone(&ban_list),
+                    self.notify_shutdown_tx.clone(),
+                    self.shutdown_complete_tx.clone(),
+                ));
+                None
+            }
+        };
+
         // PORT CHECK
         let server_port = c.server_port;
         let port_check_handle = tokio::spawn(async move {
@@ -242,27 +311,59 @@ impl CuiApp {
             let shutdown_set = (shutdown, shutdown_complete_tx);
             // pcp
             let cloned_channel_manager = Arc::clone(&channel_manager);
+            let cloned_config = Arc::clone(&shared_config);
             // http
             let cloned_http_service = http_svc.clone();
+            let cloned_tls_acceptor = tls_acceptor.clone();
             // rtmp
             let cloned_manager_sender = manager_sender.clone();
             // let cloned_local_address = Arc::clone(&local_address);
 
             let (tcp_stream, remote_addr) = listener.accept().await?;
+            // 断る場合は何も返さずに閉じる
+            let permit = match flood_guard.accept(remote_addr.ip()) {
+                Ok(permit) => permit,
+                Err(e) => {
+                    debug!("reject connection from {remote_addr}: {e}");
+                    continue 'accept_loop;
+                }
+            };
+            let cloned_flood_guard = Arc::clone(&flood_guard);
+            let banned = ban_list.check(remote_addr.ip());
 
             tokio::spawn(async move {
                 info!("Next is Connection: {connection_id} {remote_addr:?}");
-                let protocol = match identify_protocol(&tcp_stream).await {
+                let timeout = cloned_flood_guard.handshake_timeout();
+                let protocol = match identify_protocol_timeout(&tcp_stream, timeout).await {
                     Ok(p) => p,
+                    Err(IdentifierError::Timeout) => {
+                        warn!("protocol identify timeout {remote_addr}");
+                        cloned_flood_guard.handshake_failed(remote_addr.ip());
+                        return;
+                    }
                     Err(e) => {
                         warn!("comming protocol identify error :{e}");
+                        cloned_flood_guard.handshake_failed(remote_addr.ip());
                         ConnectionProtocol::Unknown
                     }
                 };
+                if banned {
+                    Self::reject_banned(
+                        protocol,
+                        cloned_channel_manager.session_id(),
+                        tcp_stream,
+                        remote_addr,
+                        timeout,
+                    )
+                    .await;
+                    return;
+                }
                 match &protocol {
                     ConnectionProtocol::PeerCast => {
                         Self::spawn_pcp_portcheck(
                             cloned_channel_manager,
+                            cloned_flood_guard,
+                            permit,
                             //
                             connection_id,
                             tcp_stream,
@@ -274,7 +375,11 @@ impl CuiApp {
                     ConnectionProtocol::PeerCastHttp => {
                         Self::spawn_pcp_server(
                             cloned_channel_manager,
+                            cloned_config,
                             cloned_manager_sender,
+                            cloned_flood_guard,
+                            permit,
+                            //
                             connection_id,
                             tcp_stream,
                             remote_addr,
@@ -299,6 +404,22 @@ impl CuiApp {
                         )
                         .await;
                     }
+                    ConnectionProtocol::Tls => match cloned_tls_acceptor {
+                        Some(acceptor) => {
+                            Self::serve_https(
+                                acceptor,
+                                cloned_flood_guard,
+                                connection_id,
+                                tcp_stream,
+                                remote_addr,
+                                shutdown_set,
+                                cloned_http_service
+                                    .into_make_service_with_connect_info::<MyConnectInfo>(),
+                            )
+                            .await;
+                        }
+                        None => debug!("TLS is not enabled. close {remote_addr}"),
+                    },
                 };
             });
             // tokio::spawn
@@ -308,6 +429,8 @@ impl CuiApp {
 
     async fn spawn_rtmp_server(
         manager_sender: UnboundedSender<StreamManagerMessage>,
+        connections: Arc<ConnectionRegistry>,
+        ban_list: Arc<BanList>,
         listener: TcpListener,
         rtmp_addr: String,
     ) -> Result<(), std::io::Error> {
@@ -315,9 +438,17 @@ impl CuiApp {
 
         loop {
             let (stream, connection_info) = listener.accept().await?;
+            if ban_list.check(connection_info.ip()) {
+                info!("rejected banned RTMP connection from {connection_info}");
+                continue;
+            }
             let current_id = ConnectionId::new();
 
-            let connection = connection::Connection::new(current_id.0, manager_sender.clone());
+            let connection = connection::Connection::new(
+                current_id.0,
+                manager_sender.clone(),
+                Arc::clone(&connections),
+            );
             println!(
                 "Connection {}: Connection received from {}",
                 current_id.0,
@@ -328,9 +459,44 @@ impl CuiApp {
         }
     }
 
+    // PCPにはBANされた事を伝え、それ以外は403を返して閉じる
+    async fn reject_banned(
+        protocol: ConnectionProtocol,
+        session_id: GnuId,
+        mut tcp_stream: TcpStream,
+        remote_addr: SocketAddr,
+        timeout: Duration,
+    ) {
+        match protocol {
+            ConnectionProtocol::PeerCast => {
+                let r = tokio::time::timeout(
+                    timeout,
+                    reject_banned(&mut tcp_stream, remote_addr, session_id),
+                )
+                .await;
+                if let Ok(Err(e)) = r {
+                    debug!("failed to reject banned PCP connection {remote_addr}: {e}");
+                }
+            }
+            ConnectionProtocol::Tls => {
+                info!("rejected banned TLS connection from {remote_addr}");
+                let _ = tcp_stream.shutdown().await;
+            }
+            _ => {
+                info!("rejected banned connection from {remote_addr}");
+                let _ = tcp_stream
+                    .write_all(b"HTTP/1.0 403 Forbidden\r\n\r\n")
+                    .await;
+                let _ = tcp_stream.shutdown().await;
+            }
+        }
+    }
+
     // https://github.com/plonk/peercast-yt/blob/787be6405cc2d82a5d26c0023aaa5d1973c13802/core/common/servent.cpp#L1250
     async fn spawn_pcp_portcheck(
         channel_manager: Arc<ChannelManager>,
+        flood_guard: Arc<FloodGuard>,
+        permit: AcceptPermit,
         //
         connection_id: ConnectionId,
         tcp_stream: TcpStream,
@@ -340,47 +506,266 @@ impl CuiApp {
         let _handle = tokio::task::spawn(async move {
             info!("incomming PCP Port Check");
 
-            let x = PcpHandshake::new(
+            let mut handshake = PcpHandshake::new(
                 connection_id,
                 tcp_stream,
                 None,
                 remote_addr,
                 BytesMut::with_capacity(4096),
                 channel_manager.session_id(),
-            )
-            .incoming(channel_manager)
-            .await;
+            );
+            let timeout = flood_guard.handshake_timeout();
+            let x = match tokio::time::timeout(timeout, handshake.incoming(channel_manager)).await {
+                Ok(r) => r,
+                Err(_) => Err(HandshakeError::Timeout),
+            };
+            if let Err(e) = &x {
+                metrics::handshake_failed(e);
+                flood_guard.handshake_failed(remote_addr.ip());
+            }
 
+            drop(permit);
             drop(shutdown_set)
         });
     }
 
     async fn spawn_pcp_server(
         channel_manager: Arc<ChannelManager>,
+        config: Arc<RwLock<Config>>,
         rtmp_stream_manager: UnboundedSender<StreamManagerMessage>,
+        flood_guard: Arc<FloodGuard>,
+        permit: AcceptPermit,
         //
         connection_id: ConnectionId,
-        tcp_stream: TcpStream,
+        mut tcp_stream: TcpStream,
         remote_addr: SocketAddr,
         shutdown_set: ShutdownAndNotifySet,
     ) {
         let handle = tokio::task::spawn(async move {
-            // channel_manager.get(co);
             info!("incomming PCP Protocol");
-            // connection.start_negotiation(stream).await
-            drop(shutdown_set)
+            let mut read_buf = BytesMut::with_capacity(4096);
+            let timeout = flood_guard.handshake_timeout();
+            let req = match tokio::time::timeout(
+                timeout,
+                read_channel_request(&mut tcp_stream, &mut read_buf),
+            )
+            .await
+            {
+                Ok(r) => r,
+                Err(_) => Err(HandshakeError::Timeout),
+            };
+            let req = match req {
+                Ok(req) => req,
+                Err(e) => {
+                    debug!("invalid PCP relay request from {remote_addr}: {e}");
+                    metrics::handshake_failed(&e);
+                    flood_guard.handshake_failed(remote_addr.ip());
+                    return;
+                }
+            };
+
+            let Some(ch) = channel_manager.get(&req.channel_id) else {
+                debug!("PCP relay request for unknown CID:{}", req.channel_id);
+                let _ = tcp_stream
+                    .write_all(b"HTTP/1.0 404 Not Found\r\n\r\n")
+                    .await;
+                let _ = tcp_stream.shutdown().await;
+                return;
+            };
+
+            // 限定公開チャンネルはトークンを確認する
+            let config = config.read().unwrap().clone();
+            if let Err(e) =
+                listener_token::authorize(&ch, config.token_secret.as_deref(), req.token.as_deref())
+                    .await
+            {
+                info!(
+                    "reject PCP relay request from {remote_addr} CID:{}: {e}",
+                    req.channel_id
+                );
+                let _ = tcp_stream
+                    .write_all(b"HTTP/1.0 403 Forbidden\r\n\r\n")
+                    .await;
+                let _ = tcp_stream.shutdown().await;
+                return;
+            }
+
+            // リレー数の上限を超えているか上り帯域を使い切っていたら、上流を次の接続先として教えて断る
+            let mut limits = ConnectionLimits::from_config(&config);
+            if config
+                .local_address
+                .iter()
+                .any(|net| net.contains(&remote_addr.ip()))
+            {
+                limits.max_connections_per_ip = 0;
+            }
+            let session_id = channel_manager.session_id();
+            let upload_limiter = channel_manager.upload_limiter();
+            let registered = if upload_limiter.is_relay_full(&ch.id()) {
+                Err("upload bandwidth is exhausted".to_string())
+            } else {
+                channel_manager
+                    .connections()
+                    .try_register(
+                        connection_id,
+                        Protocol::PcpRelayOut,
+                        remote_addr,
+                        ch.id(),
+                        &limits,
+                    )
+                    .map_err(|e| e.to_string())
+            };
+            let mut handle = match registered {
+                Ok(handle) => handle,
+                Err(e) => {
+                    info!(
+                        "reject PCP relay request from {remote_addr} CID:{}: {e}",
+                        ch.id()
+                    );
+                    let hosts = ch
+                        .upstream_addr()
+                        .map(|addr| {
+                            let flags1 = HostFlags1::NONE.set_relay(true).set_recv(true);
+                            HostBuilder::new(ch.id(), addr).flags1(flags1).build()
+                        })
+                        .into_iter()
+                        .collect();
+                    if let Err(e) =
+                        reject_relay_full(&mut tcp_stream, read_buf, remote_addr, session_id, hosts)
+                            .await
+                    {
+                        debug!("failed to redirect PCP relay {remote_addr}: {e}");
+                    }
+                    return;
+                }
+            };
+
+            // 切断時にConnectionLeftが流れる
+            let _guard = ch.events().connection_guard(
+                ch.id(),
+                connection_id,
+                ConnectionKind::Relay,
+                remote_addr,
+            );
+            let reciever = ch.channel_reciever(connection_id);
+            let throttle = upload_limiter.throttle(&ch.id());
+            let relay = relay_out(
+                &mut tcp_stream,
+                read_buf,
+                remote_addr,
+                session_id,
+                reciever,
+                &mut handle,
+                &throttle,
+            );
+            let (mut shutdown, _notify) = shutdown_set;
+            tokio::select! {
+                r = relay => {
+                    if let Err(e) = r {
+                        debug!("PCP relay {remote_addr} closed: {e}");
+                    }
+                }
+                _ = shutdown.recv() => {}
+            }
+            info!("PCP relay {remote_addr} CID:{} finished", ch.id());
+            drop(permit);
         });
     }
 
-    async fn spawn_http_server(
-        // local_address: Arc<Vec<IpNet>>,
+    // tls_portで待ち受ける場合
+    async fn spawn_https_listener(
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+        http_svc: axum::Router,
+        flood_guard: Arc<FloodGuard>,
+        ban_list: Arc<BanList>,
+        notify_shutdown_tx: broadcast::Sender<()>,
+        shutdown_complete_tx: mpsc::Sender<()>,
+    ) -> Result<(), std::io::Error> {
+        loop {
+            let (tcp_stream, remote_addr) = listener.accept().await?;
+            let permit = match flood_guard.accept(remote_addr.ip()) {
+                Ok(permit) => permit,
+                Err(e) => {
+                    debug!("reject connection from {remote_addr}: {e}");
+                    continue;
+                }
+            };
+            if ban_list.check(remote_addr.ip()) {
+                info!("rejected banned TLS connection from {remote_addr}");
+                continue;
+            }
+            let connection_id = ConnectionId::new();
+            let shutdown_set = (
+                Shutdown::new(notify_shutdown_tx.subscribe()),
+                shutdown_complete_tx.clone(),
+            );
+            let acceptor = acceptor.clone();
+            let flood_guard = Arc::clone(&flood_guard);
+            let make_service = http_svc
+                .clone()
+                .into_make_service_with_connect_info::<MyConnectInfo>();
+            tokio::spawn(async move {
+                Self::serve_https(
+                    acceptor,
+                    flood_guard,
+                    connection_id,
+                    tcp_stream,
+                    remote_addr,
+                    shutdown_set,
+                    make_service,
+                )
+                .await;
+                drop(permit);
+            });
+        }
+    }
+
+    async fn serve_https(
+        acceptor: TlsAcceptor,
+        flood_guard: Arc<FloodGuard>,
         //
         connection_id: ConnectionId,
         tcp_stream: TcpStream,
         remote_addr: SocketAddr,
         shutdown_set: ShutdownAndNotifySet,
-        mut make_service: IntoMakeServiceWithConnectInfo<axum::Router, MyConnectInfo>,
+        make_service: IntoMakeServiceWithConnectInfo<axum::Router, MyConnectInfo>,
     ) {
+        let timeout = flood_guard.handshake_timeout();
+        let tls_stream = match tokio::time::timeout(timeout, acceptor.accept(tcp_stream)).await {
+            Ok(Ok(s)) => s,
+            Ok(Err(e)) => {
+                warn!("TLS handshake failed {remote_addr}: {e}");
+                flood_guard.handshake_failed(remote_addr.ip());
+                return;
+            }
+            Err(_) => {
+                warn!("TLS handshake timeout {remote_addr}");
+                flood_guard.handshake_failed(remote_addr.ip());
+                return;
+            }
+        };
+        Self::spawn_http_server(
+            connection_id,
+            tls_stream,
+            remote_addr,
+            shutdown_set,
+            make_service,
+        )
+        .await;
+    }
+
+    async fn spawn_http_server<S>(
+        // local_address: Arc<Vec<IpNet>>,
+        //
+        connection_id: ConnectionId,
+        tcp_stream: S,
+        remote_addr: SocketAddr,
+        shutdown_set: ShutdownAndNotifySet,
+        mut make_service: IntoMakeServiceWithConnectInfo<axum::Router, MyConnectInfo>,
+    ) where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
         // let tower_service = make_service
         //     .call(NewConInfo {})
         //     .await
@@ -28,6 +28,7 @@ impl CuiDL {
         let task_config = SourceTaskConfig::Relay(RelayTaskConfig {
             addr: connect_addr,
             self_addr: None,
+            token: None,
         });
         let _r = ch.connect(ConnectionId::new(), task_config);
 
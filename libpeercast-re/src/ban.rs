@@ -0,0 +1,175 @@
+//! 接続を拒否するIPの一覧
+//!
+//! HTTP/PCP/RTMPのaccept直後に確認する。設定ファイルに保存し、APIから追加・削除する
+//! 期限切れのエントリーは無いものとして扱い、次に保存する時に消える
+
+use std::{
+    net::{AddrParseError, IpAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+/// "192.168.0.1" のような単一のIPも受け付ける
+pub fn parse_net(s: &str) -> Result<IpNet, AddrParseError> {
+    match s.parse::<IpNet>() {
+        Ok(net) => Ok(net),
+        Err(_) => s.parse::<IpAddr>().map(IpNet::from),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub net: IpNet,
+    /// Noneで無期限
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub comment: String,
+}
+
+impl BanEntry {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BanStatus {
+    #[serde(flatten)]
+    pub entry: BanEntry,
+    /// 起動してから拒否した回数
+    pub hits: u64,
+}
+
+#[derive(Debug)]
+struct Banned {
+    entry: BanEntry,
+    hits: AtomicU64,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// BanList
+//
+#[derive(Debug, Default)]
+pub struct BanList {
+    entries: RwLock<Vec<Banned>>,
+}
+
+impl BanList {
+    pub fn new(entries: Vec<BanEntry>) -> Arc<Self> {
+        let list = Self::default();
+        for entry in entries {
+            list.add(entry);
+        }
+        Arc::new(list)
+    }
+
+    /// BANされていればヒット数を増やしてtrue
+    pub fn check(&self, ip: IpAddr) -> bool {
+        self.check_at(ip, Utc::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: DateTime<Utc>) -> bool {
+        let entries = self.entries.read().unwrap();
+        match entries
+            .iter()
+            .find(|b| b.entry.net.contains(&ip) && !b.entry.is_expired(now))
+        {
+            Some(banned) => {
+                banned.hits.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 同じネットワークがあれば置き換える(ヒット数は引き継ぐ)
+    pub fn add(&self, entry: BanEntry) {
+        let mut entries = self.entries.write().unwrap();
+        match entries.iter_mut().find(|b| b.entry.net == entry.net) {
+            Some(banned) => banned.entry = entry,
+            None => entries.push(Banned {
+                entry,
+                hits: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn remove(&self, net: &IpNet) -> bool {
+        let mut entries = self.entries.write().unwrap();
+        let len = entries.len();
+        entries.retain(|b| &b.entry.net != net);
+        entries.len() != len
+    }
+
+    /// 期限切れを除いた一覧
+    pub fn list(&self) -> Vec<BanStatus> {
+        let now = Utc::now();
+        let entries = self.entries.read().unwrap();
+        entries
+            .iter()
+            .filter(|b| !b.entry.is_expired(now))
+            .map(|b| BanStatus {
+                entry: b.entry.clone(),
+                hits: b.hits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// 設定ファイルに保存する分
+    pub fn entries(&self) -> Vec<BanEntry> {
+        self.list().into_iter().map(|s| s.entry).collect()
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_ban_list() {
+        let now = Utc::now();
+        let list = BanList::new(vec![
+            BanEntry {
+                net: parse_net("192.168.0.0/24").unwrap(),
+                expires_at: None,
+                comment: "".into(),
+            },
+            BanEntry {
+                net: parse_net("10.0.0.1").unwrap(),
+                expires_at: Some(now + Duration::hours(1)),
+                comment: "spam".into(),
+            },
+        ]);
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(list.check_at(ip("192.168.0.10"), now));
+        assert!(list.check_at(ip("192.168.0.20"), now));
+        assert!(!list.check_at(ip("192.168.1.1"), now));
+        assert!(list.check_at(ip("10.0.0.1"), now));
+        assert!(!list.check_at(ip("10.0.0.1"), now + Duration::hours(2)));
+        assert_eq!(list.list()[0].hits, 2);
+
+        // 置き換えてもヒット数はそのまま
+        list.add(BanEntry {
+            net: parse_net("192.168.0.0/24").unwrap(),
+            expires_at: Some(now - Duration::seconds(1)),
+            comment: "".into(),
+        });
+        assert!(!list.check_at(ip("192.168.0.10"), now));
+        assert_eq!(list.entries().len(), 1);
+
+        assert!(list.remove(&parse_net("10.0.0.1/32").unwrap()));
+        assert!(!list.remove(&parse_net("10.0.0.1/32").unwrap()));
+        assert!(list.list().is_empty());
+        assert!(parse_net("example.com").is_err());
+    }
+}
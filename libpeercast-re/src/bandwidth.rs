@@ -0,0 +1,303 @@
+//! 上り帯域の制限
+//!
+//! 全体・チャンネル毎・接続毎のトークンバケットを持ち、送信前に全部から取る
+//! 全体かチャンネルのバケットが空になっている間はリレーを受け付けない(relay full)扱いにする
+//! 制限値は設定の変更で差し替えられ、接続中の送信にもすぐ反映される
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::{config::Config, pcp::GnuId};
+
+/// kbps -> byte/sec
+fn kbps_to_bytes(kbps: u32) -> u64 {
+    kbps as u64 * 1000 / 8
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UploadLimitConfig {
+    /// byte/sec, Noneで無制限
+    pub global: Option<u64>,
+    pub per_channel: Option<u64>,
+    pub per_connection: Option<u64>,
+}
+
+impl UploadLimitConfig {
+    pub fn from_config(config: &Config) -> Self {
+        let rate = |kbps: u32| (kbps > 0).then(|| kbps_to_bytes(kbps));
+        Self {
+            global: rate(config.max_upload_kbps),
+            per_channel: rate(config.max_channel_upload_kbps),
+            per_connection: rate(config.max_connection_upload_kbps),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// TokenBucket
+//
+#[derive(Debug)]
+struct BucketState {
+    /// byte/sec, Noneで無制限。貯められる最大量は1秒分
+    rate: Option<u64>,
+    /// 先に使った分はマイナスになる
+    tokens: f64,
+    /// 最後に補充した時刻
+    last: Instant,
+}
+
+impl BucketState {
+    fn refill(&mut self, now: Instant) {
+        if let Some(rate) = self.rate {
+            let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate as f64).min(rate as f64);
+        }
+        self.last = now;
+    }
+}
+
+#[derive(Debug)]
+pub struct TokenBucket {
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    /// Noneで無制限
+    pub fn new(rate: Option<u64>) -> Self {
+        let rate = rate.map(|r| r.max(1));
+        Self {
+            state: Mutex::new(BucketState {
+                rate,
+                tokens: rate.unwrap_or_default() as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn rate(&self) -> Option<u64> {
+        self.state.lock().unwrap().rate
+    }
+
+    pub fn set_rate(&self, rate: Option<u64>) {
+        self.set_rate_at(rate, Instant::now())
+    }
+
+    fn set_rate_at(&self, rate: Option<u64>, now: Instant) {
+        let rate = rate.map(|r| r.max(1));
+        let mut state = self.state.lock().unwrap();
+        state.refill(now);
+        state.tokens = match (state.rate, rate) {
+            (_, None) => 0.0,
+            // 無制限から制限ありになったら満タンから始める
+            (None, Some(rate)) => rate as f64,
+            (Some(_), Some(rate)) => state.tokens.min(rate as f64),
+        };
+        state.rate = rate;
+    }
+
+    /// nバイト分を先に使い、送信して良くなるまでの待ち時間を返す
+    /// 1回の送信がバケットより大きくても、その分後ろの送信が待たされる
+    fn take_at(&self, n: usize, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let Some(rate) = state.rate else {
+            return Duration::ZERO;
+        };
+        state.refill(now);
+        state.tokens -= n as f64;
+        match state.tokens {
+            tokens if tokens >= 0.0 => Duration::ZERO,
+            tokens => Duration::from_secs_f64(-tokens / rate as f64),
+        }
+    }
+
+    pub fn take(&self, n: usize) -> Duration {
+        self.take_at(n, Instant::now())
+    }
+
+    fn is_exhausted_at(&self, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.rate.is_none() {
+            return false;
+        }
+        state.refill(now);
+        state.tokens <= 0.0
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.is_exhausted_at(Instant::now())
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// UploadLimiter
+//
+#[derive(Debug)]
+pub struct UploadLimiter {
+    // throttleと設定の変更が入れ違わないように、バケットを作る間も持っておく
+    config: Mutex<UploadLimitConfig>,
+    global: Arc<TokenBucket>,
+    // 使っている接続が無くなったら消える
+    channels: Mutex<HashMap<GnuId, Weak<TokenBucket>>>,
+    // 設定の変更を反映するためだけに持つ
+    connections: Mutex<Vec<Weak<TokenBucket>>>,
+}
+
+impl UploadLimiter {
+    pub fn new(config: UploadLimitConfig) -> Arc<Self> {
+        Arc::new(Self {
+            global: Arc::new(TokenBucket::new(config.global)),
+            config: Mutex::new(config),
+            channels: Default::default(),
+            connections: Default::default(),
+        })
+    }
+
+    /// 制限値を変える。接続中のThrottleにもそのまま反映される
+    pub fn reconfigure(&self, config: UploadLimitConfig) {
+        let mut current = self.config.lock().unwrap();
+        self.global.set_rate(config.global);
+
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, bucket| bucket.strong_count() > 0);
+        for bucket in channels.values().filter_map(Weak::upgrade) {
+            bucket.set_rate(config.per_channel);
+        }
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|bucket| bucket.strong_count() > 0);
+        for bucket in connections.iter().filter_map(Weak::upgrade) {
+            bucket.set_rate(config.per_connection);
+        }
+        *current = config;
+    }
+
+    fn channel_bucket(&self, config: &UploadLimitConfig, channel_id: &GnuId) -> Arc<TokenBucket> {
+        let mut channels = self.channels.lock().unwrap();
+        channels.retain(|_, bucket| bucket.strong_count() > 0);
+        if let Some(bucket) = channels.get(channel_id).and_then(Weak::upgrade) {
+            return bucket;
+        }
+        let bucket = Arc::new(TokenBucket::new(config.per_channel));
+        channels.insert(*channel_id, Arc::downgrade(&bucket));
+        bucket
+    }
+
+    /// 1つの送信先(HTTPの視聴者、PCPの下流)用
+    pub fn throttle(&self, channel_id: &GnuId) -> Throttle {
+        let config = self.config.lock().unwrap();
+        let channel = self.channel_bucket(&config, channel_id);
+        let connection = Arc::new(TokenBucket::new(config.per_connection));
+        let mut connections = self.connections.lock().unwrap();
+        connections.retain(|bucket| bucket.strong_count() > 0);
+        connections.push(Arc::downgrade(&connection));
+        Throttle {
+            buckets: vec![self.global.clone(), channel, connection],
+        }
+    }
+
+    /// 帯域を使い切っている間は新しいリレーを受けられない
+    pub fn is_relay_full(&self, channel_id: &GnuId) -> bool {
+        if self.global.is_exhausted() {
+            return true;
+        }
+        let channels = self.channels.lock().unwrap();
+        channels
+            .get(channel_id)
+            .and_then(Weak::upgrade)
+            .is_some_and(|b| b.is_exhausted())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    buckets: Vec<Arc<TokenBucket>>,
+}
+
+impl Throttle {
+    /// nバイト送る前に呼ぶ。一番厳しい制限に合わせて待つ
+    pub async fn acquire(&self, n: usize) {
+        let wait = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.take(n))
+            .max()
+            .unwrap_or_default();
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(Some(1000));
+        let now = Instant::now();
+        assert_eq!(bucket.take_at(600, now), Duration::ZERO);
+        assert!(!bucket.is_exhausted_at(now));
+        // 1000 - 600 - 900 = -500 -> 0.5秒待つ
+        assert_eq!(bucket.take_at(900, now), Duration::from_millis(500));
+        assert!(bucket.is_exhausted_at(now));
+        // 1秒で1000貯まる
+        let later = now + Duration::from_secs(1);
+        assert!(!bucket.is_exhausted_at(later));
+        // 容量以上は貯まらない
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(bucket.take_at(1000, much_later), Duration::ZERO);
+        assert!(bucket.is_exhausted_at(much_later));
+
+        // 制限を緩めても貯まっている分はそのまま、無制限なら待たない
+        bucket.set_rate_at(Some(2000), much_later);
+        assert_eq!(bucket.take_at(1000, much_later), Duration::from_millis(500));
+        bucket.set_rate_at(None, much_later);
+        assert_eq!(bucket.take_at(1_000_000, much_later), Duration::ZERO);
+        assert!(!bucket.is_exhausted_at(much_later));
+    }
+
+    #[crate::test]
+    async fn test_upload_limiter() {
+        let limiter = UploadLimiter::new(UploadLimitConfig {
+            global: None,
+            per_channel: Some(100),
+            per_connection: None,
+        });
+        let channel_id = GnuId::new();
+        let other_id = GnuId::new();
+        assert!(!limiter.is_relay_full(&channel_id));
+
+        // 同じチャンネルの接続はバケットを共有する
+        let t1 = limiter.throttle(&channel_id);
+        let t2 = limiter.throttle(&channel_id);
+        assert!(Arc::ptr_eq(&t1.buckets[1], &t2.buckets[1]));
+        assert!(t2.buckets[1].take(150) > Duration::ZERO);
+        assert!(limiter.is_relay_full(&channel_id));
+        assert!(!limiter.is_relay_full(&other_id));
+
+        drop((t1, t2));
+        assert!(!limiter.is_relay_full(&channel_id));
+        let channels = limiter.channels.lock().unwrap();
+        assert!(channels.values().all(|b| b.strong_count() == 0));
+        drop(channels);
+
+        // 待つのは足りない分だけ
+        let start = tokio::time::Instant::now();
+        limiter.throttle(&other_id).acquire(110).await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // 設定の変更は接続中のThrottleにも反映される
+        let t = limiter.throttle(&channel_id);
+        limiter.reconfigure(UploadLimitConfig {
+            global: Some(1000),
+            per_channel: None,
+            per_connection: Some(500),
+        });
+        let rates = t.buckets.iter().map(|b| b.rate()).collect::<Vec<_>>();
+        assert_eq!(rates, vec![Some(1000), None, Some(500)]);
+        assert_eq!(limiter.throttle(&other_id).buckets[2].rate(), Some(500));
+    }
+}
@@ -0,0 +1,323 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use crate::codec::rtmp::{
+    flv::DataType,
+    tag_header::{AudioTagHeader, VideoTagHeader},
+};
+
+use super::{ContainerPacket, ContainerSplitter, SplitError};
+
+const TAG_HEADER_LENGTH: usize = 11;
+const PREVIOUS_TAG_SIZE_LENGTH: usize = 4;
+
+////////////////////////////////////////////////////////////////////////////////
+// FlvTag
+//
+
+/// PreviousTagSizeまで含んだ1つのFLVタグ
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlvTag {
+    raw: Bytes,
+}
+
+impl FlvTag {
+    /// 先頭からタグを1つ読み込む。データが足りなければNone
+    pub fn read(buf: &mut BytesMut) -> Result<Option<FlvTag>, SplitError> {
+        if buf.len() < TAG_HEADER_LENGTH {
+            return Ok(None);
+        }
+        if buf[0] & 0xC0 != 0 {
+            // 予約bitが立っているのはFLVとして壊れている
+            return Err(SplitError::InvalidData("flv tag reserved bits"));
+        }
+        let data_size = u32::from_be_bytes([0, buf[1], buf[2], buf[3]]) as usize;
+        let tag_length = TAG_HEADER_LENGTH + data_size + PREVIOUS_TAG_SIZE_LENGTH;
+        if buf.len() < tag_length {
+            return Ok(None);
+        }
+
+        Ok(Some(FlvTag {
+            raw: buf.split_to(tag_length).freeze(),
+        }))
+    }
+
+    pub fn data_type(&self) -> DataType {
+        DataType(self.raw[0] & 0x1F)
+    }
+
+    pub fn timestamp(&self) -> u32 {
+        let r = &self.raw;
+        u32::from_be_bytes([r[7], r[4], r[5], r[6]])
+    }
+
+    /// タイムスタンプを書き換えた新しいタグを返す
+    pub fn with_timestamp(&self, timestamp: u32) -> FlvTag {
+        let t = timestamp.to_be_bytes();
+        let mut raw = BytesMut::from(&self.raw[..]);
+        raw[4] = t[1];
+        raw[5] = t[2];
+        raw[6] = t[3];
+        raw[7] = t[0];
+        FlvTag { raw: raw.freeze() }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.raw[TAG_HEADER_LENGTH..(self.raw.len() - PREVIOUS_TAG_SIZE_LENGTH)]
+    }
+
+    pub fn bytes(&self) -> &Bytes {
+        &self.raw
+    }
+
+    /// onMetaDataやAVC/AACのシーケンスヘッダーなど、再生開始時に必要なタグ
+    pub fn is_header(&self) -> bool {
+        let data = self.data();
+        match self.data_type() {
+            DataType::SCRIPT => true,
+            DataType::VIDEO => is_video_sequence_header(data),
+            DataType::AUDIO => is_audio_sequence_header(data),
+            _ => false,
+        }
+    }
+
+    pub fn is_video_keyframe(&self) -> bool {
+        self.data_type() == DataType::VIDEO
+            && VideoTagHeader::read(self.data()).is_some_and(|h| h.is_keyframe())
+    }
+}
+
+fn is_video_sequence_header(data: &[u8]) -> bool {
+    VideoTagHeader::read(data).is_some_and(|h| h.is_sequence_header())
+}
+
+fn is_audio_sequence_header(data: &[u8]) -> bool {
+    AudioTagHeader::read(data).is_some_and(|h| h.is_sequence_header())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FlvSplitter
+//
+
+/// FLVのヘッダーとシーケンスヘッダーをHead、残りのタグをDataとして分割する
+#[derive(Debug)]
+pub struct FlvSplitter {
+    buf: BytesMut,
+    // b"FLV"から最初のPreviousTagSizeまで
+    magic: Option<Bytes>,
+    has_video: bool,
+    metadata: Option<FlvTag>,
+    video_header: Option<FlvTag>,
+    audio_header: Option<FlvTag>,
+    is_head_sent: bool,
+}
+
+impl FlvSplitter {
+    pub fn new() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            magic: None,
+            has_video: false,
+            metadata: None,
+            video_header: None,
+            audio_header: None,
+            is_head_sent: false,
+        }
+    }
+
+    /// FLVファイルのヘッダーを読み込む。データが足りなければNone
+    pub fn read_magic(buf: &mut BytesMut) -> Result<Option<Bytes>, SplitError> {
+        if buf.len() < 9 {
+            return Ok(None);
+        }
+        if &buf[0..3] != b"FLV" {
+            return Err(SplitError::InvalidMagic);
+        }
+        let offset = (&buf[5..9]).get_u32() as usize;
+        if offset < 9 {
+            return Err(SplitError::InvalidData("flv header offset"));
+        }
+        if buf.len() < offset + PREVIOUS_TAG_SIZE_LENGTH {
+            return Ok(None);
+        }
+        Ok(Some(
+            buf.split_to(offset + PREVIOUS_TAG_SIZE_LENGTH).freeze(),
+        ))
+    }
+
+    fn head(&self) -> Bytes {
+        let mut head = BytesMut::new();
+        head.put(&self.magic.as_ref().unwrap()[..]);
+        for tag in [&self.metadata, &self.video_header, &self.audio_header]
+            .into_iter()
+            .flatten()
+        {
+            head.put(&tag.bytes()[..]);
+        }
+        head.freeze()
+    }
+
+    fn handle_tag(&mut self, tag: FlvTag, packets: &mut Vec<ContainerPacket>) {
+        if tag.is_header() {
+            match tag.data_type() {
+                DataType::SCRIPT => self.metadata = Some(tag),
+                DataType::VIDEO => self.video_header = Some(tag),
+                _ => self.audio_header = Some(tag),
+            };
+            // 配信途中でヘッダーが変わったら送り直す
+            if self.is_head_sent {
+                packets.push(ContainerPacket::Head {
+                    payload: self.head(),
+                });
+            }
+            return;
+        }
+
+        if !self.is_head_sent {
+            self.is_head_sent = true;
+            packets.push(ContainerPacket::Head {
+                payload: self.head(),
+            });
+        }
+
+        let keyframe = if self.has_video {
+            tag.is_video_keyframe()
+        } else {
+            true
+        };
+        packets.push(ContainerPacket::Data {
+            payload: tag.raw,
+            keyframe,
+        });
+    }
+}
+
+impl ContainerSplitter for FlvSplitter {
+    fn push(&mut self, buf: &[u8]) -> Result<Vec<ContainerPacket>, SplitError> {
+        self.buf.extend_from_slice(buf);
+        let mut packets = vec![];
+
+        if self.magic.is_none() {
+            match Self::read_magic(&mut self.buf)? {
+                None => return Ok(packets),
+                Some(magic) => {
+                    self.has_video = magic[4] & 0b0000_0001 != 0;
+                    self.magic = Some(magic);
+                }
+            }
+        }
+
+        while let Some(tag) = FlvTag::read(&mut self.buf)? {
+            self.handle_tag(tag, &mut packets);
+        }
+
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn flv_magic(has_audio: bool, has_video: bool) -> Vec<u8> {
+        let mut flags = 0_u8;
+        if has_video {
+            flags |= 0b0000_0001
+        };
+        if has_audio {
+            flags |= 0b0000_0100
+        };
+        vec![b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0]
+    }
+
+    fn flv_tag(data_type: DataType, timestamp: u32, data: &[u8]) -> Vec<u8> {
+        let size = (data.len() as u32).to_be_bytes();
+        let t = timestamp.to_be_bytes();
+        let mut buf = vec![
+            data_type.0,
+            size[1],
+            size[2],
+            size[3],
+            t[1],
+            t[2],
+            t[3],
+            t[0],
+            0,
+            0,
+            0,
+        ];
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(&((data.len() + 11) as u32).to_be_bytes());
+        buf
+    }
+
+    #[test]
+    fn test_flv_tag() {
+        let raw = flv_tag(DataType::VIDEO, 0x01020304, &[0x17, 0x01, 0, 0, 0]);
+        let mut buf = BytesMut::from(&raw[..]);
+        let tag = FlvTag::read(&mut buf).unwrap().unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(tag.data_type(), DataType::VIDEO);
+        assert_eq!(tag.timestamp(), 0x01020304);
+        assert!(tag.is_video_keyframe());
+        assert!(!tag.is_header());
+
+        let tag = tag.with_timestamp(100);
+        assert_eq!(tag.timestamp(), 100);
+        assert_eq!(tag.data(), &[0x17, 0x01, 0, 0, 0]);
+
+        // 足りない場合は待つ
+        let mut buf = BytesMut::from(&raw[..10]);
+        assert_eq!(FlvTag::read(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn test_flv_splitter() {
+        let mut stream = flv_magic(true, true);
+        stream.extend(flv_tag(DataType::SCRIPT, 0, b"meta"));
+        stream.extend(flv_tag(DataType::VIDEO, 0, &[0x17, 0x00, 0, 0, 0]));
+        stream.extend(flv_tag(DataType::AUDIO, 0, &[0xAF, 0x00, 0x12]));
+        let keyframe = flv_tag(DataType::VIDEO, 33, &[0x17, 0x01, 0, 0, 0]);
+        let interframe = flv_tag(DataType::VIDEO, 66, &[0x27, 0x01, 0, 0, 0]);
+        stream.extend(&keyframe);
+        stream.extend(&interframe);
+
+        // 細切れに入力しても結果は変わらない
+        let mut splitter = FlvSplitter::new();
+        let mut packets = vec![];
+        for chunk in stream.chunks(7) {
+            packets.extend(splitter.push(chunk).unwrap());
+        }
+
+        assert_eq!(packets.len(), 3);
+        let head_len = stream.len() - keyframe.len() - interframe.len();
+        assert_eq!(
+            packets[0],
+            ContainerPacket::Head {
+                payload: Bytes::copy_from_slice(&stream[..head_len])
+            }
+        );
+        assert_eq!(
+            packets[1],
+            ContainerPacket::Data {
+                payload: Bytes::from(keyframe),
+                keyframe: true
+            }
+        );
+        assert_eq!(
+            packets[2],
+            ContainerPacket::Data {
+                payload: Bytes::from(interframe),
+                keyframe: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_flv_splitter_invalid() {
+        let mut splitter = FlvSplitter::new();
+        assert_eq!(
+            splitter.push(b"<html></html>"),
+            Err(SplitError::InvalidMagic)
+        );
+    }
+}
@@ -0,0 +1,536 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{ContainerPacket, ContainerSplitter, SplitError};
+
+// Element IDs (マーカービットを含む)
+const ID_EBML: u32 = 0x1A45DFA3;
+const ID_SEGMENT: u32 = 0x18538067;
+const ID_CLUSTER: u32 = 0x1F43B675;
+const ID_DOC_TYPE: u32 = 0x4282;
+const ID_TRACKS: u32 = 0x1654AE6B;
+const ID_TRACK_ENTRY: u32 = 0xAE;
+const ID_TRACK_NUMBER: u32 = 0xD7;
+const ID_TRACK_TYPE: u32 = 0x83;
+const ID_SIMPLE_BLOCK: u32 = 0xA3;
+const ID_BLOCK_GROUP: u32 = 0xA0;
+const ID_BLOCK: u32 = 0xA1;
+const ID_REFERENCE_BLOCK: u32 = 0xFB;
+const TRACK_TYPE_VIDEO: u64 = 1;
+// Segment直下の要素
+const LEVEL1_IDS: [u32; 8] = [
+    0x114D9B74, // SeekHead
+    0x1549A966, // Info
+    ID_TRACKS,  // Tracks
+    0x1C53BB6B, // Cues
+    0x1254C367, // Tags
+    0x1043A770, // Chapters
+    0x1941A469, // Attachments
+    ID_CLUSTER,
+];
+
+/// EBMLの要素ヘッダー
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ElementHeader {
+    id: u32,
+    // Noneはサイズ不明(ライブ配信のSegment/Clusterで使われる)
+    size: Option<u64>,
+    header_length: usize,
+}
+
+impl ElementHeader {
+    fn read(buf: &[u8]) -> Result<Option<Self>, SplitError> {
+        let Some((id, id_length)) = read_vint(buf, 4, true)? else {
+            return Ok(None);
+        };
+        let Some((size, size_length)) = read_vint(&buf[id_length..], 8, false)? else {
+            return Ok(None);
+        };
+        // 全bitが1ならサイズ不明
+        let unknown = size == (1_u64 << (7 * size_length)) - 1;
+
+        Ok(Some(Self {
+            id: id as u32,
+            size: if unknown { None } else { Some(size) },
+            header_length: id_length + size_length,
+        }))
+    }
+
+    fn element_length(&self) -> Option<usize> {
+        self.size.map(|s| self.header_length + s as usize)
+    }
+}
+
+// 可変長整数を読む。(値, 長さ)を返す
+fn read_vint(
+    buf: &[u8],
+    max_length: usize,
+    keep_marker: bool,
+) -> Result<Option<(u64, usize)>, SplitError> {
+    let Some(first) = buf.first() else {
+        return Ok(None);
+    };
+    let length = first.leading_zeros() as usize + 1;
+    if length > max_length {
+        return Err(SplitError::InvalidData("ebml vint length"));
+    }
+    if buf.len() < length {
+        return Ok(None);
+    }
+    let mut value = if keep_marker {
+        *first as u64
+    } else {
+        (*first as u64) & (0xFF_u64 >> length)
+    };
+    for b in &buf[1..length] {
+        value = (value << 8) | (*b as u64);
+    }
+    Ok(Some((value, length)))
+}
+
+// サイズの分かっている子要素を(ID, 中身)で列挙する。壊れていたらそこで止まる
+fn children<'a>(buf: &'a [u8]) -> impl Iterator<Item = (u32, &'a [u8])> + 'a {
+    let mut buf = buf;
+    std::iter::from_fn(move || {
+        let header = ElementHeader::read(buf).ok()??;
+        let length = header.element_length()?;
+        if buf.len() < length {
+            return None;
+        }
+        let (element, rest) = buf.split_at(length);
+        buf = rest;
+        Some((header.id, &element[header.header_length..]))
+    })
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+/// EBMLヘッダーのDocTypeがwebmか
+pub(super) fn is_webm(buf: &[u8]) -> bool {
+    let Ok(Some(header)) = ElementHeader::read(buf) else {
+        return false;
+    };
+    match header.element_length() {
+        Some(length) if header.id == ID_EBML && buf.len() >= length => {
+            children(&buf[header.header_length..length])
+                .any(|(id, data)| id == ID_DOC_TYPE && data.starts_with(b"webm"))
+        }
+        _ => false,
+    }
+}
+
+// Tracksから最初の映像トラックの番号を探す
+fn video_track_number(tracks: &[u8]) -> Option<u64> {
+    children(tracks)
+        .filter(|(id, _)| *id == ID_TRACK_ENTRY)
+        .find_map(|(_, entry)| {
+            let mut number = None;
+            let mut is_video = false;
+            for (id, data) in children(entry) {
+                match id {
+                    ID_TRACK_NUMBER => number = Some(read_uint(data)),
+                    ID_TRACK_TYPE => is_video = read_uint(data) == TRACK_TYPE_VIDEO,
+                    _ => {}
+                }
+            }
+            number.filter(|_| is_video)
+        })
+}
+
+// Clusterの子要素が映像トラックのBlockなら、キーフレームかどうかを返す
+fn block_keyframe(id: u32, data: &[u8], video_track: u64) -> Option<bool> {
+    match id {
+        ID_SIMPLE_BLOCK => {
+            let (track, length) = read_vint(data, 8, false).ok()??;
+            if track != video_track {
+                return None;
+            }
+            // TrackNumberの後ろはTimecode(2byte)とFlags
+            let flags = data.get(length + 2)?;
+            Some(flags & 0x80 != 0)
+        }
+        ID_BLOCK_GROUP => {
+            let mut track = None;
+            let mut has_reference = false;
+            for (id, data) in children(data) {
+                match id {
+                    ID_BLOCK => track = read_vint(data, 8, false).ok().flatten().map(|(t, _)| t),
+                    ID_REFERENCE_BLOCK => has_reference = true,
+                    _ => {}
+                }
+            }
+            (track? == video_track).then_some(!has_reference)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    EbmlHeader,
+    SegmentHeader,
+    Segment,
+    // サイズ不明のClusterの中身を読んでいる
+    UnknownSizeCluster,
+}
+
+/// EBMLヘッダーからTracksまでをHead、ClusterをDataとして分割する
+#[derive(Debug)]
+pub struct MatroskaSplitter {
+    buf: BytesMut,
+    state: State,
+    head: BytesMut,
+    is_head_sent: bool,
+    // Noneなら映像が無いので全てのClusterから再生できる
+    video_track: Option<u64>,
+    //
+    pending: BytesMut,
+    // Noneはまだ映像のBlockが来ていないので判定できない
+    pending_keyframe: Option<bool>,
+}
+
+impl MatroskaSplitter {
+    pub fn new() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            state: State::EbmlHeader,
+            head: BytesMut::new(),
+            is_head_sent: false,
+            video_track: None,
+            pending: BytesMut::new(),
+            pending_keyframe: None,
+        }
+    }
+
+    fn flush(&mut self, packets: &mut Vec<ContainerPacket>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        packets.push(ContainerPacket::Data {
+            payload: self.pending.split().freeze(),
+            keyframe: self.pending_keyframe.unwrap_or(false),
+        });
+        self.pending_keyframe = Some(false);
+    }
+
+    // Clusterの中で最初に来た映像のBlockで判定する
+    fn cluster_keyframe(&self, cluster: &[u8]) -> bool {
+        match self.video_track {
+            None => true,
+            Some(track) => children(cluster)
+                .find_map(|(id, data)| block_keyframe(id, data, track))
+                .unwrap_or(false),
+        }
+    }
+
+    fn send_head_once(&mut self, packets: &mut Vec<ContainerPacket>) {
+        if !self.is_head_sent {
+            self.is_head_sent = true;
+            packets.push(ContainerPacket::Head {
+                payload: Bytes::copy_from_slice(&self.head),
+            });
+        }
+    }
+
+    // 要素全体が揃っていれば取り出す
+    fn take_element(&mut self, header: &ElementHeader) -> Result<Option<Bytes>, SplitError> {
+        let Some(length) = header.element_length() else {
+            return Err(SplitError::InvalidData("ebml unknown size element"));
+        };
+        if self.buf.len() < length {
+            return Ok(None);
+        }
+        Ok(Some(self.buf.split_to(length).freeze()))
+    }
+
+    // 1要素分処理したらtrue、データ不足ならfalse
+    fn step(&mut self, packets: &mut Vec<ContainerPacket>) -> Result<bool, SplitError> {
+        let Some(header) = ElementHeader::read(&self.buf)? else {
+            return Ok(false);
+        };
+
+        match self.state {
+            State::EbmlHeader => {
+                if header.id != ID_EBML {
+                    return Err(SplitError::InvalidMagic);
+                }
+                let Some(element) = self.take_element(&header)? else {
+                    return Ok(false);
+                };
+                self.head.put(&element[..]);
+                self.state = State::SegmentHeader;
+            }
+            State::SegmentHeader => {
+                if header.id != ID_SEGMENT {
+                    return Err(SplitError::InvalidData("ebml segment"));
+                }
+                // Segmentはヘッダーだけ読み、中身は要素ごとに処理する
+                let segment_header = self.buf.split_to(header.header_length);
+                self.head.put(&segment_header[..]);
+                self.state = State::Segment;
+            }
+            State::Segment => {
+                if header.id == ID_CLUSTER {
+                    self.send_head_once(packets);
+                    self.flush(packets);
+                    if header.size.is_none() {
+                        let cluster_header = self.buf.split_to(header.header_length);
+                        self.pending.put(&cluster_header[..]);
+                        self.pending_keyframe = self.video_track.is_none().then_some(true);
+                        self.state = State::UnknownSizeCluster;
+                    } else {
+                        let Some(cluster) = self.take_element(&header)? else {
+                            return Ok(false);
+                        };
+                        let keyframe = self.cluster_keyframe(&cluster[header.header_length..]);
+                        packets.push(ContainerPacket::Data {
+                            payload: cluster,
+                            keyframe,
+                        });
+                    }
+                } else {
+                    let Some(element) = self.take_element(&header)? else {
+                        return Ok(false);
+                    };
+                    if self.is_head_sent {
+                        // Cuesなど、Clusterの後ろに来る要素はそのまま流す
+                        self.flush(packets);
+                        packets.push(ContainerPacket::Data {
+                            payload: element,
+                            keyframe: false,
+                        });
+                    } else {
+                        if header.id == ID_TRACKS {
+                            self.video_track = video_track_number(&element[header.header_length..]);
+                        }
+                        self.head.put(&element[..]);
+                    }
+                }
+            }
+            State::UnknownSizeCluster => {
+                if LEVEL1_IDS.contains(&header.id) {
+                    // Clusterが終わった
+                    self.state = State::Segment;
+                    return Ok(true);
+                }
+                let Some(element) = self.take_element(&header)? else {
+                    return Ok(false);
+                };
+                if let (None, Some(track)) = (self.pending_keyframe, self.video_track) {
+                    self.pending_keyframe =
+                        block_keyframe(header.id, &element[header.header_length..], track);
+                }
+                self.pending.put(&element[..]);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl ContainerSplitter for MatroskaSplitter {
+    fn push(&mut self, buf: &[u8]) -> Result<Vec<ContainerPacket>, SplitError> {
+        self.buf.extend_from_slice(buf);
+        let mut packets = vec![];
+
+        while self.step(&mut packets)? {}
+        // サイズ不明のClusterは読めたところまで送る
+        // ただしキーフレームか判定できるまでは溜めておく
+        if self.state != State::UnknownSizeCluster || self.pending_keyframe.is_some() {
+            self.flush(&mut packets);
+        }
+
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn element(id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buf = id
+            .to_be_bytes()
+            .iter()
+            .copied()
+            .skip_while(|b| *b == 0)
+            .collect::<Vec<_>>();
+        // サイズは8byteで表現する
+        buf.push(0x01);
+        buf.extend_from_slice(&(data.len() as u64).to_be_bytes()[1..]);
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    fn unknown_size(id: u32) -> Vec<u8> {
+        let mut buf = id.to_be_bytes().to_vec();
+        buf.push(0xFF);
+        buf
+    }
+
+    #[test]
+    fn test_element_header() {
+        assert_eq!(
+            ElementHeader::read(&[0x1A, 0x45, 0xDF, 0xA3, 0x84]).unwrap(),
+            Some(ElementHeader {
+                id: ID_EBML,
+                size: Some(4),
+                header_length: 5
+            })
+        );
+        assert_eq!(
+            ElementHeader::read(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]).unwrap(),
+            Some(ElementHeader {
+                id: ID_CLUSTER,
+                size: None,
+                header_length: 5
+            })
+        );
+        assert_eq!(ElementHeader::read(&[0x1A, 0x45]).unwrap(), None);
+        assert!(ElementHeader::read(&[0x00]).is_err());
+    }
+
+    #[test]
+    fn test_matroska_splitter() {
+        let ebml = element(ID_EBML, b"webm");
+        let info = element(0x1549A966, b"info");
+        let tracks = element(0x1654AE6B, b"tracks");
+        let cluster = element(ID_CLUSTER, b"cluster1");
+        let block = element(0xA3, b"block");
+
+        let mut stream = vec![];
+        stream.extend(&ebml);
+        stream.extend(unknown_size(ID_SEGMENT));
+        stream.extend(&info);
+        stream.extend(&tracks);
+        stream.extend(&cluster);
+        stream.extend(unknown_size(ID_CLUSTER));
+        stream.extend(&block);
+        stream.extend(&block);
+        stream.extend(&cluster);
+
+        let mut splitter = MatroskaSplitter::new();
+        let mut packets = vec![];
+        for chunk in stream.chunks(5) {
+            packets.extend(splitter.push(chunk).unwrap());
+        }
+
+        let head_len = ebml.len() + 5 + info.len() + tracks.len();
+        assert_eq!(
+            packets[0],
+            ContainerPacket::Head {
+                payload: Bytes::copy_from_slice(&stream[..head_len])
+            }
+        );
+        assert_eq!(
+            packets[1],
+            ContainerPacket::Data {
+                payload: Bytes::from(cluster.clone()),
+                keyframe: true
+            }
+        );
+        // サイズ不明のClusterは細切れになるが先頭だけkeyframe
+        let data = packets[2..]
+            .iter()
+            .map(|p| match p {
+                ContainerPacket::Data { payload, keyframe } => (payload.clone(), *keyframe),
+                _ => panic!("unexpected head"),
+            })
+            .collect::<Vec<_>>();
+        assert!(data[0].1);
+        assert!(data[1..data.len() - 1].iter().all(|(_, k)| !k));
+        assert_eq!(data.last().unwrap(), &(Bytes::from(cluster), true));
+        let unknown_cluster = data[..data.len() - 1]
+            .iter()
+            .flat_map(|(p, _)| p.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(unknown_cluster.len(), 5 + block.len() * 2);
+    }
+
+    fn simple_block(track: u8, keyframe: bool) -> Vec<u8> {
+        let flags = if keyframe { 0x80 } else { 0x00 };
+        element(ID_SIMPLE_BLOCK, &[0x80 | track, 0, 0, flags, 0xAA])
+    }
+
+    fn tracks() -> Vec<u8> {
+        let audio = [element(ID_TRACK_NUMBER, &[1]), element(ID_TRACK_TYPE, &[2])].concat();
+        let video = [element(ID_TRACK_NUMBER, &[2]), element(ID_TRACK_TYPE, &[1])].concat();
+        element(
+            ID_TRACKS,
+            &[
+                element(ID_TRACK_ENTRY, &audio),
+                element(ID_TRACK_ENTRY, &video),
+            ]
+            .concat(),
+        )
+    }
+
+    #[test]
+    fn test_matroska_keyframe() {
+        let ebml = element(ID_EBML, &element(ID_DOC_TYPE, b"webm"));
+        assert!(is_webm(&ebml));
+        assert!(!is_webm(&element(
+            ID_EBML,
+            &element(ID_DOC_TYPE, b"matroska")
+        )));
+        let tracks = tracks();
+        assert_eq!(video_track_number(&tracks[12..]), Some(2));
+
+        // 音声のBlockが先に来ても映像のBlockで判定する
+        let key_cluster = element(
+            ID_CLUSTER,
+            &[simple_block(1, true), simple_block(2, true)].concat(),
+        );
+        let delta_cluster = element(
+            ID_CLUSTER,
+            &[simple_block(1, true), simple_block(2, false)].concat(),
+        );
+        let group = element(
+            ID_BLOCK_GROUP,
+            &[
+                element(ID_BLOCK, &[0x82, 0, 0, 0, 0xAA]),
+                element(ID_REFERENCE_BLOCK, &[0xFF]),
+            ]
+            .concat(),
+        );
+        let group_cluster = element(ID_CLUSTER, &group);
+
+        let mut stream = vec![];
+        stream.extend(&ebml);
+        stream.extend(unknown_size(ID_SEGMENT));
+        stream.extend(&tracks);
+        stream.extend(&key_cluster);
+        stream.extend(&delta_cluster);
+        stream.extend(&group_cluster);
+        stream.extend(unknown_size(ID_CLUSTER));
+        stream.extend(simple_block(1, true));
+        stream.extend(simple_block(2, true));
+
+        let mut splitter = MatroskaSplitter::new();
+        let mut packets = vec![];
+        for chunk in stream.chunks(3) {
+            packets.extend(splitter.push(chunk).unwrap());
+        }
+        let keyframes = packets[1..]
+            .iter()
+            .map(|p| match p {
+                ContainerPacket::Data { keyframe, .. } => *keyframe,
+                _ => panic!("unexpected head"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keyframes[..3], [true, false, false]);
+        // サイズ不明のClusterは映像のBlockが届くまで送られない
+        assert!(keyframes[3]);
+        assert!(keyframes[4..].iter().all(|k| !k));
+    }
+
+    #[test]
+    fn test_matroska_splitter_invalid() {
+        let mut splitter = MatroskaSplitter::new();
+        assert_eq!(
+            splitter.push(&element(0x1549A966, b"info")),
+            Err(SplitError::InvalidMagic)
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! 外部から受け取ったバイト列をPCPのHead/Dataパケットの単位に分割する
+//!
+//! HTTP Pullなど、コンテナ形式のまま届くストリームを中継するために使う
+
+mod flv;
+mod matroska;
+mod mpegts;
+
+use bytes::Bytes;
+use thiserror::Error;
+
+pub use flv::{FlvSplitter, FlvTag};
+pub use matroska::MatroskaSplitter;
+pub use mpegts::MpegTsSplitter;
+
+#[derive(Debug, Error, PartialEq, Clone)]
+pub enum SplitError {
+    #[error("invalid magic")]
+    InvalidMagic,
+    #[error("invalid data ({0})")]
+    InvalidData(&'static str),
+}
+
+/// 分割されたパケット
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContainerPacket {
+    /// 途中から再生するために必要なヘッダー部分
+    Head { payload: Bytes },
+    /// keyframe: このパケットから再生を開始できる
+    Data { payload: Bytes, keyframe: bool },
+}
+
+pub trait ContainerSplitter: Send + Sync + std::fmt::Debug {
+    /// 受け取ったバイト列を溜め込み、分割できたパケットを返す
+    fn push(&mut self, buf: &[u8]) -> Result<Vec<ContainerPacket>, SplitError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerType {
+    Flv,
+    MpegTs,
+    Matroska,
+    /// DocTypeがwebmのMatroska
+    WebM,
+}
+
+impl ContainerType {
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match mime.as_str() {
+            "video/x-flv" | "video/flv" => Some(Self::Flv),
+            "video/mp2t" | "video/mpeg" => Some(Self::MpegTs),
+            "video/x-matroska" | "audio/x-matroska" => Some(Self::Matroska),
+            "video/webm" | "audio/webm" => Some(Self::WebM),
+            _ => None,
+        }
+    }
+
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let ext = path.rsplit_once('.')?.1.to_ascii_lowercase();
+        match ext.as_str() {
+            "flv" => Some(Self::Flv),
+            "ts" | "m2ts" => Some(Self::MpegTs),
+            "mkv" | "mka" => Some(Self::Matroska),
+            "webm" => Some(Self::WebM),
+            _ => None,
+        }
+    }
+
+    /// 先頭のバイト列から判別する
+    pub fn from_magic(head: &[u8]) -> Option<Self> {
+        match head {
+            [b'F', b'L', b'V', ..] => Some(Self::Flv),
+            // EBMLヘッダーが揃っていなければMatroskaとして扱う
+            [0x1A, 0x45, 0xDF, 0xA3, ..] if matroska::is_webm(head) => Some(Self::WebM),
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(Self::Matroska),
+            [mpegts::SYNC_BYTE, ..] => Some(Self::MpegTs),
+            _ => None,
+        }
+    }
+
+    pub fn splitter(&self) -> Box<dyn ContainerSplitter> {
+        match self {
+            ContainerType::Flv => Box::new(FlvSplitter::new()),
+            ContainerType::MpegTs => Box::new(MpegTsSplitter::new()),
+            ContainerType::Matroska | ContainerType::WebM => Box::new(MatroskaSplitter::new()),
+        }
+    }
+
+    /// ChannelInfo.typ
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ContainerType::Flv => "FLV",
+            ContainerType::MpegTs => "TS",
+            ContainerType::Matroska => "MKV",
+            ContainerType::WebM => "WEBM",
+        }
+    }
+
+    /// ChannelInfo.typから判別する
+    pub fn from_type_name(typ: &str) -> Option<Self> {
+        match typ.to_ascii_uppercase().as_str() {
+            "FLV" => Some(Self::Flv),
+            "TS" => Some(Self::MpegTs),
+            "MKV" => Some(Self::Matroska),
+            "WEBM" => Some(Self::WebM),
+            _ => None,
+        }
+    }
+
+    /// ChannelInfo.stream_ext
+    pub fn stream_ext(&self) -> &'static str {
+        match self {
+            ContainerType::Flv => ".flv",
+            ContainerType::MpegTs => ".ts",
+            ContainerType::Matroska => ".mkv",
+            ContainerType::WebM => ".webm",
+        }
+    }
+
+    /// ChannelInfo.stream_type
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ContainerType::Flv => "video/x-flv",
+            ContainerType::MpegTs => "video/mp2t",
+            ContainerType::Matroska => "video/x-matroska",
+            ContainerType::WebM => "video/webm",
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_container_type() {
+        assert_eq!(
+            ContainerType::from_content_type("video/x-flv"),
+            Some(ContainerType::Flv)
+        );
+        assert_eq!(
+            ContainerType::from_content_type("Video/MP2T; charset=binary"),
+            Some(ContainerType::MpegTs)
+        );
+        assert_eq!(ContainerType::from_content_type("text/html"), None);
+
+        assert_eq!(
+            ContainerType::from_extension("/live/stream.webm"),
+            Some(ContainerType::WebM)
+        );
+        assert_eq!(
+            ContainerType::from_content_type("video/x-matroska"),
+            Some(ContainerType::Matroska)
+        );
+        assert_eq!(
+            ContainerType::from_type_name("webm"),
+            Some(ContainerType::WebM)
+        );
+        assert_eq!(ContainerType::from_extension("/live/stream"), None);
+
+        assert_eq!(
+            ContainerType::from_magic(b"FLV\x01\x05"),
+            Some(ContainerType::Flv)
+        );
+        assert_eq!(
+            ContainerType::from_magic(&[0x1A, 0x45, 0xDF, 0xA3]),
+            Some(ContainerType::Matroska)
+        );
+        assert_eq!(
+            ContainerType::from_magic(&[0x47, 0x40]),
+            Some(ContainerType::MpegTs)
+        );
+        assert_eq!(ContainerType::from_magic(b"<html>"), None);
+    }
+}
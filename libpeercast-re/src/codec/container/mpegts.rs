@@ -0,0 +1,341 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use super::{ContainerPacket, ContainerSplitter, SplitError};
+
+pub(super) const SYNC_BYTE: u8 = 0x47;
+const PACKET_SIZE: usize = 188;
+const PID_PAT: u16 = 0x0000;
+// Dataとして送り出す最大のパケット数
+const MAX_PACKETS_PER_DATA: usize = 64;
+
+/// 188byteのTSパケットの内、分割に必要な部分だけ読んだもの
+#[derive(Debug)]
+struct TsPacket<'a> {
+    pid: u16,
+    payload_unit_start: bool,
+    random_access: bool,
+    payload: &'a [u8],
+}
+
+impl<'a> TsPacket<'a> {
+    fn parse(p: &'a [u8]) -> Result<Self, SplitError> {
+        if p.len() != PACKET_SIZE || p[0] != SYNC_BYTE {
+            return Err(SplitError::InvalidData("ts sync byte"));
+        }
+        let payload_unit_start = p[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([p[1] & 0x1F, p[2]]);
+        let adaptation_field_control = (p[3] & 0x30) >> 4;
+
+        let mut offset = 4;
+        let mut random_access = false;
+        if adaptation_field_control & 0b10 != 0 {
+            let length = p[4] as usize;
+            if length > 0 {
+                random_access = p[5] & 0x40 != 0;
+            }
+            offset += 1 + length;
+        }
+        let payload = if adaptation_field_control & 0b01 != 0 && offset < PACKET_SIZE {
+            &p[offset..]
+        } else {
+            &[]
+        };
+
+        Ok(Self {
+            pid,
+            payload_unit_start,
+            random_access,
+            payload,
+        })
+    }
+
+    /// pointer_fieldを読み飛ばしたPSIのセクション
+    fn section(&self) -> Option<&'a [u8]> {
+        if !self.payload_unit_start {
+            return None;
+        }
+        let pointer = *self.payload.first()? as usize;
+        self.payload.get((1 + pointer)..)
+    }
+}
+
+// PSIのversion_number
+fn section_version(section: &[u8]) -> Option<u8> {
+    section.get(5).map(|b| (b & 0x3E) >> 1)
+}
+
+/// PAT/PMTをHead、それ以外のパケットをまとめてDataとして分割する
+#[derive(Debug)]
+pub struct MpegTsSplitter {
+    buf: BytesMut,
+    pat: Option<(u8, Bytes)>, // (version, packet)
+    pmt_pid: Option<u16>,
+    pmt: Option<(u8, Bytes)>, // (version, packet)
+    video_pid: Option<u16>,
+    //
+    pending: BytesMut,
+    pending_keyframe: bool,
+    pending_count: usize,
+}
+
+impl MpegTsSplitter {
+    pub fn new() -> Self {
+        Self {
+            buf: BytesMut::new(),
+            pat: None,
+            pmt_pid: None,
+            pmt: None,
+            video_pid: None,
+            pending: BytesMut::new(),
+            pending_keyframe: false,
+            pending_count: 0,
+        }
+    }
+
+    fn head(&self) -> Option<Bytes> {
+        let ((_, pat), (_, pmt)) = (self.pat.as_ref()?, self.pmt.as_ref()?);
+        let mut head = BytesMut::with_capacity(PACKET_SIZE * 2);
+        head.put(&pat[..]);
+        head.put(&pmt[..]);
+        Some(head.freeze())
+    }
+
+    // 先頭の同期バイトまで読み飛ばす
+    fn resync(&mut self) {
+        let skip = self.buf[1..]
+            .iter()
+            .position(|b| *b == SYNC_BYTE)
+            .map_or(self.buf.len(), |p| p + 1);
+        let _ = self.buf.split_to(skip);
+    }
+
+    fn flush(&mut self, packets: &mut Vec<ContainerPacket>) {
+        if self.pending.is_empty() {
+            return;
+        }
+        packets.push(ContainerPacket::Data {
+            payload: self.pending.split().freeze(),
+            keyframe: self.pending_keyframe,
+        });
+        self.pending_keyframe = false;
+        self.pending_count = 0;
+    }
+
+    /// PAT/PMTが更新されたらtrue
+    fn handle_psi(&mut self, packet: &TsPacket, raw: &[u8]) -> bool {
+        let Some(section) = packet.section() else {
+            return false;
+        };
+        let Some(version) = section_version(section) else {
+            return false;
+        };
+
+        if packet.pid == PID_PAT {
+            if self.pat.as_ref().map(|(v, _)| *v) == Some(version) {
+                return false;
+            }
+            // 最初のプログラムのPMTを使う
+            // table_id(8) section_length(16) ts_id(16) version(8) section_number(8) last_section_number(8)
+            let program = section.get(8..12);
+            self.pmt_pid = program
+                .filter(|p| u16::from_be_bytes([p[0], p[1]]) != 0)
+                .or_else(|| section.get(12..16))
+                .map(|p| u16::from_be_bytes([p[2] & 0x1F, p[3]]));
+            self.pat = Some((version, Bytes::copy_from_slice(raw)));
+            self.pmt = None;
+            return false;
+        }
+
+        if Some(packet.pid) == self.pmt_pid {
+            if self.pmt.as_ref().map(|(v, _)| *v) == Some(version) {
+                return false;
+            }
+            self.video_pid = find_video_pid(section);
+            self.pmt = Some((version, Bytes::copy_from_slice(raw)));
+            return true;
+        }
+
+        false
+    }
+}
+
+// PMTから映像のPIDを探す
+fn find_video_pid(section: &[u8]) -> Option<u16> {
+    let section_length = u16::from_be_bytes([*section.get(1)? & 0x0F, *section.get(2)?]) as usize;
+    let program_info_length =
+        u16::from_be_bytes([*section.get(10)? & 0x0F, *section.get(11)?]) as usize;
+    // CRC32の手前まで
+    let end = (3 + section_length).saturating_sub(4).min(section.len());
+    let mut i = 12 + program_info_length;
+    while i + 5 <= end {
+        let stream_type = section[i];
+        let pid = u16::from_be_bytes([section[i + 1] & 0x1F, section[i + 2]]);
+        let es_info_length = u16::from_be_bytes([section[i + 3] & 0x0F, section[i + 4]]) as usize;
+        // MPEG1/2 Video, MPEG4 Visual, H.264, H.265
+        if matches!(stream_type, 0x01 | 0x02 | 0x10 | 0x1B | 0x24) {
+            return Some(pid);
+        }
+        i += 5 + es_info_length;
+    }
+    None
+}
+
+impl ContainerSplitter for MpegTsSplitter {
+    fn push(&mut self, buf: &[u8]) -> Result<Vec<ContainerPacket>, SplitError> {
+        self.buf.extend_from_slice(buf);
+        let mut packets = vec![];
+
+        while self.buf.len() >= PACKET_SIZE {
+            if self.buf[0] != SYNC_BYTE {
+                self.resync();
+                continue;
+            }
+            let raw = self.buf.split_to(PACKET_SIZE).freeze();
+            let packet = TsPacket::parse(&raw)?;
+
+            if self.handle_psi(&packet, &raw) {
+                self.flush(&mut packets);
+                packets.push(ContainerPacket::Head {
+                    payload: self.head().unwrap(),
+                });
+            }
+            // Headを送るまではDataも送れない
+            if self.pmt.is_none() {
+                continue;
+            }
+
+            let keyframe = match self.video_pid {
+                Some(pid) => pid == packet.pid && packet.payload_unit_start && packet.random_access,
+                // 音声のみ
+                None => packet.payload_unit_start && packet.pid != PID_PAT,
+            };
+            if keyframe || self.pending_count >= MAX_PACKETS_PER_DATA {
+                self.flush(&mut packets);
+            }
+            if self.pending.is_empty() {
+                self.pending_keyframe = keyframe;
+            }
+            self.pending.put(&raw[..]);
+            self.pending_count += 1;
+        }
+        self.flush(&mut packets);
+
+        Ok(packets)
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn ts_packet(pid: u16, pusi: bool, random_access: bool, payload: &[u8]) -> Vec<u8> {
+        let pid = pid.to_be_bytes();
+        let mut p = vec![SYNC_BYTE, pid[0] & 0x1F, pid[1], 0x30];
+        if pusi {
+            p[1] |= 0x40;
+        }
+        // adaptation fieldでpayloadの長さを合わせる
+        let stuffing = PACKET_SIZE - 4 - 2 - payload.len();
+        p.push((1 + stuffing) as u8);
+        p.push(if random_access { 0x40 } else { 0x00 });
+        p.extend(std::iter::repeat(0xFF).take(stuffing));
+        p.extend_from_slice(payload);
+        assert_eq!(p.len(), PACKET_SIZE);
+        p
+    }
+
+    fn pat(pmt_pid: u16) -> Vec<u8> {
+        let pid = pmt_pid.to_be_bytes();
+        #[rustfmt::skip]
+        let section = [
+            0x00, // pointer_field
+            0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0x00, 0x01, 0xE0 | pid[0], pid[1],
+            0x00, 0x00, 0x00, 0x00, // CRC
+        ];
+        ts_packet(PID_PAT, true, false, &section)
+    }
+
+    fn pmt(pmt_pid: u16, video_pid: u16, audio_pid: u16) -> Vec<u8> {
+        let v = video_pid.to_be_bytes();
+        let a = audio_pid.to_be_bytes();
+        #[rustfmt::skip]
+        let section = [
+            0x00, // pointer_field
+            0x02, 0xB0, 0x17, 0x00, 0x01, 0xC1, 0x00, 0x00,
+            0xE0 | v[0], v[1], 0xF0, 0x00,
+            0x1B, 0xE0 | v[0], v[1], 0xF0, 0x00,
+            0x0F, 0xE0 | a[0], a[1], 0xF0, 0x00,
+            0x00, 0x00, 0x00, 0x00, // CRC
+        ];
+        ts_packet(pmt_pid, true, false, &section)
+    }
+
+    #[test]
+    fn test_mpegts_splitter() {
+        let pat = pat(0x1000);
+        let pmt = pmt(0x1000, 0x100, 0x101);
+        let key = ts_packet(0x100, true, true, &[0; 10]);
+        let video = ts_packet(0x100, false, false, &[0; 10]);
+        let audio = ts_packet(0x101, true, false, &[0; 10]);
+
+        let mut stream = vec![];
+        // PAT/PMTより前のデータは捨てられる
+        stream.extend(&audio);
+        stream.extend(&pat);
+        stream.extend(&pmt);
+        stream.extend(&key);
+        stream.extend(&video);
+        stream.extend(&audio);
+        stream.extend(&key);
+
+        let mut splitter = MpegTsSplitter::new();
+        let packets = splitter.push(&stream).unwrap();
+
+        let mut head = pat.clone();
+        head.extend(&pmt);
+        assert_eq!(
+            packets[0],
+            ContainerPacket::Head {
+                payload: Bytes::from(head)
+            }
+        );
+        // PMTはkeyframeではない
+        assert_eq!(
+            packets[1],
+            ContainerPacket::Data {
+                payload: Bytes::from(pmt.clone()),
+                keyframe: false
+            }
+        );
+        let mut gop = key.clone();
+        gop.extend(&video);
+        gop.extend(&audio);
+        assert_eq!(
+            packets[2],
+            ContainerPacket::Data {
+                payload: Bytes::from(gop),
+                keyframe: true
+            }
+        );
+        assert_eq!(
+            packets[3],
+            ContainerPacket::Data {
+                payload: Bytes::from(key),
+                keyframe: true
+            }
+        );
+        assert_eq!(packets.len(), 4);
+    }
+
+    #[test]
+    fn test_mpegts_resync() {
+        let mut stream = vec![0x00, 0x01, 0x02];
+        stream.extend(pat(0x1000));
+        stream.extend(pmt(0x1000, 0x100, 0x101));
+
+        let mut splitter = MpegTsSplitter::new();
+        let packets = splitter.push(&stream).unwrap();
+        assert!(matches!(packets[0], ContainerPacket::Head { .. }));
+    }
+}
@@ -1,5 +1,7 @@
+pub mod container;
 pub mod rtmp {
     pub mod flv;
+    pub mod tag_header;
 }
 
 pub use rtmp::flv::FlvWriter;
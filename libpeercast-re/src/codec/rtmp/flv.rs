@@ -103,6 +103,7 @@ where
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct DataType(pub u8);
 impl DataType {
     pub const AUDIO: Self = Self(0x08);
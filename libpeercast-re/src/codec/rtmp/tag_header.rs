@@ -0,0 +1,225 @@
+//! FLVのVideo/Audioタグの先頭を読む
+//!
+//! 従来のCodecIDによる形式と、Enhanced RTMPのFourCCによる形式(ExHeader)の両方に対応する
+//! See: https://github.com/veovera/enhanced-rtmp
+
+/// FourCC
+pub type FourCc = [u8; 4];
+
+pub const FOURCC_AVC: FourCc = *b"avc1";
+pub const FOURCC_HEVC: FourCc = *b"hvc1";
+pub const FOURCC_AV1: FourCc = *b"av01";
+pub const FOURCC_VP9: FourCc = *b"vp09";
+pub const FOURCC_OPUS: FourCc = *b"Opus";
+pub const FOURCC_FLAC: FourCc = *b"fLaC";
+pub const FOURCC_AAC: FourCc = *b"mp4a";
+
+// 従来形式のCodecID
+const CODEC_ID_AVC: u8 = 7;
+// 規格外だが中国系のサービスで使われていたHEVC
+const CODEC_ID_HEVC: u8 = 12;
+const SOUND_FORMAT_AAC: u8 = 10;
+const SOUND_FORMAT_EX_HEADER: u8 = 9;
+
+const FRAME_TYPE_KEY: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    /// 従来形式のCodecID
+    Legacy(u8),
+    FourCc(FourCc),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    /// 従来形式のSoundFormat
+    Legacy(u8),
+    FourCc(FourCc),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketKind {
+    /// AVCDecoderConfigurationRecordなど、デコーダーの初期化に必要なデータ
+    SequenceHeader,
+    CodedFrame,
+    SequenceEnd,
+    /// Metadata, Multitrackなど、ここでは扱わないもの
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoTagHeader {
+    pub codec: VideoCodec,
+    pub frame_type: u8,
+    pub packet_kind: PacketKind,
+}
+
+impl VideoTagHeader {
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let first = *data.first()?;
+        let is_ex_header = first & 0x80 != 0;
+
+        if is_ex_header {
+            // IsExHeader UB[1], FrameType UB[3], PacketType UB[4], FourCC UI32
+            let fourcc: FourCc = data.get(1..5)?.try_into().ok()?;
+            let packet_kind = match first & 0x0F {
+                0 => PacketKind::SequenceHeader,
+                // CodedFrames, CodedFramesX(CompositionTime無し)
+                1 | 3 => PacketKind::CodedFrame,
+                2 => PacketKind::SequenceEnd,
+                _ => PacketKind::Other,
+            };
+            Some(Self {
+                codec: VideoCodec::FourCc(fourcc),
+                frame_type: (first & 0x70) >> 4,
+                packet_kind,
+            })
+        } else {
+            // FrameType UB[4], CodecID UB[4], AVCPacketType UI8 (AVC/HEVCのみ)
+            let codec_id = first & 0x0F;
+            let packet_kind = match codec_id {
+                CODEC_ID_AVC | CODEC_ID_HEVC => match *data.get(1)? {
+                    0 => PacketKind::SequenceHeader,
+                    1 => PacketKind::CodedFrame,
+                    2 => PacketKind::SequenceEnd,
+                    _ => PacketKind::Other,
+                },
+                _ => PacketKind::CodedFrame,
+            };
+            Some(Self {
+                codec: VideoCodec::Legacy(codec_id),
+                frame_type: (first & 0xF0) >> 4,
+                packet_kind,
+            })
+        }
+    }
+
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_kind == PacketKind::SequenceHeader
+    }
+
+    pub fn is_keyframe(&self) -> bool {
+        self.frame_type == FRAME_TYPE_KEY && self.packet_kind == PacketKind::CodedFrame
+    }
+
+    /// HEVC, AV1, VP9など従来のFLVでは表現できない形式か
+    pub fn is_enhanced(&self) -> bool {
+        matches!(self.codec, VideoCodec::FourCc(_))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioTagHeader {
+    pub codec: AudioCodec,
+    pub packet_kind: PacketKind,
+}
+
+impl AudioTagHeader {
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let first = *data.first()?;
+        let sound_format = (first & 0xF0) >> 4;
+
+        match sound_format {
+            SOUND_FORMAT_EX_HEADER => {
+                // SoundFormat UB[4], AudioPacketType UB[4], FourCC UI32
+                let fourcc: FourCc = data.get(1..5)?.try_into().ok()?;
+                let packet_kind = match first & 0x0F {
+                    0 => PacketKind::SequenceHeader,
+                    1 => PacketKind::CodedFrame,
+                    2 => PacketKind::SequenceEnd,
+                    _ => PacketKind::Other,
+                };
+                Some(Self {
+                    codec: AudioCodec::FourCc(fourcc),
+                    packet_kind,
+                })
+            }
+            SOUND_FORMAT_AAC => {
+                let packet_kind = match *data.get(1)? {
+                    0 => PacketKind::SequenceHeader,
+                    _ => PacketKind::CodedFrame,
+                };
+                Some(Self {
+                    codec: AudioCodec::Legacy(sound_format),
+                    packet_kind,
+                })
+            }
+            _ => Some(Self {
+                codec: AudioCodec::Legacy(sound_format),
+                packet_kind: PacketKind::CodedFrame,
+            }),
+        }
+    }
+
+    pub fn is_sequence_header(&self) -> bool {
+        self.packet_kind == PacketKind::SequenceHeader
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_legacy_video() {
+        // AVC sequence header
+        let h = VideoTagHeader::read(&[0x17, 0x00, 0, 0, 0]).unwrap();
+        assert_eq!(h.codec, VideoCodec::Legacy(CODEC_ID_AVC));
+        assert!(h.is_sequence_header());
+        assert!(!h.is_keyframe());
+        // AVC keyframe / interframe
+        assert!(VideoTagHeader::read(&[0x17, 0x01, 0, 0, 0])
+            .unwrap()
+            .is_keyframe());
+        assert!(!VideoTagHeader::read(&[0x27, 0x01, 0, 0, 0])
+            .unwrap()
+            .is_keyframe());
+        // Sorenson H.263
+        assert!(VideoTagHeader::read(&[0x12]).unwrap().is_keyframe());
+        assert_eq!(VideoTagHeader::read(&[0x17]), None);
+    }
+
+    #[test]
+    fn test_enhanced_video() {
+        // HEVC SequenceStart
+        let h = VideoTagHeader::read(&[0x90, b'h', b'v', b'c', b'1']).unwrap();
+        assert_eq!(h.codec, VideoCodec::FourCc(FOURCC_HEVC));
+        assert!(h.is_enhanced());
+        assert!(h.is_sequence_header());
+
+        // AV1 CodedFrames keyframe / interframe
+        let h = VideoTagHeader::read(&[0x91, b'a', b'v', b'0', b'1', 0xAA]).unwrap();
+        assert_eq!(h.codec, VideoCodec::FourCc(FOURCC_AV1));
+        assert!(h.is_keyframe());
+        assert!(!VideoTagHeader::read(&[0xA1, b'a', b'v', b'0', b'1'])
+            .unwrap()
+            .is_keyframe());
+        // HEVC CodedFramesX keyframe
+        assert!(VideoTagHeader::read(&[0x93, b'h', b'v', b'c', b'1'])
+            .unwrap()
+            .is_keyframe());
+        // Metadata
+        let h = VideoTagHeader::read(&[0x94, b'h', b'v', b'c', b'1']).unwrap();
+        assert_eq!(h.packet_kind, PacketKind::Other);
+
+        assert_eq!(VideoTagHeader::read(&[0x90, b'h', b'v']), None);
+    }
+
+    #[test]
+    fn test_audio() {
+        assert!(AudioTagHeader::read(&[0xAF, 0x00])
+            .unwrap()
+            .is_sequence_header());
+        assert!(!AudioTagHeader::read(&[0xAF, 0x01])
+            .unwrap()
+            .is_sequence_header());
+        // MP3
+        assert!(!AudioTagHeader::read(&[0x2F]).unwrap().is_sequence_header());
+
+        let h = AudioTagHeader::read(&[0x90, b'O', b'p', b'u', b's']).unwrap();
+        assert_eq!(h.codec, AudioCodec::FourCc(FOURCC_OPUS));
+        assert!(h.is_sequence_header());
+        let h = AudioTagHeader::read(&[0x91, b'O', b'p', b'u', b's', 0xFC]).unwrap();
+        assert_eq!(h.packet_kind, PacketKind::CodedFrame);
+    }
+}
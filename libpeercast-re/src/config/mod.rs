@@ -15,8 +15,10 @@ use pbkdf2::{
 use rand_core::OsRng;
 use tracing::{debug, info, warn};
 use tracing_subscriber::field::debug;
+use url::Url;
 
 use crate::{
+    ban::BanEntry,
     error::{AuthError, ConfigError, ParseVariableError},
     pcp::GnuId,
 };
@@ -27,6 +29,11 @@ pub use loader::*;
 const SECTION_SERVER: &str = "Server";
 const SECTION_ROOT: &str = "Root";
 const SECTION_PRIVACY: &str = "Privacy";
+const SECTION_RECORD: &str = "Record";
+const SECTION_LIMIT: &str = "Limit";
+const SECTION_YP: &str = "Yp";
+const SECTION_BAN: &str = "Ban";
+const SECTION_TLS: &str = "Tls";
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -36,12 +43,70 @@ pub struct Config {
     pub server_port: u16,
     pub rtmp_port: u16,
     pub local_address: Vec<IpNet>,
+    /// このネットワークから来た接続はX-Forwarded-For/Forwardedを信じる(リバースプロキシ)
+    pub trusted_proxies: Vec<IpNet>,
+    /// 配信者ID(BCID)。配信チャンネルのIDはこれと名前・ジャンルから作る
+    pub broadcast_id: Option<GnuId>,
     pub root_mode: bool,
     pub root_session_id: Option<GnuId>,
 
     // Privacy
     pub username: Option<String>,
     pub password: Option<ConfigPassword>,
+    /// 限定公開チャンネルの視聴トークンの署名鍵, 最初にトークンを発行した時に作る
+    pub token_secret: Option<String>,
+
+    // Record
+    pub record_directory: PathBuf,
+    /// {name}, {id}, {time} を置き換える(拡張子は自動で付く)
+    pub record_filename: String,
+    /// byte, 0で無効
+    pub record_rotate_size: u64,
+    /// sec, 0で無効
+    pub record_rotate_duration: u64,
+
+    // Limit
+    /// チャンネル毎の下流PCP接続数, 0で無制限
+    pub max_relays: u32,
+    /// チャンネル毎のHTTP視聴者数, 0で無制限
+    pub max_listeners: u32,
+    /// 全チャンネル合計, 0で無制限
+    pub max_total_relays: u32,
+    pub max_total_listeners: u32,
+    /// 1つのIPからの接続数(local_addressは除く), 0で無制限
+    pub max_connections_per_ip: u32,
+    /// 上り帯域(kbps)の全体・チャンネル毎・接続毎の上限, 0で無制限
+    pub max_upload_kbps: u32,
+    pub max_channel_upload_kbps: u32,
+    pub max_connection_upload_kbps: u32,
+    /// 同時接続数, 0で無制限
+    pub max_connections: u32,
+    /// 1つのIPから1分間に受け付ける接続数, 0で無制限
+    pub accepts_per_minute: u32,
+    /// sec, プロトコル判別・ハンドシェイクのタイムアウト
+    pub handshake_timeout: u32,
+    /// 1分間にこの回数ハンドシェイクに失敗したIPをban_duration(sec)の間BANする, 0で無効
+    pub ban_threshold: u32,
+    pub ban_duration: u32,
+
+    // Yp
+    /// チャンネル一覧を取得するindex.txtのURL
+    pub yp_urls: Vec<Url>,
+    /// sec
+    pub yp_update_interval: u64,
+
+    // Ban
+    /// 接続を拒否するIP・ネットワーク
+    pub ban_list: Vec<BanEntry>,
+
+    // Tls
+    /// HTTPS(UI/API)を受け付ける
+    pub tls_enabled: bool,
+    /// 0ならserver_portでTLSのClientHelloを判別して受け付ける
+    pub tls_port: u16,
+    /// 両方とも未設定なら設定ファイルの隣に自己署名証明書を作って使う
+    pub tls_cert_file: Option<PathBuf>,
+    pub tls_key_file: Option<PathBuf>,
 }
 
 impl Config {
@@ -55,46 +120,109 @@ impl Config {
             server_port,
             rtmp_port,
             local_address,
+            trusted_proxies,
+            broadcast_id,
             // Privacy
             username,
             password,
+            token_secret,
             // Root
             root_mode,
             root_session_id,
+            // Record
+            record_directory,
+            record_filename,
+            record_rotate_size,
+            record_rotate_duration,
+            // Limit
+            max_relays,
+            max_listeners,
+            max_total_relays,
+            max_total_listeners,
+            max_connections_per_ip,
+            max_upload_kbps,
+            max_channel_upload_kbps,
+            max_connection_upload_kbps,
+            max_connections,
+            accepts_per_minute,
+            handshake_timeout,
+            ban_threshold,
+            ban_duration,
+            // Yp
+            yp_urls,
+            yp_update_interval,
+            // Ban
+            ban_list,
+            // Tls
+            tls_enabled,
+            tls_port,
+            tls_cert_file,
+            tls_key_file,
         } = Config::default();
 
-        let (server_address, server_port, rtmp_port, local_address) = match conf
-            .section(Some(SECTION_SERVER))
-        {
-            None => (server_address, server_port, rtmp_port, local_address),
-            Some(sec) => {
-                let server_address = match sec.get("server_address") {
-                    None | Some("") => server_address,
-                    Some(s) => {
-                        let ip = s
-                            .parse::<IpAddr>()
-                            .map_err(|e| ParseVariableError::from(e))?;
-                        ConfigAddress::Config(ip)
-                    }
-                };
-                let server_port = match sec.get("server_port") {
-                    None | Some("") => server_port,
-                    Some(s) => s.parse::<u16>().map_err(|e| ParseVariableError::from(e))?,
-                };
-                let rtmp_port = match sec.get("rtmp_port") {
-                    None | Some("") => rtmp_port,
-                    Some(s) => s.parse::<u16>().map_err(|e| ParseVariableError::from(e))?,
-                };
-                let local_address = match sec.get("local_address") {
-                    None | Some("") => local_address,
-                    Some(s) => serde_json::from_str(s).map_err(|e| ParseVariableError::from(e))?,
-                };
-                (server_address, server_port, rtmp_port, local_address)
-            }
-        };
+        let (server_address, server_port, rtmp_port, local_address, trusted_proxies, broadcast_id) =
+            match conf.section(Some(SECTION_SERVER)) {
+                None => (
+                    server_address,
+                    server_port,
+                    rtmp_port,
+                    local_address,
+                    trusted_proxies,
+                    broadcast_id,
+                ),
+                Some(sec) => {
+                    let server_address = match sec.get("server_address") {
+                        None | Some("") => server_address,
+                        Some(s) => {
+                            let ip = s
+                                .parse::<IpAddr>()
+                                .map_err(|e| ParseVariableError::from(e))?;
+                            ConfigAddress::Config(ip)
+                        }
+                    };
+                    let server_port = match sec.get("server_port") {
+                        None | Some("") => server_port,
+                        Some(s) => s.parse::<u16>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    let rtmp_port = match sec.get("rtmp_port") {
+                        None | Some("") => rtmp_port,
+                        Some(s) => s.parse::<u16>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    // 古い設定ファイルはpermit_addressで保存していた
+                    let local_address = match sec
+                        .get("local_address")
+                        .or_else(|| sec.get("permit_address"))
+                    {
+                        None | Some("") => local_address,
+                        Some(s) => {
+                            serde_json::from_str(s).map_err(|e| ParseVariableError::from(e))?
+                        }
+                    };
+                    let trusted_proxies = match sec.get("trusted_proxies") {
+                        None | Some("") => trusted_proxies,
+                        Some(s) => {
+                            serde_json::from_str(s).map_err(|e| ParseVariableError::from(e))?
+                        }
+                    };
+                    let broadcast_id = match sec.get("broadcast_id") {
+                        None | Some("") => broadcast_id,
+                        Some(s) => {
+                            Some(GnuId::from_str(s).map_err(|e| ParseVariableError::from(e))?)
+                        }
+                    };
+                    (
+                        server_address,
+                        server_port,
+                        rtmp_port,
+                        local_address,
+                        trusted_proxies,
+                        broadcast_id,
+                    )
+                }
+            };
 
-        let (username, password) = match conf.section(Some(SECTION_PRIVACY)) {
-            None => (username, password),
+        let (username, password, token_secret) = match conf.section(Some(SECTION_PRIVACY)) {
+            None => (username, password, token_secret),
             Some(sec) => {
                 let username = match sec.get("username") {
                     None | Some("") => username,
@@ -117,7 +245,11 @@ impl Config {
                         }
                     }
                 };
-                (username, password)
+                let token_secret = match sec.get("token_secret") {
+                    None | Some("") => token_secret,
+                    Some(s) => Some(s.to_string()),
+                };
+                (username, password, token_secret)
             }
         };
 
@@ -138,21 +270,206 @@ impl Config {
             }
         };
 
+        let (record_directory, record_filename, record_rotate_size, record_rotate_duration) =
+            match conf.section(Some(SECTION_RECORD)) {
+                None => (
+                    record_directory,
+                    record_filename,
+                    record_rotate_size,
+                    record_rotate_duration,
+                ),
+                Some(sec) => {
+                    let record_directory = match sec.get("record_directory") {
+                        None | Some("") => record_directory,
+                        Some(s) => PathBuf::from(s),
+                    };
+                    let record_filename = match sec.get("record_filename") {
+                        None | Some("") => record_filename,
+                        Some(s) => s.to_string(),
+                    };
+                    let record_rotate_size = match sec.get("record_rotate_size") {
+                        None | Some("") => record_rotate_size,
+                        Some(s) => s.parse::<u64>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    let record_rotate_duration = match sec.get("record_rotate_duration") {
+                        None | Some("") => record_rotate_duration,
+                        Some(s) => s.parse::<u64>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    (
+                        record_directory,
+                        record_filename,
+                        record_rotate_size,
+                        record_rotate_duration,
+                    )
+                }
+            };
+
+        let (
+            max_relays,
+            max_listeners,
+            max_total_relays,
+            max_total_listeners,
+            max_connections_per_ip,
+            max_upload_kbps,
+            max_channel_upload_kbps,
+            max_connection_upload_kbps,
+            max_connections,
+            accepts_per_minute,
+            handshake_timeout,
+            ban_threshold,
+            ban_duration,
+        ) = match conf.section(Some(SECTION_LIMIT)) {
+            None => (
+                max_relays,
+                max_listeners,
+                max_total_relays,
+                max_total_listeners,
+                max_connections_per_ip,
+                max_upload_kbps,
+                max_channel_upload_kbps,
+                max_connection_upload_kbps,
+                max_connections,
+                accepts_per_minute,
+                handshake_timeout,
+                ban_threshold,
+                ban_duration,
+            ),
+            Some(sec) => {
+                // 全部u32で、空なら既定値
+                let get = |key: &str, default: u32| -> Result<u32, ConfigError> {
+                    match sec.get(key) {
+                        None | Some("") => Ok(default),
+                        Some(s) => Ok(s.parse::<u32>().map_err(|e| ParseVariableError::from(e))?),
+                    }
+                };
+                (
+                    get("max_relays", max_relays)?,
+                    get("max_listeners", max_listeners)?,
+                    get("max_total_relays", max_total_relays)?,
+                    get("max_total_listeners", max_total_listeners)?,
+                    get("max_connections_per_ip", max_connections_per_ip)?,
+                    get("max_upload_kbps", max_upload_kbps)?,
+                    get("max_channel_upload_kbps", max_channel_upload_kbps)?,
+                    get("max_connection_upload_kbps", max_connection_upload_kbps)?,
+                    get("max_connections", max_connections)?,
+                    get("accepts_per_minute", accepts_per_minute)?,
+                    get("handshake_timeout", handshake_timeout)?,
+                    get("ban_threshold", ban_threshold)?,
+                    get("ban_duration", ban_duration)?,
+                )
+            }
+        };
+
+        let (yp_urls, yp_update_interval) = match conf.section(Some(SECTION_YP)) {
+            None => (yp_urls, yp_update_interval),
+            Some(sec) => {
+                let yp_urls = match sec.get("yp_urls") {
+                    None | Some("") => yp_urls,
+                    Some(s) => serde_json::from_str(s).map_err(|e| ParseVariableError::from(e))?,
+                };
+                let yp_update_interval = match sec.get("yp_update_interval") {
+                    None | Some("") => yp_update_interval,
+                    Some(s) => s.parse::<u64>().map_err(|e| ParseVariableError::from(e))?,
+                };
+                (yp_urls, yp_update_interval)
+            }
+        };
+
+        let ban_list = match conf.section(Some(SECTION_BAN)) {
+            None => ban_list,
+            Some(sec) => match sec.get("ban_list") {
+                None | Some("") => ban_list,
+                Some(s) => serde_json::from_str(s).map_err(|e| ParseVariableError::from(e))?,
+            },
+        };
+
+        let (tls_enabled, tls_port, tls_cert_file, tls_key_file) =
+            match conf.section(Some(SECTION_TLS)) {
+                None => (tls_enabled, tls_port, tls_cert_file, tls_key_file),
+                Some(sec) => {
+                    let tls_enabled = match sec.get("tls_enabled") {
+                        None | Some("") => tls_enabled,
+                        Some(s) => s.parse::<bool>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    let tls_port = match sec.get("tls_port") {
+                        None | Some("") => tls_port,
+                        Some(s) => s.parse::<u16>().map_err(|e| ParseVariableError::from(e))?,
+                    };
+                    let tls_cert_file = match sec.get("tls_cert_file") {
+                        None | Some("") => tls_cert_file,
+                        Some(s) => Some(PathBuf::from(s)),
+                    };
+                    let tls_key_file = match sec.get("tls_key_file") {
+                        None | Some("") => tls_key_file,
+                        Some(s) => Some(PathBuf::from(s)),
+                    };
+                    (tls_enabled, tls_port, tls_cert_file, tls_key_file)
+                }
+            };
+
         Ok(Config {
             config_file_path,
             server_address,
             server_port,
             rtmp_port,
             local_address,
+            trusted_proxies,
+            broadcast_id,
             // Privacy
             username,
             password,
+            token_secret,
             // Root
             root_mode,
             root_session_id,
+            // Record
+            record_directory,
+            record_filename,
+            record_rotate_size,
+            record_rotate_duration,
+            // Limit
+            max_relays,
+            max_listeners,
+            max_total_relays,
+            max_total_listeners,
+            max_connections_per_ip,
+            max_upload_kbps,
+            max_channel_upload_kbps,
+            max_connection_upload_kbps,
+            max_connections,
+            accepts_per_minute,
+            handshake_timeout,
+            ban_threshold,
+            ban_duration,
+            // Yp
+            yp_urls,
+            yp_update_interval,
+            // Ban
+            ban_list,
+            // Tls
+            tls_enabled,
+            tls_port,
+            tls_cert_file,
+            tls_key_file,
         })
     }
 
+    /// 平文のパスワードをハッシュ化して設定する
+    pub fn set_password(&mut self, password: &str) {
+        self.password = Some(ConfigPassword::Plain(password.into()).to_hashed());
+    }
+
+    /// usernameが設定されていない場合はpasswordだけを確認する
+    pub fn authenticate(&self, username: &str, password: &str) -> Result<(), AuthError> {
+        let Some(config_password) = &self.password else {
+            return Err(AuthError::PasswordNotSet);
+        };
+        match &self.username {
+            Some(name) if name != username => Err(AuthError::WrongPassword),
+            _ => config_password.verify_password(password),
+        }
+    }
+
     // pub fn save_str(&self) -> Result<Vec<u8>, ConfigError> {
     pub fn save_str(&self) -> Vec<u8> {
         let mut ini = ini::Ini::new();
@@ -161,8 +478,18 @@ impl Config {
             .set("server_port", &self.server_port.to_string())
             .set("rtmp_port", &self.rtmp_port.to_string())
             .set(
-                "permit_address",
+                "local_address",
                 serde_json::to_string(&self.local_address).unwrap(),
+            )
+            .set(
+                "trusted_proxies",
+                serde_json::to_string(&self.trusted_proxies).unwrap(),
+            )
+            .set(
+                "broadcast_id",
+                self.broadcast_id
+                    .as_ref()
+                    .map_or(String::new(), |id| id.to_string()),
             );
 
         ini.with_section(Some(SECTION_PRIVACY))
@@ -175,6 +502,10 @@ impl Config {
             .set(
                 "password",
                 self.password.as_ref().map_or(String::new(), |pw| pw.into()),
+            )
+            .set(
+                "token_secret",
+                self.token_secret.clone().unwrap_or_default(),
             );
         ini.with_section(Some(SECTION_ROOT))
             .set("root_mode", &self.root_mode.to_string())
@@ -185,6 +516,54 @@ impl Config {
                     .as_ref()
                     .map_or(String::new(), |id| id.to_string()),
             );
+        ini.with_section(Some(SECTION_RECORD))
+            .set(
+                "record_directory",
+                self.record_directory.to_string_lossy().to_string(),
+            )
+            .set("record_filename", &self.record_filename)
+            .set("record_rotate_size", self.record_rotate_size.to_string())
+            .set(
+                "record_rotate_duration",
+                self.record_rotate_duration.to_string(),
+            );
+        ini.with_section(Some(SECTION_LIMIT))
+            .set("max_relays", self.max_relays.to_string())
+            .set("max_listeners", self.max_listeners.to_string())
+            .set("max_total_relays", self.max_total_relays.to_string())
+            .set("max_total_listeners", self.max_total_listeners.to_string())
+            .set(
+                "max_connections_per_ip",
+                self.max_connections_per_ip.to_string(),
+            )
+            .set("max_upload_kbps", self.max_upload_kbps.to_string())
+            .set(
+                "max_channel_upload_kbps",
+                self.max_channel_upload_kbps.to_string(),
+            )
+            .set(
+                "max_connection_upload_kbps",
+                self.max_connection_upload_kbps.to_string(),
+            )
+            .set("max_connections", self.max_connections.to_string())
+            .set("accepts_per_minute", self.accepts_per_minute.to_string())
+            .set("handshake_timeout", self.handshake_timeout.to_string())
+            .set("ban_threshold", self.ban_threshold.to_string())
+            .set("ban_duration", self.ban_duration.to_string());
+        ini.with_section(Some(SECTION_YP))
+            .set("yp_urls", serde_json::to_string(&self.yp_urls).unwrap())
+            .set("yp_update_interval", self.yp_update_interval.to_string());
+        ini.with_section(Some(SECTION_BAN))
+            .set("ban_list", serde_json::to_string(&self.ban_list).unwrap());
+        let path_or_empty = |p: &Option<PathBuf>| {
+            p.as_ref()
+                .map_or(String::new(), |p| p.to_string_lossy().to_string())
+        };
+        ini.with_section(Some(SECTION_TLS))
+            .set("tls_enabled", self.tls_enabled.to_string())
+            .set("tls_port", self.tls_port.to_string())
+            .set("tls_cert_file", path_or_empty(&self.tls_cert_file))
+            .set("tls_key_file", path_or_empty(&self.tls_key_file));
 
         let mut buf = Vec::new();
         let _r = ini.write_to(&mut buf).unwrap();
@@ -218,11 +597,43 @@ impl Default for Config {
             server_port: 17144,
             rtmp_port: 11935,
             local_address: vec!["127.0.0.0/8".parse().unwrap()],
+            trusted_proxies: vec![],
+            broadcast_id: None,
             root_mode: false,
             root_session_id: None,
             //
             username: None,
             password: None,
+            token_secret: None,
+            //
+            record_directory: PathBuf::from("recordings"),
+            record_filename: "{name}_{time}".into(),
+            record_rotate_size: 0,
+            record_rotate_duration: 0,
+            //
+            max_relays: 0,
+            max_listeners: 0,
+            max_total_relays: 0,
+            max_total_listeners: 0,
+            max_connections_per_ip: 0,
+            max_upload_kbps: 0,
+            max_channel_upload_kbps: 0,
+            max_connection_upload_kbps: 0,
+            max_connections: 1000,
+            accepts_per_minute: 120,
+            handshake_timeout: 10,
+            ban_threshold: 10,
+            ban_duration: 600,
+            //
+            yp_urls: vec![],
+            yp_update_interval: 600,
+            //
+            ban_list: vec![],
+            //
+            tls_enabled: false,
+            tls_port: 0,
+            tls_cert_file: None,
+            tls_key_file: None,
         }
     }
 }
@@ -265,8 +676,7 @@ pub enum ConfigPassword {
 }
 
 impl ConfigPassword {
-    #[allow(dead_code)]
-    fn verify_password(&self, password: &str) -> Result<(), AuthError> {
+    pub fn verify_password(&self, password: &str) -> Result<(), AuthError> {
         match self {
             ConfigPassword::Plain(plain) => {
                 if plain == password {
@@ -285,7 +695,7 @@ impl ConfigPassword {
         }
     }
 
-    fn to_hashed(&self) -> Self {
+    pub fn to_hashed(&self) -> Self {
         match self {
             ConfigPassword::Hashed(hashed) => ConfigPassword::Hashed(hashed.clone()),
             ConfigPassword::Plain(plain_password) => {
@@ -382,6 +792,62 @@ mod test {
         assert_eq!(conf.username, def_conf.username);
         assert_eq!(conf.password, def_conf.password);
         assert_eq!(conf.root_mode, def_conf.root_mode);
+        assert_eq!(conf.record_directory, def_conf.record_directory);
+        assert_eq!(conf.record_filename, def_conf.record_filename);
+
+        let s = render!(include_str!("config.test.ini.j2"), record_directory => "/tmp/rec", record_rotate_size => 1024);
+        let conf = Config::load_str(&s).unwrap();
+        assert_eq!(conf.record_directory, PathBuf::from("/tmp/rec"));
+        assert_eq!(conf.record_rotate_size, 1024);
+        assert_eq!(conf.record_rotate_duration, 0);
+
+        let s = render!(include_str!("config.test.ini.j2"), max_relays => 4, max_upload_kbps => 2000, max_connections_per_ip => 3);
+        let conf = Config::load_str(&s).unwrap();
+        assert_eq!(conf.max_relays, 4);
+        assert_eq!(conf.max_listeners, 0);
+        assert_eq!(conf.max_total_listeners, 0);
+        assert_eq!(conf.max_connections_per_ip, 3);
+        assert_eq!(conf.max_upload_kbps, 2000);
+        assert_eq!(conf.max_connection_upload_kbps, 0);
+
+        // 保存した内容を読み戻せる
+        let mut conf = Config::default();
+        conf.local_address = vec!["192.168.0.0/16".parse().unwrap()];
+        conf.max_listeners = 10;
+        conf.trusted_proxies = vec!["10.0.0.1/32".parse().unwrap()];
+        conf.broadcast_id = Some(GnuId::new_random());
+        conf.ban_list = vec![BanEntry {
+            net: "10.0.0.1/32".parse().unwrap(),
+            expires_at: None,
+            comment: "spam".into(),
+        }];
+        let saved = Config::load_str(&conf.to_string()).unwrap();
+        assert_eq!(saved.local_address, conf.local_address);
+        assert_eq!(saved.max_listeners, 10);
+        assert_eq!(saved.trusted_proxies, conf.trusted_proxies);
+        assert_eq!(saved.broadcast_id, conf.broadcast_id);
+        assert_eq!(saved.ban_list, conf.ban_list);
+
+        let s = render!(include_str!("config.test.ini.j2"), tls_enabled => true, tls_cert_file => "/etc/peercast/cert.pem");
+        let conf = Config::load_str(&s).unwrap();
+        assert!(conf.tls_enabled);
+        assert_eq!(conf.tls_port, 0);
+        assert_eq!(
+            conf.tls_cert_file,
+            Some(PathBuf::from("/etc/peercast/cert.pem"))
+        );
+        assert_eq!(conf.tls_key_file, None);
+
+        let s = render!(include_str!("config.test.ini.j2"), yp_urls => r#"["http://yp.example.com/index.txt"]"#);
+        let conf = Config::load_str(&s).unwrap();
+        assert_eq!(
+            conf.yp_urls,
+            vec![Url::parse("http://yp.example.com/index.txt").unwrap()]
+        );
+        assert_eq!(conf.yp_update_interval, 600);
+
+        let conf = Config::load_str("[Server]\npermit_address=[\"10.0.0.0/8\"]").unwrap();
+        assert_eq!(conf.local_address, vec!["10.0.0.0/8".parse().unwrap()]);
 
         let s = render!(include_str!("config.test.ini.j2"),  server_port => 1, password=>"plain_password");
         let conf = Config::load_str(&s).unwrap();
@@ -389,6 +855,11 @@ mod test {
             conf.password,
             Some(ConfigPassword::Plain("plain_password".to_string()))
         );
+        assert_eq!(conf.token_secret, None);
+
+        let s = render!(include_str!("config.test.ini.j2"), token_secret => "0123abcd");
+        let conf = Config::load_str(&s).unwrap();
+        assert_eq!(conf.token_secret.as_deref(), Some("0123abcd"));
     }
 
     #[test]
@@ -457,6 +928,13 @@ mod test {
             _ => assert!(false),
         };
 
+        // record_rotate_size
+        let s = render!(include_str!("config.test.ini.j2"),  record_rotate_size => "1MB");
+        match Config::load_str(&s) {
+            Err(ConfigError::ParseVariable(ParseVariableError::Integer(_))) => assert!(true),
+            _ => assert!(false),
+        };
+
         // username
         // MEMO: 今のところエラーになる表現無し(usernameにはどんな文字でも使える)
 
@@ -475,6 +953,23 @@ mod test {
         assert!(p.verify_password("7145").is_err());
     }
 
+    #[test]
+    fn test_config_authenticate() {
+        let mut config = Config::default();
+        assert!(matches!(
+            config.authenticate("", "7144"),
+            Err(AuthError::PasswordNotSet)
+        ));
+
+        config.password = Some(ConfigPassword::Plain("7144".into()));
+        assert!(config.authenticate("anyone", "7144").is_ok());
+        assert!(config.authenticate("anyone", "7145").is_err());
+
+        config.username = Some("peca".into());
+        assert!(config.authenticate("peca", "7144").is_ok());
+        assert!(config.authenticate("anyone", "7144").is_err());
+    }
+
     #[ignore = "spec test"]
     #[test]
     fn path_buf() {
@@ -9,7 +9,7 @@ use crate::error::{self, HandshakeError};
 
 static GLOBAL_CONNECTION_COUNT: AtomicI32 = AtomicI32::new(1);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
 pub struct ConnectionId(pub i32);
 impl ConnectionId {
     pub fn new() -> Self {
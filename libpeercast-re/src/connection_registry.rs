@@ -0,0 +1,494 @@
+//! 生きている接続の一覧
+//!
+//! HTTPの視聴者、PCPの中継、RTMPの配信/視聴を登録しておき、
+//! APIから一覧を見たり切断したりできるようにする
+//! 登録は接続側が持つConnectionHandleがdropされるまで残る
+//! レジストリはChannelManagerが持ち、AppStateからも同じものを参照する
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use prometheus::IntCounter;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::{config::Config, error::LimitExceeded, metrics, pcp::GnuId, ConnectionId};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Protocol {
+    /// HTTPでストリームを視聴している
+    HttpListener,
+    /// 上流からPCPで受信している
+    PcpRelayIn,
+    /// 下流へPCPで中継している
+    PcpRelayOut,
+    /// ハンドシェイク済みでPublish/Playの前
+    Rtmp,
+    RtmpPublisher,
+    RtmpPlayer,
+    /// YPとのやり取り(index.txtの取得・ルートへの掲載)
+    YpLink,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::HttpListener => "http_listener",
+            Protocol::PcpRelayIn => "pcp_relay_in",
+            Protocol::PcpRelayOut => "pcp_relay_out",
+            Protocol::Rtmp => "rtmp",
+            Protocol::RtmpPublisher => "rtmp_publisher",
+            Protocol::RtmpPlayer => "rtmp_player",
+            Protocol::YpLink => "yp_link",
+        }
+    }
+
+    // こちらが送る側の接続。IP毎の上限はこれを数える
+    fn is_downstream(&self) -> bool {
+        matches!(
+            self,
+            Protocol::HttpListener | Protocol::PcpRelayOut | Protocol::RtmpPlayer
+        )
+    }
+}
+
+/// 接続数の上限。0は無制限
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_listeners: u32,
+    pub max_relays: u32,
+    pub max_total_listeners: u32,
+    pub max_total_relays: u32,
+    pub max_connections_per_ip: u32,
+}
+
+impl ConnectionLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_listeners: config.max_listeners,
+            max_relays: config.max_relays,
+            max_total_listeners: config.max_total_listeners,
+            max_total_relays: config.max_total_relays,
+            max_connections_per_ip: config.max_connections_per_ip,
+        }
+    }
+
+    /// (チャンネル毎, 全体)の上限
+    pub fn max_for(&self, protocol: Protocol) -> (u32, u32) {
+        match protocol {
+            Protocol::HttpListener => (self.max_listeners, self.max_total_listeners),
+            Protocol::PcpRelayOut => (self.max_relays, self.max_total_relays),
+            _ => (0, 0),
+        }
+    }
+}
+
+/// チャンネルの視聴者・リレーの数と上限(0は無制限)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ChannelConnections {
+    pub listeners: u32,
+    pub max_listeners: u32,
+    pub relays: u32,
+    pub max_relays: u32,
+    // 全体の上限に達しているか
+    #[serde(skip)]
+    total_listeners_full: bool,
+    #[serde(skip)]
+    total_relays_full: bool,
+}
+
+impl ChannelConnections {
+    pub fn is_listener_full(&self) -> bool {
+        self.total_listeners_full
+            || (self.max_listeners > 0 && self.listeners >= self.max_listeners)
+    }
+
+    pub fn is_relay_full(&self) -> bool {
+        self.total_relays_full || (self.max_relays > 0 && self.relays >= self.max_relays)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub connection_id: ConnectionId,
+    pub protocol: Protocol,
+    pub remote: SocketAddr,
+    pub channel_id: Option<GnuId>,
+    pub user_agent: Option<String>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug)]
+struct Meta {
+    protocol: Protocol,
+    channel_id: Option<GnuId>,
+    user_agent: Option<String>,
+    // チャンネル毎の転送量(in, out)
+    channel_bytes: Option<(IntCounter, IntCounter)>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    connection_id: ConnectionId,
+    remote: SocketAddr,
+    started_at: DateTime<Utc>,
+    meta: Mutex<Meta>,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    // 切断を要求された時にPCPのquitコードが入る
+    quit_tx: watch::Sender<Option<u32>>,
+}
+
+impl Entry {
+    fn info(&self) -> ConnectionInfo {
+        let meta = self.meta.lock().unwrap();
+        ConnectionInfo {
+            connection_id: self.connection_id,
+            protocol: meta.protocol,
+            remote: self.remote,
+            channel_id: meta.channel_id,
+            user_agent: meta.user_agent.clone(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            started_at: self.started_at,
+        }
+    }
+}
+
+type Entries = Arc<Mutex<HashMap<ConnectionId, Arc<Entry>>>>;
+
+////////////////////////////////////////////////////////////////////////////////
+// ConnectionRegistry
+//
+#[derive(Debug, Default)]
+pub struct ConnectionRegistry {
+    entries: Entries,
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn register(
+        &self,
+        connection_id: ConnectionId,
+        protocol: Protocol,
+        remote: SocketAddr,
+    ) -> ConnectionHandle {
+        let handle = self.new_handle(connection_id, protocol, remote, None);
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(connection_id, Arc::clone(&handle.entry));
+        handle
+    }
+
+    /// 上限を確認してから登録する。数えるのと登録を同じロックの中でやる
+    pub fn try_register(
+        &self,
+        connection_id: ConnectionId,
+        protocol: Protocol,
+        remote: SocketAddr,
+        channel_id: GnuId,
+        limits: &ConnectionLimits,
+    ) -> Result<ConnectionHandle, LimitExceeded> {
+        let mut entries = self.entries.lock().unwrap();
+
+        let (max_channel, max_total) = limits.max_for(protocol);
+        let (mut in_channel, mut total, mut same_ip) = (0, 0, 0);
+        for entry in entries.values() {
+            let meta = entry.meta.lock().unwrap();
+            if meta.protocol == protocol {
+                total += 1;
+                if meta.channel_id == Some(channel_id) {
+                    in_channel += 1;
+                }
+            }
+            if meta.protocol.is_downstream() && entry.remote.ip() == remote.ip() {
+                same_ip += 1;
+            }
+        }
+        let exceeded = |max: u32, count: u32| max > 0 && count >= max;
+        let relay = protocol == Protocol::PcpRelayOut;
+        if exceeded(max_channel, in_channel) {
+            return Err(if relay {
+                LimitExceeded::ChannelRelays(max_channel)
+            } else {
+                LimitExceeded::ChannelListeners(max_channel)
+            });
+        }
+        if exceeded(max_total, total) {
+            return Err(if relay {
+                LimitExceeded::TotalRelays(max_total)
+            } else {
+                LimitExceeded::TotalListeners(max_total)
+            });
+        }
+        if exceeded(limits.max_connections_per_ip, same_ip) {
+            return Err(LimitExceeded::PerIp(limits.max_connections_per_ip));
+        }
+
+        let handle = self.new_handle(connection_id, protocol, remote, Some(channel_id));
+        entries.insert(connection_id, Arc::clone(&handle.entry));
+        Ok(handle)
+    }
+
+    fn new_handle(
+        &self,
+        connection_id: ConnectionId,
+        protocol: Protocol,
+        remote: SocketAddr,
+        channel_id: Option<GnuId>,
+    ) -> ConnectionHandle {
+        let (quit_tx, quit_rx) = watch::channel(None);
+        let entry = Arc::new(Entry {
+            connection_id,
+            remote,
+            started_at: Utc::now(),
+            meta: Mutex::new(Meta {
+                protocol,
+                channel_id,
+                user_agent: None,
+                channel_bytes: channel_id.as_ref().map(metrics::channel_bytes),
+            }),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            quit_tx,
+        });
+        ConnectionHandle {
+            entries: Arc::clone(&self.entries),
+            entry,
+            quit_rx,
+        }
+    }
+
+    pub fn channel_connections(
+        &self,
+        channel_id: &GnuId,
+        limits: &ConnectionLimits,
+    ) -> ChannelConnections {
+        let full = |max: u32, protocol| max > 0 && self.count(protocol, None) >= max;
+        ChannelConnections {
+            listeners: self.count(Protocol::HttpListener, Some(channel_id)),
+            max_listeners: limits.max_listeners,
+            relays: self.count(Protocol::PcpRelayOut, Some(channel_id)),
+            max_relays: limits.max_relays,
+            total_listeners_full: full(limits.max_total_listeners, Protocol::HttpListener),
+            total_relays_full: full(limits.max_total_relays, Protocol::PcpRelayOut),
+        }
+    }
+
+    /// channel_idがNoneなら全チャンネルの合計
+    pub fn count(&self, protocol: Protocol, channel_id: Option<&GnuId>) -> u32 {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .values()
+            .filter(|entry| {
+                let meta = entry.meta.lock().unwrap();
+                meta.protocol == protocol
+                    && channel_id.map_or(true, |id| meta.channel_id.as_ref() == Some(id))
+            })
+            .count() as u32
+    }
+
+    /// connection_id順
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let entries = self.entries.lock().unwrap();
+        let mut list = entries.values().map(|e| e.info()).collect::<Vec<_>>();
+        list.sort_by_key(|c| c.connection_id);
+        list
+    }
+
+    pub fn get(&self, connection_id: &ConnectionId) -> Option<ConnectionInfo> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(connection_id).map(|e| e.info())
+    }
+
+    /// 接続側に切断を要求する。PCPの接続ならquit_code(error_code::QuitCode)を送ってから切る
+    pub fn disconnect(&self, connection_id: &ConnectionId, quit_code: u32) -> bool {
+        let entries = self.entries.lock().unwrap();
+        match entries.get(connection_id) {
+            Some(entry) => {
+                info!("disconnect requested {connection_id} quit_code:{quit_code}");
+                entry.quit_tx.send_replace(Some(quit_code));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// ConnectionHandle
+//
+#[derive(Debug)]
+pub struct ConnectionHandle {
+    entries: Entries,
+    entry: Arc<Entry>,
+    quit_rx: watch::Receiver<Option<u32>>,
+}
+
+impl ConnectionHandle {
+    pub fn connection_id(&self) -> ConnectionId {
+        self.entry.connection_id
+    }
+
+    pub fn set_protocol(&self, protocol: Protocol) {
+        self.entry.meta.lock().unwrap().protocol = protocol;
+    }
+
+    pub fn set_channel(&self, channel_id: GnuId) {
+        let mut meta = self.entry.meta.lock().unwrap();
+        meta.channel_id = Some(channel_id);
+        meta.channel_bytes = Some(metrics::channel_bytes(&channel_id));
+    }
+
+    pub fn set_user_agent(&self, user_agent: impl Into<String>) {
+        self.entry.meta.lock().unwrap().user_agent = Some(user_agent.into());
+    }
+
+    pub fn add_bytes_in(&self, n: usize) {
+        self.entry.bytes_in.fetch_add(n as u64, Ordering::Relaxed);
+        if let Some((bytes_in, _)) = &self.entry.meta.lock().unwrap().channel_bytes {
+            bytes_in.inc_by(n as u64);
+        }
+    }
+
+    pub fn add_bytes_out(&self, n: usize) {
+        self.entry.bytes_out.fetch_add(n as u64, Ordering::Relaxed);
+        if let Some((_, bytes_out)) = &self.entry.meta.lock().unwrap().channel_bytes {
+            bytes_out.inc_by(n as u64);
+        }
+    }
+
+    /// 切断を要求されていればquitコードを返す
+    pub fn quit_code(&self) -> Option<u32> {
+        *self.quit_rx.borrow()
+    }
+
+    /// 切断を要求されるまで待つ
+    pub async fn disconnect_requested(&mut self) -> u32 {
+        loop {
+            if let Some(code) = *self.quit_rx.borrow_and_update() {
+                return code;
+            }
+            // entryを自分で持っているのでSenderが先に無くなることは無い
+            if self.quit_rx.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&self.entry.connection_id);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use crate::pcp::error_code::QuitCode;
+
+    use super::*;
+
+    #[crate::test]
+    async fn test_connection_registry() {
+        let registry = ConnectionRegistry::new();
+        let remote = "192.168.0.2:7144".parse().unwrap();
+        let id = ConnectionId::new();
+
+        let mut handle = registry.register(id, Protocol::PcpRelayIn, remote);
+        let channel_id = GnuId::new();
+        handle.set_channel(channel_id);
+        handle.add_bytes_in(100);
+        handle.add_bytes_out(20);
+
+        let info = registry.get(&id).unwrap();
+        assert_eq!(info.protocol, Protocol::PcpRelayIn);
+        assert_eq!(info.channel_id, Some(channel_id));
+        assert_eq!((info.bytes_in, info.bytes_out), (100, 20));
+        assert_eq!(registry.list().len(), 1);
+
+        assert_eq!(handle.quit_code(), None);
+        assert!(registry.disconnect(&id, QuitCode::USER_SHUTDOWN));
+        assert_eq!(handle.disconnect_requested().await, QuitCode::USER_SHUTDOWN);
+
+        drop(handle);
+        assert!(registry.get(&id).is_none());
+        assert!(!registry.disconnect(&id, QuitCode::USER_SHUTDOWN));
+    }
+
+    #[test]
+    fn test_try_register() {
+        let registry = ConnectionRegistry::new();
+        let remote = "192.168.0.2:7144".parse().unwrap();
+        let other = "192.168.0.3:7144".parse().unwrap();
+        let (ch1, ch2) = (GnuId::new(), GnuId::new());
+        let limits = ConnectionLimits {
+            max_listeners: 1,
+            max_total_listeners: 2,
+            max_connections_per_ip: 2,
+            ..Default::default()
+        };
+        let listen = |remote, channel_id| {
+            registry.try_register(
+                ConnectionId::new(),
+                Protocol::HttpListener,
+                remote,
+                channel_id,
+                &limits,
+            )
+        };
+
+        let h1 = listen(remote, ch1).unwrap();
+        assert_eq!(
+            listen(other, ch1).unwrap_err(),
+            LimitExceeded::ChannelListeners(1)
+        );
+        let h2 = listen(remote, ch2).unwrap();
+        assert_eq!(registry.count(Protocol::HttpListener, Some(&ch2)), 1);
+        assert_eq!(registry.count(Protocol::HttpListener, None), 2);
+
+        // リレーは視聴者とは別に数えるが、IP毎の上限には入る
+        let relay = registry.try_register(
+            ConnectionId::new(),
+            Protocol::PcpRelayOut,
+            remote,
+            ch1,
+            &limits,
+        );
+        assert_eq!(relay.unwrap_err(), LimitExceeded::PerIp(2));
+        let relay = registry.try_register(
+            ConnectionId::new(),
+            Protocol::PcpRelayOut,
+            other,
+            ch1,
+            &limits,
+        );
+        assert!(relay.is_ok());
+
+        let conns = registry.channel_connections(&ch1, &limits);
+        assert_eq!((conns.listeners, conns.relays), (1, 1));
+        assert!(conns.is_listener_full());
+        assert!(!conns.is_relay_full());
+
+        drop((h1, h2));
+        assert!(listen(other, ch1).is_ok());
+    }
+}
@@ -2,7 +2,7 @@ use std::{net::AddrParseError, num::ParseIntError, str::ParseBoolError};
 
 use thiserror::Error;
 
-use crate::pcp::{GnuId, GnuIdParseError};
+use crate::pcp::{error_code::QuitCode, GnuId, GnuIdParseError};
 
 // 主に通信について
 #[derive(Debug, Error)]
@@ -20,6 +20,9 @@ pub enum HandshakeError {
     #[error("ChannelNotFound")]
     ChannelNotFound,
 
+    #[error("Forbidden")]
+    Forbidden,
+
     #[error("Could not find a server to connect")]
     ServerNotFound,
 
@@ -36,6 +39,48 @@ pub enum HandshakeError {
     Failed,
 }
 
+impl HandshakeError {
+    /// メトリクスのラベル用
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HandshakeError::HttpResponse => "http_response",
+            HandshakeError::ChannelNotFound => "channel_not_found",
+            HandshakeError::Forbidden => "forbidden",
+            HandshakeError::ServerNotFound => "server_not_found",
+            HandshakeError::Timeout => "timeout",
+            HandshakeError::Parse(_) => "parse",
+            HandshakeError::IoError(_) => "io",
+            HandshakeError::Failed => "failed",
+        }
+    }
+}
+
+// 接続数の上限
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum LimitExceeded {
+    #[error("too many listeners on this channel (max {0})")]
+    ChannelListeners(u32),
+
+    #[error("too many listeners (max {0})")]
+    TotalListeners(u32),
+
+    #[error("too many relays on this channel (max {0})")]
+    ChannelRelays(u32),
+
+    #[error("too many relays (max {0})")]
+    TotalRelays(u32),
+
+    #[error("too many connections from your address (max {0})")]
+    PerIp(u32),
+}
+
+impl LimitExceeded {
+    /// PCPの下流にはUNAVAILABLEを返して他のホストへ行ってもらう
+    pub fn quit_code(&self) -> u32 {
+        QuitCode::UNAVAILABLE_ERROR
+    }
+}
+
 // 主にデータ解析について
 #[derive(Error, Debug)]
 pub enum AtomParseError {
@@ -92,4 +137,38 @@ pub enum ParseVariableError {
 pub enum AuthError {
     #[error("Password is not matche to store password hash.")]
     WrongPassword,
+    #[error("Password is not configured.")]
+    PasswordNotSet,
+}
+
+// 録画について
+#[derive(Error, Debug)]
+pub enum RecordError {
+    #[error("Channel is already recording")]
+    AlreadyRecording,
+
+    #[error("Channel is not recording")]
+    NotRecording,
+
+    #[error("IoError {0:?}")]
+    Io(#[from] std::io::Error),
+}
+
+// HTTPSについて
+#[derive(Error, Debug)]
+pub enum TlsError {
+    #[error("IoError {0:?}")]
+    Io(#[from] std::io::Error),
+
+    #[error("no certificate found in {0:?}")]
+    NoCertificate(std::path::PathBuf),
+
+    #[error("no private key found in {0:?}")]
+    NoPrivateKey(std::path::PathBuf),
+
+    #[error("failed to generate self-signed certificate: {0}")]
+    Generate(#[from] rcgen::Error),
+
+    #[error(transparent)]
+    Rustls(#[from] tokio_rustls::rustls::Error),
 }
@@ -0,0 +1,208 @@
+//! チャンネルや接続の変化を購読者に配信する
+//!
+//! HTTPのSSE/WebSocketから流す他、内部で状態を監視したい時にも使う
+
+use std::net::SocketAddr;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::trace;
+
+use crate::{
+    pcp::{ChannelInfo, GnuId, TaskStatus, TrackInfo},
+    ConnectionId,
+};
+
+// 受信側が遅れた場合はこれを超えた分が捨てられる
+const EVENT_BUS_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionKind {
+    /// HTTPで視聴している
+    Listener,
+    /// PCPで中継している(上流・下流とも)
+    Relay,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ChannelCreated {
+        channel_id: GnuId,
+    },
+    ChannelDeleted {
+        channel_id: GnuId,
+    },
+    ChannelStatus {
+        channel_id: GnuId,
+        status: TaskStatus,
+    },
+    ChannelInfo {
+        channel_id: GnuId,
+        info: ChannelInfo,
+    },
+    ChannelTrack {
+        channel_id: GnuId,
+        track: TrackInfo,
+    },
+    ConnectionJoined {
+        channel_id: GnuId,
+        connection_id: ConnectionId,
+        kind: ConnectionKind,
+        remote: SocketAddr,
+    },
+    ConnectionLeft {
+        channel_id: GnuId,
+        connection_id: ConnectionId,
+        kind: ConnectionKind,
+    },
+    /// PCP_MESGで届いたテキスト。fromは送ったノードのSessionID
+    ChannelMessage {
+        channel_id: GnuId,
+        from: Option<GnuId>,
+        text: String,
+    },
+}
+
+impl Event {
+    pub fn channel_id(&self) -> GnuId {
+        match self {
+            Event::ChannelCreated { channel_id }
+            | Event::ChannelDeleted { channel_id }
+            | Event::ChannelStatus { channel_id, .. }
+            | Event::ChannelInfo { channel_id, .. }
+            | Event::ChannelTrack { channel_id, .. }
+            | Event::ConnectionJoined { channel_id, .. }
+            | Event::ConnectionLeft { channel_id, .. }
+            | Event::ChannelMessage { channel_id, .. } => *channel_id,
+        }
+    }
+
+    /// SSEのevent名
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ChannelCreated { .. } => "channel_created",
+            Event::ChannelDeleted { .. } => "channel_deleted",
+            Event::ChannelStatus { .. } => "channel_status",
+            Event::ChannelInfo { .. } => "channel_info",
+            Event::ChannelTrack { .. } => "channel_track",
+            Event::ConnectionJoined { .. } => "connection_joined",
+            Event::ConnectionLeft { .. } => "connection_left",
+            Event::ChannelMessage { .. } => "channel_message",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+
+    /// 購読者がいなければ捨てる
+    pub fn publish(&self, event: Event) {
+        trace!(?event);
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// dropされた時にConnectionLeftを流すガードを作る
+    pub fn connection_guard(
+        &self,
+        channel_id: GnuId,
+        connection_id: ConnectionId,
+        kind: ConnectionKind,
+        remote: SocketAddr,
+    ) -> ConnectionGuard {
+        self.publish(Event::ConnectionJoined {
+            channel_id,
+            connection_id,
+            kind,
+            remote,
+        });
+        ConnectionGuard {
+            events: self.clone(),
+            channel_id,
+            connection_id,
+            kind,
+        }
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    events: EventBus,
+    channel_id: GnuId,
+    connection_id: ConnectionId,
+    kind: ConnectionKind,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.events.publish(Event::ConnectionLeft {
+            channel_id: self.channel_id,
+            connection_id: self.connection_id,
+            kind: self.kind,
+        });
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[crate::test]
+    async fn test_event_bus() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        let channel_id = GnuId::new();
+
+        let guard = bus.connection_guard(
+            channel_id,
+            ConnectionId::new(),
+            ConnectionKind::Listener,
+            "127.0.0.1:7144".parse().unwrap(),
+        );
+        drop(guard);
+
+        let joined = rx.recv().await.unwrap();
+        assert_eq!(joined.name(), "connection_joined");
+        assert_eq!(joined.channel_id(), channel_id);
+        let left = rx.recv().await.unwrap();
+        assert_eq!(left.name(), "connection_left");
+
+        let json = serde_json::to_value(&left).unwrap();
+        assert_eq!(json["type"], "connection_left");
+        assert_eq!(json["kind"], "listener");
+        assert_eq!(json["channel_id"], String::from(&channel_id));
+    }
+
+    #[test]
+    fn test_status_event_json() {
+        let event = Event::ChannelStatus {
+            channel_id: GnuId::new(),
+            status: TaskStatus::Searching {
+                searched: 1,
+                all: 3,
+            },
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], "channel_status");
+        assert_eq!(json["status"]["searching"]["all"], 3);
+    }
+}
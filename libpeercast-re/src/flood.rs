@@ -0,0 +1,262 @@
+//! accept直後の接続の洪水対策
+//!
+//! 同時接続数の上限、IP毎のaccept頻度の制限、プロトコル判別・ハンドシェイクのタイムアウト、
+//! ハンドシェイクに何度も失敗するIPの一時的なBANをまとめて扱う
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use ipnet::IpNet;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::config::Config;
+
+// accept頻度とハンドシェイク失敗を数える期間
+const WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct FloodConfig {
+    /// 同時に処理する接続数, 0で無制限
+    pub max_connections: usize,
+    /// 1つのIPから1分間にacceptする数, 0で無制限
+    pub accepts_per_minute: u32,
+    /// プロトコル判別・ハンドシェイクのタイムアウト
+    pub handshake_timeout: Duration,
+    /// 1分間にこの回数ハンドシェイクに失敗したらBANする, 0でBANしない
+    pub ban_threshold: u32,
+    pub ban_duration: Duration,
+    /// 頻度制限とBANを掛けないネットワーク
+    pub exempt: Vec<IpNet>,
+}
+
+impl FloodConfig {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_connections: config.max_connections as usize,
+            accepts_per_minute: config.accepts_per_minute,
+            handshake_timeout: Duration::from_secs(config.handshake_timeout.max(1) as u64),
+            ban_threshold: config.ban_threshold,
+            ban_duration: Duration::from_secs(config.ban_duration as u64),
+            exempt: config.local_address.clone(),
+        }
+    }
+}
+
+impl Default for FloodConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 0,
+            accepts_per_minute: 0,
+            handshake_timeout: Duration::from_secs(10),
+            ban_threshold: 0,
+            ban_duration: Duration::from_secs(600),
+            exempt: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+    #[error("too many connections")]
+    TooManyConnections,
+
+    #[error("too many accepts from this address")]
+    RateLimited,
+
+    #[error("temporarily banned")]
+    Banned,
+}
+
+#[derive(Debug)]
+struct IpState {
+    window_start: Instant,
+    accepts: u32,
+    failures: u32,
+    banned_until: Option<Instant>,
+}
+
+impl IpState {
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            accepts: 0,
+            failures: 0,
+            banned_until: None,
+        }
+    }
+
+    fn roll_window(&mut self, now: Instant) {
+        if now.saturating_duration_since(self.window_start) >= WINDOW {
+            self.window_start = now;
+            self.accepts = 0;
+            self.failures = 0;
+        }
+    }
+
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| now < until)
+    }
+
+    // 何も覚えておく必要が無くなった
+    fn is_stale(&self, now: Instant) -> bool {
+        !self.is_banned(now) && now.saturating_duration_since(self.window_start) >= WINDOW
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FloodGuard
+//
+#[derive(Debug)]
+pub struct FloodGuard {
+    config: FloodConfig,
+    active: AtomicUsize,
+    ips: Mutex<HashMap<IpAddr, IpState>>,
+}
+
+impl FloodGuard {
+    pub fn new(config: FloodConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            active: AtomicUsize::new(0),
+            ips: Default::default(),
+        })
+    }
+
+    pub fn handshake_timeout(&self) -> Duration {
+        self.config.handshake_timeout
+    }
+
+    fn is_exempt(&self, ip: &IpAddr) -> bool {
+        self.config.exempt.iter().any(|net| net.contains(ip))
+    }
+
+    /// acceptした直後に呼ぶ。Permitをdropするまで同時接続数に数える
+    pub fn accept(self: &Arc<Self>, ip: IpAddr) -> Result<AcceptPermit, Rejected> {
+        self.accept_at(ip, Instant::now())
+    }
+
+    fn accept_at(self: &Arc<Self>, ip: IpAddr, now: Instant) -> Result<AcceptPermit, Rejected> {
+        if !self.is_exempt(&ip) {
+            let mut ips = self.ips.lock().unwrap();
+            ips.retain(|_, state| !state.is_stale(now));
+            let state = ips.entry(ip).or_insert_with(|| IpState::new(now));
+            if state.is_banned(now) {
+                return Err(Rejected::Banned);
+            }
+            state.roll_window(now);
+            state.accepts += 1;
+            let limit = self.config.accepts_per_minute;
+            if limit > 0 && state.accepts > limit {
+                return Err(Rejected::RateLimited);
+            }
+        }
+
+        let max = self.config.max_connections;
+        let prev = self.active.fetch_add(1, Ordering::AcqRel);
+        if max > 0 && prev >= max {
+            self.active.fetch_sub(1, Ordering::AcqRel);
+            return Err(Rejected::TooManyConnections);
+        }
+        Ok(AcceptPermit {
+            guard: Arc::clone(self),
+        })
+    }
+
+    /// プロトコル判別やハンドシェイクに失敗した(タイムアウトを含む)
+    pub fn handshake_failed(&self, ip: IpAddr) {
+        self.handshake_failed_at(ip, Instant::now())
+    }
+
+    fn handshake_failed_at(&self, ip: IpAddr, now: Instant) {
+        let threshold = self.config.ban_threshold;
+        if threshold == 0 || self.is_exempt(&ip) {
+            return;
+        }
+        let mut ips = self.ips.lock().unwrap();
+        let state = ips.entry(ip).or_insert_with(|| IpState::new(now));
+        state.roll_window(now);
+        state.failures += 1;
+        if state.failures >= threshold && !state.is_banned(now) {
+            warn!(
+                "ban {ip} for {:?}: {} handshake failures",
+                self.config.ban_duration, state.failures
+            );
+            state.banned_until = Some(now + self.config.ban_duration);
+            state.failures = 0;
+        }
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug)]
+pub struct AcceptPermit {
+    guard: Arc<FloodGuard>,
+}
+
+impl Drop for AcceptPermit {
+    fn drop(&mut self) {
+        self.guard.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_flood_guard() {
+        let guard = FloodGuard::new(FloodConfig {
+            max_connections: 2,
+            accepts_per_minute: 3,
+            ban_threshold: 2,
+            exempt: vec!["127.0.0.0/8".parse().unwrap()],
+            ..Default::default()
+        });
+        let ip: IpAddr = "192.168.0.2".parse().unwrap();
+        let other: IpAddr = "192.168.0.3".parse().unwrap();
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let now = Instant::now();
+
+        // 同時接続数
+        let p1 = guard.accept_at(ip, now).unwrap();
+        let p2 = guard.accept_at(ip, now).unwrap();
+        assert_eq!(
+            guard.accept_at(local, now).unwrap_err(),
+            Rejected::TooManyConnections
+        );
+        drop((p1, p2));
+        assert_eq!(guard.active_connections(), 0);
+
+        // 1分に3回まで(断られた分も数える)
+        assert!(guard.accept_at(ip, now).is_ok());
+        assert_eq!(guard.accept_at(ip, now).unwrap_err(), Rejected::RateLimited);
+        assert!(guard.accept_at(other, now).is_ok());
+        let later = now + WINDOW;
+        assert!(guard.accept_at(ip, later).is_ok());
+
+        // 2回失敗したらBAN
+        guard.handshake_failed_at(other, later);
+        assert!(guard.accept_at(other, later).is_ok());
+        guard.handshake_failed_at(other, later);
+        assert_eq!(guard.accept_at(other, later).unwrap_err(), Rejected::Banned);
+        let unbanned = later + guard.config.ban_duration;
+        assert!(guard.accept_at(other, unbanned).is_ok());
+
+        // ローカルは制限しない
+        for _ in 0..5 {
+            guard.handshake_failed_at(local, now);
+            assert!(guard.accept_at(local, now).is_ok());
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! 旧PeerCast互換の /admin?cmd= と /html/
+//!
+//! viewxmlを読む監視スクリプトや、stop/bumpを叩くプレイヤーのためのもの
+//! XMLの形式はPeerCast 0.1218のservmgr.cppに合わせている
+
+use std::{collections::HashMap, fmt::Write, net::SocketAddr, str::FromStr};
+
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use chrono::Utc;
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use http::StatusCode;
+use tracing::{debug, info};
+
+use crate::{
+    connection_registry::{ConnectionRegistry, Protocol},
+    listener_token,
+    pcp::{Channel, ChannelType, GnuId, RelayTaskConfig, SourceTaskConfig, TaskStatus},
+    ConnectionId,
+};
+
+use super::AppState;
+
+pub(super) struct AdminSvc;
+
+impl AdminSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/admin", get(admin))
+            .route("/html", get(|| async { Redirect::permanent("/html/") }))
+            .route("/html/", get(html_index))
+            // /html/en/index.html など旧PeerCastのページは全て一覧に寄せる
+            .route("/html/{*page}", get(html_index))
+    }
+}
+
+async fn admin(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let cmd = params.get("cmd").map(String::as_str).unwrap_or_default();
+    debug!(cmd, ?params);
+
+    match cmd {
+        "viewxml" => {
+            let channels = state.channel_manager.map_collect(|(_, ch)| ch.clone());
+            let uptime = (Utc::now() - state.started_at).num_seconds();
+            let xml = view_xml(&state.session_id, uptime, &channels, &state.connections);
+            ([(http::header::CONTENT_TYPE, "text/xml")], xml).into_response()
+        }
+        "stop" => {
+            let ch = match find_channel(&state, &params) {
+                Ok(ch) => ch,
+                Err(status) => return status.into_response(),
+            };
+            info!("STOP channel by admin CID:{}", ch.id());
+            ch.stop();
+            state.channel_manager.delete(&ch.id());
+            Redirect::to("/html/").into_response()
+        }
+        "bump" => {
+            let ch = match find_channel(&state, &params) {
+                Ok(ch) => ch,
+                Err(status) => return status.into_response(),
+            };
+            info!("BUMP channel by admin CID:{}", ch.id());
+            ch.retry();
+            Redirect::to("/html/").into_response()
+        }
+        "relay" => match relay(&state, &params).await {
+            Ok(()) => Redirect::to("/html/").into_response(),
+            Err(status) => status.into_response(),
+        },
+        _ => StatusCode::BAD_REQUEST.into_response(),
+    }
+}
+
+fn find_channel(state: &AppState, params: &HashMap<String, String>) -> Result<Channel, StatusCode> {
+    let id = params
+        .get("id")
+        .and_then(|id| GnuId::from_str(id).ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    state.channel_manager.get(&id).ok_or(StatusCode::NOT_FOUND)
+}
+
+// cmd=relay&id=[GnuID]&tip=[IP:PORT]&auth=[TOKEN]
+// 既に受信中のチャンネルならtipは無くても良い, 限定公開チャンネルならauthが要る
+async fn relay(state: &AppState, params: &HashMap<String, String>) -> Result<(), StatusCode> {
+    let id = params
+        .get("id")
+        .and_then(|id| GnuId::from_str(id).ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let tip = params
+        .get("tip")
+        .and_then(|tip| tip.parse::<SocketAddr>().ok());
+
+    let ch = match (state.channel_manager.get(&id), tip) {
+        (Some(ch), _) => ch,
+        (None, Some(_)) => state
+            .channel_manager
+            .create_or_get(id, ChannelType::Relay, None, None),
+        (None, None) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    match (ch.status(), tip) {
+        (TaskStatus::Receiving | TaskStatus::Searching { .. } | TaskStatus::Init, _) => {}
+        (_, Some(addr)) => {
+            info!("RELAY channel by admin CID:{id} tip:{addr}");
+            let token = params.get("auth").cloned();
+            if let Some(token) = &token {
+                listener_token::keep_relay_token(&ch, addr, token).await;
+            }
+            let config = SourceTaskConfig::Relay(RelayTaskConfig {
+                addr,
+                self_addr: None,
+                token,
+            });
+            ch.connect(ConnectionId::new(), config);
+        }
+        (_, None) => {
+            ch.retry();
+        }
+    }
+    Ok(())
+}
+
+async fn html_index(State(state): State<AppState>) -> Html<String> {
+    let channels = state.channel_manager.map_collect(|(_, ch)| ch.clone());
+    Html(channels_html(&channels))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// XML / HTML
+//
+// PeerCastのChanHit::getStatusStrに合わせる
+fn status_str(ch: &Channel) -> &'static str {
+    match (ch.status(), ch.channel_type()) {
+        (TaskStatus::Receiving, ChannelType::Broadcast) => "BROADCAST",
+        (TaskStatus::Receiving, ChannelType::Relay) => "RECEIVE",
+        (TaskStatus::Init, _) => "CONNECT",
+        (TaskStatus::Searching { .. }, _) => "SEARCH",
+        (TaskStatus::Idle, _) => "IDLE",
+        (TaskStatus::Finish, _) => "CLOSE",
+        (TaskStatus::Error, _) => "ERROR",
+    }
+}
+
+// PeerCastはbytes/secで出している。ここでは接続毎に開始からの平均を足し合わせる
+fn bandwidth(connections: &ConnectionRegistry) -> (u64, u64) {
+    let now = Utc::now();
+    connections.list().iter().fold((0, 0), |(out, inn), conn| {
+        let secs = (now - conn.started_at).num_seconds().max(1) as u64;
+        (out + conn.bytes_out / secs, inn + conn.bytes_in / secs)
+    })
+}
+
+fn view_xml(
+    session_id: &GnuId,
+    uptime: i64,
+    channels: &[Channel],
+    connections: &ConnectionRegistry,
+) -> String {
+    let (bytes_out, bytes_in) = bandwidth(connections);
+    let total = connections.list().len();
+    let relays = connections.count(Protocol::PcpRelayOut, None);
+    let direct = connections.count(Protocol::HttpListener, None);
+
+    let mut xml = String::new();
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="utf-8" ?>"#);
+    let _ = writeln!(xml, r#"<peercast session="{session_id}">"#);
+    let _ = writeln!(xml, r#"<servent uptime="{uptime}" />"#);
+    let _ = writeln!(xml, r#"<bandwidth out="{bytes_out}" in="{bytes_in}" />"#);
+    let _ = writeln!(
+        xml,
+        r#"<connections total="{total}" relays="{relays}" direct="{direct}" />"#
+    );
+
+    let _ = writeln!(xml, r#"<channels_relayed total="{}">"#, channels.len());
+    for ch in channels {
+        let info = ch.info().unwrap_or_default();
+        let track = ch.track().unwrap_or_default();
+        let age = (Utc::now() - ch.created_at()).num_seconds();
+        let _ = writeln!(
+            xml,
+            r#"<channel name="{}" id="{}" bitrate="{}" type="{}" genre="{}" desc="{}" url="{}" uptime="{age}" comment="{}" skips="0" age="{age}" bcflags="0">"#,
+            encode_double_quoted_attribute(&info.name),
+            ch.id(),
+            info.bitrate,
+            encode_double_quoted_attribute(&info.typ),
+            encode_double_quoted_attribute(&info.genre),
+            encode_double_quoted_attribute(&info.desc),
+            encode_double_quoted_attribute(&info.url),
+            encode_double_quoted_attribute(&info.comment),
+        );
+        let listeners = connections.count(Protocol::HttpListener, Some(&ch.id()));
+        let relays = connections.count(Protocol::PcpRelayOut, Some(&ch.id()));
+        // ホストキャッシュは持っていないので、直下のリレーだけを数える
+        let _ = writeln!(
+            xml,
+            r#"<relay listeners="{listeners}" relays="{relays}" hosts="{relays}" status="{}" />"#,
+            status_str(ch)
+        );
+        let _ = writeln!(
+            xml,
+            r#"<track title="{}" artist="{}" album="{}" genre="{}" contact="{}" />"#,
+            encode_double_quoted_attribute(&track.title),
+            encode_double_quoted_attribute(&track.creator),
+            encode_double_quoted_attribute(&track.album),
+            encode_double_quoted_attribute(&track.genre),
+            encode_double_quoted_attribute(&track.url),
+        );
+        let _ = writeln!(xml, "</channel>");
+    }
+    let _ = writeln!(xml, "</channels_relayed>");
+
+    let _ = writeln!(xml, r#"<channels_found total="0">"#);
+    let _ = writeln!(xml, "</channels_found>");
+    let _ = writeln!(xml, "<host_cache>");
+    let _ = writeln!(xml, "</host_cache>");
+    let _ = writeln!(xml, "</peercast>");
+    xml
+}
+
+fn channels_html(channels: &[Channel]) -> String {
+    let mut rows = String::new();
+    for ch in channels {
+        let info = ch.info().unwrap_or_default();
+        let id = ch.id();
+        let _ = writeln!(
+            rows,
+            r#"<tr><td><a href="/pls/{id}{ext}">{name}</a></td><td>{genre}</td><td>{status}</td><td><a href="/admin?cmd=bump&amp;id={id}">Bump</a> <a href="/admin?cmd=stop&amp;id={id}">Stop</a></td></tr>"#,
+            ext = info.extension(),
+            name = encode_text(&info.name),
+            genre = encode_text(&info.genre),
+            status = status_str(ch),
+        );
+    }
+    indoc::formatdoc! {r#"
+        <!DOCTYPE html>
+        <html>
+        <head><meta charset="utf-8"><title>PeerCast Relays</title></head>
+        <body>
+        <h1>Relays</h1>
+        <table>
+        <tr><th>Channel</th><th>Genre</th><th>Status</th><th></th></tr>
+        {rows}</table>
+        </body>
+        </html>
+    "#}
+}
+
+#[cfg(test)]
+mod t {
+    use crate::{
+        connection_registry::ConnectionLimits,
+        pcp::{ChannelInfo, ChannelManager, TrackInfo},
+    };
+
+    use super::*;
+
+    #[crate::test]
+    async fn test_view_xml() {
+        let session_id = GnuId::new();
+        let manager = ChannelManager::new(&session_id);
+        let mut info = ChannelInfo::new();
+        info.name = "<テスト> & \"ch\"".into();
+        info.typ = "FLV".into();
+        info.bitrate = 500;
+        let mut track = TrackInfo::new();
+        track.creator = "someone".into();
+        let ch = manager
+            .create(
+                GnuId::new(),
+                ChannelType::Broadcast,
+                Some(info),
+                Some(track),
+            )
+            .unwrap();
+
+        let connections = manager.connections();
+        let remote = "192.168.0.2:7144".parse().unwrap();
+        let limits = ConnectionLimits::default();
+        let _listener = connections
+            .try_register(
+                ConnectionId::new(),
+                Protocol::HttpListener,
+                remote,
+                ch.id(),
+                &limits,
+            )
+            .unwrap();
+        let relay = connections
+            .try_register(
+                ConnectionId::new(),
+                Protocol::PcpRelayOut,
+                remote,
+                ch.id(),
+                &limits,
+            )
+            .unwrap();
+        relay.add_bytes_out(1000);
+
+        let xml = view_xml(&session_id, 10, &[ch.clone()], &connections);
+        assert!(xml.contains(&format!(r#"<peercast session="{session_id}">"#)));
+        assert!(xml.contains(r#"<servent uptime="10" />"#));
+        assert!(xml.contains(r#"<bandwidth out="1000" in="0" />"#));
+        assert!(xml.contains(r#"<connections total="2" relays="1" direct="1" />"#));
+        assert!(xml.contains(r#"<channels_relayed total="1">"#));
+        assert!(xml.contains(r#"<relay listeners="1" relays="1" hosts="1""#));
+        assert!(xml.contains(&format!(
+            r#"<channel name="&lt;テスト&gt; &amp; &quot;ch&quot;" id="{}" bitrate="500" type="FLV""#,
+            ch.id()
+        )));
+        assert!(xml.contains(r#"status="IDLE""#));
+        assert!(xml.contains(r#"artist="someone""#));
+    }
+}
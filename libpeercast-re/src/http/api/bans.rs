@@ -0,0 +1,110 @@
+use axum::{
+    extract::{Path, State},
+    routing::{delete, get},
+    Json, Router,
+};
+use axum_core::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::{
+    ban::{parse_net, BanEntry},
+    http::AppState,
+};
+
+use super::expires_after;
+
+pub(super) struct BanSvc;
+
+impl BanSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/", get(list_bans).post(add_ban))
+            // CIDRの"/"は%2Fにエンコードする
+            .route("/{net}", delete(remove_ban))
+    }
+}
+
+async fn list_bans(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.ban_list.list())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AddBan {
+    /// "192.168.0.1" か "192.168.0.0/24"
+    net: String,
+    /// 秒数。expires_atと両方指定したらこちらが優先
+    expires_in: Option<u64>,
+    expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    comment: String,
+}
+
+async fn add_ban(State(state): State<AppState>, Json(req): Json<AddBan>) -> Response {
+    let net = match parse_net(&req.net) {
+        Ok(net) => net,
+        Err(e) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "errors": { "net": e.to_string() } })),
+            )
+                .into_response()
+        }
+    };
+    let expires_at = match req.expires_in {
+        Some(secs) => match expires_after(secs) {
+            Some(at) => Some(at),
+            None => {
+                return (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(json!({ "errors": { "expires_in": "too large" } })),
+                )
+                    .into_response()
+            }
+        },
+        None => req.expires_at,
+    };
+    let entry = BanEntry {
+        net,
+        expires_at,
+        comment: req.comment,
+    };
+    info!("ban added: {entry:?}");
+    state.ban_list.add(entry.clone());
+
+    match save(&state) {
+        Ok(()) => (StatusCode::CREATED, Json(entry)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+async fn remove_ban(State(state): State<AppState>, Path(net): Path<String>) -> StatusCode {
+    let Ok(net) = parse_net(&net) else {
+        return StatusCode::BAD_REQUEST;
+    };
+    if !state.ban_list.remove(&net) {
+        return StatusCode::NOT_FOUND;
+    }
+    info!("ban removed: {net}");
+
+    match save(&state) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(status) => status,
+    }
+}
+
+// 期限切れの分はここで消える
+fn save(state: &AppState) -> Result<(), StatusCode> {
+    let mut config = state.config();
+    config.ban_list = state.ban_list.entries();
+    if let Err(e) = config.save_file(&state.config_path) {
+        error!("failed to save config {:?}: {e}", state.config_path);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    *state.config.write().unwrap() = config;
+    Ok(())
+}
@@ -51,7 +51,9 @@ impl ChannelsSvc {
 
     async fn create(
         State(AppState {
-            channel_manager, ..
+            channel_manager,
+            config,
+            ..
         }): State<AppState>,
         extract::Json(info): extract::Json<ReqCreateChannel>,
     ) -> impl IntoResponse {
@@ -59,8 +61,17 @@ impl ChannelsSvc {
         let ch_type = ChannelType::Broadcast;
         let channel_info = ChannelInfo::from(info);
 
+        // 同じ名前・ジャンルなら配信し直しても同じIDになる
+        let Some(broadcast_id) = config.read().unwrap().broadcast_id else {
+            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
+        };
+        let channel_id = broadcast_id.channel_id(
+            &channel_info.name,
+            &channel_info.genre,
+            channel_info.bitrate,
+        );
         let Some(ch) = channel_manager.create(
-            GnuId::new(),
+            channel_id,
             ch_type,
             channel_info.into(),
             TrackInfo::default().into(),
@@ -1,7 +1,18 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
 use axum::{extract::State, routing::get, Json, Router};
-use axum_core::response::IntoResponse;
+use axum_core::response::{IntoResponse, Response};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{error, info};
 
-use crate::http::AppState;
+use crate::{
+    ban::parse_net,
+    bandwidth::UploadLimitConfig,
+    config::{Config, ConfigAddress, ConfigPassword, ConfigTrait},
+    http::AppState,
+};
 
 pub(super) struct ConfigSvc;
 
@@ -11,9 +22,272 @@ impl ConfigSvc {
     }
 }
 
+/// 設定されているかどうかだけが分かるように置き換える
+const REDACTED: &str = "********";
+
 async fn get_config(State(state): State<AppState>) -> impl IntoResponse {
-    state.config.to_string()
+    redact(state.config()).to_string()
+}
+
+// パスワードのハッシュと視聴トークンの署名鍵は返さない
+fn redact(mut config: Config) -> Config {
+    if config.password.is_some() {
+        config.password = Some(ConfigPassword::Hashed(REDACTED.into()));
+    }
+    if config.token_secret.is_some() {
+        config.token_secret = Some(REDACTED.into());
+    }
+    config
+}
+
+/// 省略したフィールドは変更しない
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigUpdate {
+    server_address: Option<String>,
+    server_port: Option<u16>,
+    rtmp_port: Option<u16>,
+    local_address: Option<Vec<String>>,
+    trusted_proxies: Option<Vec<String>>,
+    /// 空文字でユーザー名無し
+    username: Option<String>,
+    /// 平文で受け取って保存時にハッシュ化する
+    password: Option<String>,
+    max_relays: Option<u32>,
+    max_listeners: Option<u32>,
+    max_total_relays: Option<u32>,
+    max_total_listeners: Option<u32>,
+    max_connections_per_ip: Option<u32>,
+    max_upload_kbps: Option<u32>,
+    max_channel_upload_kbps: Option<u32>,
+    max_connection_upload_kbps: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigUpdated {
+    /// 反映に再起動が必要な項目
+    restart_required: Vec<&'static str>,
+}
+
+type FieldErrors = BTreeMap<String, String>;
+
+// 変更後の設定と、再起動が必要な項目を返す
+fn apply_update(
+    current: &Config,
+    update: ConfigUpdate,
+) -> Result<(Config, Vec<&'static str>), FieldErrors> {
+    let mut config = current.clone();
+    let mut errors = FieldErrors::new();
+
+    if let Some(addr) = update.server_address {
+        match addr.as_str() {
+            "" => config.server_address = ConfigAddress::NoConfig("0.0.0.0".parse().unwrap()),
+            addr => match addr.parse::<IpAddr>() {
+                Ok(ip) => config.server_address = ConfigAddress::Config(ip),
+                Err(e) => {
+                    errors.insert("server_address".into(), e.to_string());
+                }
+            },
+        }
+    }
+    if let Some(port) = update.server_port {
+        config.server_port = port;
+    }
+    if let Some(port) = update.rtmp_port {
+        config.rtmp_port = port;
+    }
+    if config.server_port == 0 {
+        errors.insert("server_port".into(), "port must not be 0".into());
+    }
+    if config.rtmp_port == 0 {
+        errors.insert("rtmp_port".into(), "port must not be 0".into());
+    }
+    if config.server_port == config.rtmp_port {
+        errors.insert(
+            "rtmp_port".into(),
+            "rtmp_port must differ from server_port".into(),
+        );
+    }
+
+    if let Some(nets) = update.local_address {
+        let mut parsed = Vec::with_capacity(nets.len());
+        for (i, net) in nets.iter().enumerate() {
+            match parse_net(net) {
+                Ok(net) => parsed.push(net),
+                Err(e) => {
+                    errors.insert(format!("local_address[{i}]"), e.to_string());
+                }
+            }
+        }
+        config.local_address = parsed;
+    }
+    if let Some(nets) = update.trusted_proxies {
+        let mut parsed = Vec::with_capacity(nets.len());
+        for (i, net) in nets.iter().enumerate() {
+            match parse_net(net) {
+                Ok(net) => parsed.push(net),
+                Err(e) => {
+                    errors.insert(format!("trusted_proxies[{i}]"), e.to_string());
+                }
+            }
+        }
+        config.trusted_proxies = parsed;
+    }
+
+    if let Some(username) = update.username {
+        config.username = (!username.is_empty()).then_some(username);
+    }
+    if let Some(password) = update.password {
+        if password.is_empty() {
+            errors.insert("password".into(), "password must not be empty".into());
+        } else if errors.is_empty() {
+            // ハッシュ化は重いので他にエラーが無い時だけ
+            config.set_password(&password);
+        }
+    }
+
+    if let Some(n) = update.max_relays {
+        config.max_relays = n;
+    }
+    if let Some(n) = update.max_listeners {
+        config.max_listeners = n;
+    }
+    if let Some(n) = update.max_total_relays {
+        config.max_total_relays = n;
+    }
+    if let Some(n) = update.max_total_listeners {
+        config.max_total_listeners = n;
+    }
+    if let Some(n) = update.max_connections_per_ip {
+        config.max_connections_per_ip = n;
+    }
+    if let Some(n) = update.max_upload_kbps {
+        config.max_upload_kbps = n;
+    }
+    if let Some(n) = update.max_channel_upload_kbps {
+        config.max_channel_upload_kbps = n;
+    }
+    if let Some(n) = update.max_connection_upload_kbps {
+        config.max_connection_upload_kbps = n;
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut restart_required = vec![];
+    if config.server_address != current.server_address {
+        restart_required.push("server_address");
+    }
+    if config.server_port != current.server_port {
+        restart_required.push("server_port");
+    }
+    if config.rtmp_port != current.rtmp_port {
+        restart_required.push("rtmp_port");
+    }
+    Ok((config, restart_required))
+}
+
+async fn put_config(State(state): State<AppState>, Json(update): Json<ConfigUpdate>) -> Response {
+    let current = state.config();
+    // パスワードのハッシュ化(pbkdf2)はCPUを使うのでブロッキングスレッドで行う
+    let result = tokio::task::spawn_blocking(move || apply_update(&current, update)).await;
+    let (config, restart_required) = match result {
+        Ok(Ok(r)) => r,
+        Ok(Err(errors)) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "errors": errors })),
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("failed to apply config update: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(e) = config.save_file(&state.config_path) {
+        error!("failed to save config {:?}: {e}", state.config_path);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    info!("config updated. restart_required={restart_required:?}");
+
+    // 許可ネットワークや信頼するプロキシ、上限はリクエスト毎に読むのでここで反映される
+    state
+        .upload_limiter
+        .reconfigure(UploadLimitConfig::from_config(&config));
+    *state.config.write().unwrap() = config;
+    Json(ConfigUpdated { restart_required }).into_response()
 }
-async fn put_config(State(state): State<AppState>) -> impl IntoResponse {
-    ()
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_apply_update() {
+        let current = Config::default();
+
+        let update = ConfigUpdate {
+            local_address: Some(vec!["192.168.0.0/16".into(), "10.0.0.1".into()]),
+            trusted_proxies: Some(vec!["172.16.0.0/12".into(), "::1".into()]),
+            max_listeners: Some(5),
+            server_port: Some(7144),
+            ..Default::default()
+        };
+        let (config, restart_required) = apply_update(&current, update).unwrap();
+        assert_eq!(config.local_address.len(), 2);
+        // IPだけならそのアドレスだけのネットワークになる
+        assert_eq!(
+            config.local_address[1],
+            "10.0.0.1/32".parse::<ipnet::IpNet>().unwrap()
+        );
+        assert_eq!(config.trusted_proxies.len(), 2);
+        assert_eq!(config.max_listeners, 5);
+        assert_eq!(restart_required, vec!["server_port"]);
+
+        let update = ConfigUpdate {
+            server_address: Some("localhost".into()),
+            rtmp_port: Some(current.server_port),
+            local_address: Some(vec!["127.0.0.1/8".into(), "192.168.0.256".into()]),
+            ..Default::default()
+        };
+        let errors = apply_update(&current, update).unwrap_err();
+        assert_eq!(
+            errors.keys().collect::<Vec<_>>(),
+            vec!["local_address[1]", "rtmp_port", "server_address"]
+        );
+
+        let update = ConfigUpdate {
+            username: Some("".into()),
+            ..Default::default()
+        };
+        let (config, restart_required) = apply_update(&current, update).unwrap();
+        assert_eq!(config.username, None);
+        assert!(restart_required.is_empty());
+
+        // 上り帯域の制限は再起動せずに反映される
+        let update = ConfigUpdate {
+            max_upload_kbps: Some(1000),
+            ..Default::default()
+        };
+        let (config, restart_required) = apply_update(&current, update).unwrap();
+        assert_eq!(config.max_upload_kbps, 1000);
+        assert!(restart_required.is_empty());
+    }
+
+    #[test]
+    fn test_redact() {
+        let mut config = Config::default();
+        assert!(!redact(config.clone()).to_string().contains(REDACTED));
+
+        config.set_password("password");
+        config.token_secret = Some("0123456789abcdef".into());
+        let hash: String = config.password.as_ref().unwrap().into();
+        let s = redact(config).to_string();
+        assert!(!s.contains(&hash));
+        assert!(!s.contains("0123456789abcdef"));
+        assert_eq!(s.matches(REDACTED).count(), 2);
+    }
 }
@@ -0,0 +1,57 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use axum_core::response::IntoResponse;
+use http::StatusCode;
+use serde::Deserialize;
+
+use crate::{
+    connection_registry::ConnectionInfo, http::AppState, pcp::error_code::QuitCode, ConnectionId,
+};
+
+pub(super) struct ConnectionsSvc;
+
+impl ConnectionsSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/", get(list_connections))
+            .route("/{id}", get(get_connection).delete(disconnect))
+    }
+}
+
+async fn list_connections(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.connections.list())
+}
+
+async fn get_connection(
+    Path(id): Path<i32>,
+    State(state): State<AppState>,
+) -> Result<Json<ConnectionInfo>, StatusCode> {
+    match state.connections.get(&ConnectionId::from(id)) {
+        Some(info) => Ok(Json(info)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DisconnectQuery {
+    /// PCPで送るquitコード。省略時はUSER_SHUTDOWN
+    quit_code: Option<u32>,
+}
+
+async fn disconnect(
+    Path(id): Path<i32>,
+    Query(query): Query<DisconnectQuery>,
+    State(state): State<AppState>,
+) -> StatusCode {
+    let quit_code = query.quit_code.unwrap_or(QuitCode::USER_SHUTDOWN);
+    match state
+        .connections
+        .disconnect(&ConnectionId::from(id), quit_code)
+    {
+        true => StatusCode::ACCEPTED,
+        false => StatusCode::NOT_FOUND,
+    }
+}
@@ -0,0 +1,121 @@
+use std::{convert::Infallible, str::FromStr};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    response::sse::{self, KeepAlive, Sse},
+    routing::get,
+    Router,
+};
+use axum_core::response::IntoResponse;
+use futures_util::{stream, Stream};
+use http::StatusCode;
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, warn};
+
+use crate::{event::Event, http::AppState, pcp::GnuId};
+
+pub(super) struct EventsSvc;
+
+impl EventsSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/", get(sse_events))
+            .route("/ws", get(ws_events))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EventsQuery {
+    /// 指定されたチャンネルのイベントだけを流す
+    channel: Option<String>,
+}
+
+impl EventsQuery {
+    fn channel_id(&self) -> Result<Option<GnuId>, StatusCode> {
+        match &self.channel {
+            None => Ok(None),
+            Some(id) => GnuId::from_str(id)
+                .map(Some)
+                .map_err(|_| StatusCode::BAD_REQUEST),
+        }
+    }
+}
+
+// 遅れて取りこぼした分は飛ばす。送信側が無くなったらNone
+async fn next_event(
+    rx: &mut broadcast::Receiver<Event>,
+    channel_id: Option<GnuId>,
+) -> Option<Event> {
+    loop {
+        match rx.recv().await {
+            Ok(ev) if channel_id.is_none_or(|id| ev.channel_id() == id) => return Some(ev),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(n)) => {
+                warn!("event subscriber lagged, skipped {n} events");
+                continue;
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn sse_events(
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, StatusCode> {
+    let channel_id = query.channel_id()?;
+    let rx = state.channel_manager.events().subscribe();
+
+    let stream = stream::unfold(rx, move |mut rx| async move {
+        let ev = next_event(&mut rx, channel_id).await?;
+        let sse_event = sse::Event::default()
+            .event(ev.name())
+            .json_data(&ev)
+            .unwrap_or_default();
+        Some((Ok(sse_event), rx))
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+async fn ws_events(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(query): Query<EventsQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let channel_id = query.channel_id()?;
+    let rx = state.channel_manager.events().subscribe();
+    Ok(ws.on_upgrade(move |socket| send_events(socket, rx, channel_id)))
+}
+
+async fn send_events(
+    mut socket: WebSocket,
+    mut rx: broadcast::Receiver<Event>,
+    channel_id: Option<GnuId>,
+) {
+    loop {
+        tokio::select! {
+            ev = next_event(&mut rx, channel_id) => {
+                let Some(ev) = ev else {
+                    break;
+                };
+                let Ok(text) = serde_json::to_string(&ev) else {
+                    continue;
+                };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            msg = socket.recv() => match msg {
+                // クライアントからのメッセージは読み捨てる
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    debug!("websocket event subscriber closed");
+}
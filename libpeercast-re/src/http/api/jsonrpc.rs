@@ -0,0 +1,392 @@
+//! PeerCastStation互換の JSON-RPC API (/api/1)
+//!
+//! PeCa Toolsなど既存のツールから使えるように、PeerCastStationと同じメソッド名・
+//! キー名で返す。チャンネル以外の設定系メソッドは今のところ未対応
+//! See: https://github.com/kumaryu/peercaststation/wiki/JSON-RPC-API-%E3%83%A1%E3%83%A2
+
+use std::str::FromStr;
+
+use axum::{body::Bytes, extract::State, response::Response, Json};
+use axum_core::response::IntoResponse;
+use chrono::Utc;
+use http::StatusCode;
+use serde_json::{json, Map, Value};
+use tracing::debug;
+
+use crate::{
+    bandwidth::UploadLimiter,
+    config::Config,
+    connection_registry::{ChannelConnections, ConnectionLimits},
+    http::AppState,
+    pcp::{Channel, ChannelInfo, ChannelManager, ChannelType, GnuId, TaskStatus, TrackInfo},
+    PKG_AGENT, PKG_SERVANT_VERSION, PKG_SERVANT_VERSION_VP,
+};
+
+const JSONRPC_VERSION: &str = "2.0";
+const API_VERSION: &str = "1.0.0";
+
+// JSON-RPC 2.0の定義
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+// PeerCastStation独自
+const CHANNEL_NOT_FOUND: i32 = -1;
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i32,
+    message: &'static str,
+}
+
+impl RpcError {
+    const fn new(code: i32, message: &'static str) -> Self {
+        Self { code, message }
+    }
+
+    fn to_value(&self) -> Value {
+        json!({ "code": self.code, "message": self.message })
+    }
+}
+
+pub(super) async fn handle(State(state): State<AppState>, body: Bytes) -> Response {
+    let Ok(req) = serde_json::from_slice::<Value>(&body) else {
+        let err = RpcError::new(PARSE_ERROR, "Parse error");
+        return Json(error_response(Value::Null, &err)).into_response();
+    };
+
+    let config = state.config();
+    let rpc = Rpc {
+        channel_manager: &state.channel_manager,
+        config: &config,
+        upload_limiter: &state.upload_limiter,
+    };
+    let resp = match req {
+        // バッチ呼び出し
+        Value::Array(reqs) if !reqs.is_empty() => {
+            let resps = reqs
+                .into_iter()
+                .filter_map(|req| rpc.handle_request(req))
+                .collect::<Vec<_>>();
+            (!resps.is_empty()).then_some(Value::Array(resps))
+        }
+        req => rpc.handle_request(req),
+    };
+
+    match resp {
+        Some(resp) => Json(resp).into_response(),
+        // 通知だけの場合は何も返さない
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+fn error_response(id: Value, err: &RpcError) -> Value {
+    json!({
+        "jsonrpc": JSONRPC_VERSION,
+        "id": id,
+        "error": err.to_value(),
+    })
+}
+
+struct Rpc<'a> {
+    channel_manager: &'a ChannelManager,
+    config: &'a Config,
+    upload_limiter: &'a UploadLimiter,
+}
+
+impl Rpc<'_> {
+    /// idが無い(通知)場合はNone
+    fn handle_request(&self, req: Value) -> Option<Value> {
+        let Value::Object(mut req) = req else {
+            let err = RpcError::new(INVALID_REQUEST, "Invalid Request");
+            return Some(error_response(Value::Null, &err));
+        };
+        let id = req.remove("id");
+        let method = match req.get("method") {
+            Some(Value::String(m)) if req.get("jsonrpc") == Some(&json!(JSONRPC_VERSION)) => m,
+            _ => {
+                let err = RpcError::new(INVALID_REQUEST, "Invalid Request");
+                return Some(error_response(id.unwrap_or_default(), &err));
+            }
+        };
+        let params = req.get("params").cloned().unwrap_or_default();
+        debug!(?id, method, ?params);
+
+        let result = self.call(method, &params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({
+                "jsonrpc": JSONRPC_VERSION,
+                "id": id,
+                "result": result,
+            }),
+            Err(err) => error_response(id, &err),
+        })
+    }
+
+    fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "getVersionInfo" => Ok(json!({
+                "agentName": PKG_AGENT,
+                "apiVersion": API_VERSION,
+                "jsonrpc": JSONRPC_VERSION,
+            })),
+            "getChannels" => {
+                let channels = self
+                    .channel_manager
+                    .map_collect(|(_, ch)| self.channel_value(ch));
+                Ok(Value::Array(channels))
+            }
+            "getChannelInfo" => {
+                let ch = self.channel(params)?;
+                Ok(json!({
+                    "info": info_value(&ch.info().unwrap_or_default()),
+                    "track": track_value(&ch.track().unwrap_or_default()),
+                    "yellowPages": [],
+                }))
+            }
+            "getChannelStatus" => Ok(self.status_value(&self.channel(params)?)),
+            "getChannelRelayTree" => Ok(json!([self.relay_node(&self.channel(params)?)])),
+            "setChannelInfo" => {
+                let ch = self.channel(params)?;
+                if let Some(Value::Object(info)) = param(params, 1, "info") {
+                    ch.set_info(merge_info(ch.info().unwrap_or_default(), info));
+                }
+                if let Some(Value::Object(track)) = param(params, 2, "track") {
+                    ch.set_track(merge_track(ch.track().unwrap_or_default(), track));
+                }
+                Ok(Value::Null)
+            }
+            "stopChannel" => {
+                let ch = self.channel(params)?;
+                ch.stop();
+                self.channel_manager.delete(&ch.id());
+                Ok(Value::Null)
+            }
+            "bumpChannel" => {
+                self.channel(params)?.retry();
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, "Method not found")),
+        }
+    }
+
+    fn channel(&self, params: &Value) -> Result<Channel, RpcError> {
+        let Some(Value::String(id)) = param(params, 0, "channelId") else {
+            return Err(RpcError::new(INVALID_PARAMS, "Invalid params"));
+        };
+        let id =
+            GnuId::from_str(id).map_err(|_| RpcError::new(INVALID_PARAMS, "Invalid params"))?;
+        self.channel_manager
+            .get(&id)
+            .ok_or(RpcError::new(CHANNEL_NOT_FOUND, "Channel not found"))
+    }
+
+    fn channel_value(&self, ch: &Channel) -> Value {
+        json!({
+            "channelId": ch.id(),
+            "status": self.status_value(ch),
+            "info": info_value(&ch.info().unwrap_or_default()),
+            "track": track_value(&ch.track().unwrap_or_default()),
+            "yellowPages": [],
+        })
+    }
+
+    fn connections(&self, ch: &Channel) -> ChannelConnections {
+        let limits = ConnectionLimits::from_config(self.config);
+        self.channel_manager
+            .connections()
+            .channel_connections(&ch.id(), &limits)
+    }
+
+    fn status_value(&self, ch: &Channel) -> Value {
+        let status = ch.status();
+        let uptime = (Utc::now() - ch.created_at()).num_seconds();
+        let conns = self.connections(ch);
+        json!({
+            "status": status_name(&status),
+            "source": "",
+            "uptime": uptime,
+            "localRelays": conns.relays,
+            "localDirects": conns.listeners,
+            // 下流から先は分からないので自分の分だけ
+            "totalRelays": conns.relays,
+            "totalDirects": conns.listeners,
+            "isBroadcasting": matches!(ch.channel_type(), ChannelType::Broadcast),
+            // 上り帯域を使い切っている間はリレーを受けない
+            "isRelayFull": conns.is_relay_full() || self.upload_limiter.is_relay_full(&ch.id()),
+            "isDirectFull": conns.is_listener_full(),
+            "isReceiving": status == TaskStatus::Receiving,
+        })
+    }
+
+    // 下流の接続はまだ管理していないので自分だけを返す
+    fn relay_node(&self, ch: &Channel) -> Value {
+        let status = ch.status();
+        let conns = self.connections(ch);
+        json!({
+            "sessionId": self.channel_manager.session_id(),
+            "address": self.config.server_address.to_ipaddr().to_string(),
+            "port": self.config.server_port,
+            "isFirewalled": false,
+            "localRelays": conns.relays,
+            "localDirects": conns.listeners,
+            "isTracker": matches!(ch.channel_type(), ChannelType::Broadcast),
+            "isRelayFull": conns.is_relay_full() || self.upload_limiter.is_relay_full(&ch.id()),
+            "isDirectFull": conns.is_listener_full(),
+            "isReceiving": status == TaskStatus::Receiving,
+            "isControlFull": false,
+            "version": PKG_SERVANT_VERSION,
+            "versionVP": PKG_SERVANT_VERSION_VP,
+            "children": [],
+        })
+    }
+}
+
+/// 配列で渡された場合は位置、オブジェクトの場合は名前で取り出す
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Option<&'a Value> {
+    match params {
+        Value::Array(a) => a.get(index),
+        Value::Object(o) => o.get(name),
+        _ => None,
+    }
+}
+
+// PeerCastStationのSourceStreamStatusに合わせる
+fn status_name(status: &TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Init => "Connecting",
+        TaskStatus::Searching { .. } => "Searching",
+        TaskStatus::Receiving => "Receiving",
+        TaskStatus::Idle => "Idle",
+        TaskStatus::Finish => "Finished",
+        TaskStatus::Error => "Error",
+    }
+}
+
+fn info_value(info: &ChannelInfo) -> Value {
+    json!({
+        "name": info.name,
+        "url": info.url,
+        "genre": info.genre,
+        "desc": info.desc,
+        "comment": info.comment,
+        "bitrate": info.bitrate,
+        "contentType": info.typ,
+        "mimeType": info.mime_type(),
+    })
+}
+
+fn track_value(track: &TrackInfo) -> Value {
+    json!({
+        "name": track.title,
+        "genre": track.genre,
+        "album": track.album,
+        "creator": track.creator,
+        "url": track.url,
+    })
+}
+
+fn merge_info(mut info: ChannelInfo, patch: &Map<String, Value>) -> ChannelInfo {
+    let get = |key: &str| patch.get(key).and_then(Value::as_str).map(String::from);
+    if let Some(v) = get("name") {
+        info.name = v;
+    }
+    if let Some(v) = get("url") {
+        info.url = v;
+    }
+    if let Some(v) = get("genre") {
+        info.genre = v;
+    }
+    if let Some(v) = get("desc") {
+        info.desc = v;
+    }
+    if let Some(v) = get("comment") {
+        info.comment = v;
+    }
+    info
+}
+
+fn merge_track(mut track: TrackInfo, patch: &Map<String, Value>) -> TrackInfo {
+    let get = |key: &str| patch.get(key).and_then(Value::as_str).map(String::from);
+    if let Some(v) = get("name") {
+        track.title = v;
+    }
+    if let Some(v) = get("genre") {
+        track.genre = v;
+    }
+    if let Some(v) = get("album") {
+        track.album = v;
+    }
+    if let Some(v) = get("creator") {
+        track.creator = v;
+    }
+    if let Some(v) = get("url") {
+        track.url = v;
+    }
+    track
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn request(rpc: &Rpc, method: &str, params: Value) -> Value {
+        let req = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        rpc.handle_request(req).unwrap()
+    }
+
+    #[crate::test]
+    async fn test_jsonrpc() {
+        let channel_manager = ChannelManager::new(&GnuId::new());
+        let config = Config::default();
+        let upload_limiter = UploadLimiter::new(Default::default());
+        let rpc = Rpc {
+            channel_manager: &channel_manager,
+            config: &config,
+            upload_limiter: &upload_limiter,
+        };
+
+        let id = GnuId::new();
+        let mut info = ChannelInfo::new();
+        info.name = "テスト".into();
+        channel_manager.create(id, ChannelType::Broadcast, Some(info), None);
+
+        let resp = request(&rpc, "getVersionInfo", Value::Null);
+        assert_eq!(resp["result"]["agentName"], PKG_AGENT);
+
+        let resp = request(&rpc, "getChannels", Value::Null);
+        assert_eq!(resp["result"][0]["channelId"], String::from(&id));
+        assert_eq!(resp["result"][0]["info"]["name"], "テスト");
+        assert_eq!(resp["result"][0]["status"]["isBroadcasting"], true);
+
+        // 位置引数と名前付き引数
+        let resp = request(
+            &rpc,
+            "setChannelInfo",
+            json!([String::from(&id), { "genre": "game" }, { "name": "song" }]),
+        );
+        assert_eq!(resp["result"], Value::Null);
+        let resp = request(
+            &rpc,
+            "getChannelInfo",
+            json!({ "channelId": String::from(&id) }),
+        );
+        assert_eq!(resp["result"]["info"]["genre"], "game");
+        assert_eq!(resp["result"]["track"]["name"], "song");
+
+        let resp = request(&rpc, "stopChannel", json!([String::from(&id)]));
+        assert_eq!(resp["result"], Value::Null);
+        let resp = request(&rpc, "getChannelStatus", json!([String::from(&id)]));
+        assert_eq!(resp["error"]["code"], CHANNEL_NOT_FOUND);
+
+        let resp = request(&rpc, "noSuchMethod", Value::Null);
+        assert_eq!(resp["error"]["code"], METHOD_NOT_FOUND);
+        let resp = request(&rpc, "getChannelInfo", json!(["XYZ"]));
+        assert_eq!(resp["error"]["code"], INVALID_PARAMS);
+
+        // 通知には応答しない
+        let notify = json!({ "jsonrpc": "2.0", "method": "getVersionInfo" });
+        assert_eq!(rpc.handle_request(notify), None);
+    }
+}
@@ -0,0 +1,53 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, State},
+    routing::post,
+    Json, Router,
+};
+use axum_core::response::{IntoResponse, Response};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+use crate::{http::AppState, pcp::GnuId};
+
+pub(super) struct MessagesSvc;
+
+impl MessagesSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new().route("/{id}", post(send_message))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendMessage {
+    text: String,
+}
+
+/// チャンネルのツリーにメッセージを流す。届いたメッセージは/api/eventsのchannel_messageで見る
+async fn send_message(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<SendMessage>,
+) -> Response {
+    let Ok(id) = GnuId::from_str(&id) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let Some(ch) = state.channel_manager.get(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if req.text.trim().is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": { "text": "must not be empty" } })),
+        )
+            .into_response();
+    }
+
+    info!("send message to CID:{}", ch.id());
+    ch.send_message(req.text);
+    StatusCode::ACCEPTED.into_response()
+}
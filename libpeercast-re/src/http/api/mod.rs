@@ -2,10 +2,11 @@ use std::time::SystemTime;
 
 use axum::{
     extract::{Query, State},
-    routing::get,
+    routing::{get, post},
     Json, Router,
 };
 use axum_core::response::IntoResponse;
+use chrono::{DateTime, TimeDelta, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
@@ -13,7 +14,15 @@ use super::AppState;
 use crate::pcp::GnuId;
 
 // mod channels;
+mod bans;
 mod config;
+mod connections;
+mod events;
+mod jsonrpc;
+mod messages;
+mod private;
+mod recordings;
+mod yp;
 
 ////////////////////////////////////////////////////////////////////////////////
 // Api
@@ -24,7 +33,16 @@ impl Api {
     pub(super) fn new() -> Router<AppState> {
         Router::new()
             // .nest("/channels", channels::ChannelsSvc::new())
+            .nest("/bans", bans::BanSvc::new())
             .nest("/config", config::ConfigSvc::new())
+            .nest("/connections", connections::ConnectionsSvc::new())
+            .nest("/events", events::EventsSvc::new())
+            .nest("/messages", messages::MessagesSvc::new())
+            .nest("/private", private::PrivateSvc::new())
+            .nest("/recordings", recordings::RecordingsSvc::new())
+            .nest("/yp", yp::YpSvc::new())
+            // PeerCastStation互換
+            .route("/1", post(jsonrpc::handle))
             .route("/ping", get(Self::pong))
             .route("/info", get(Self::info))
     }
@@ -38,11 +56,17 @@ impl Api {
     async fn info(State(app): State<AppState>) -> impl IntoResponse {
         Json(json!({
             "hostname": "localhost",
-            "port": app.config.server_port,
+            "port": app.config().server_port,
         }))
     }
 }
 
+/// 今からsecs秒後。表せない大きさならNone
+fn expires_after(secs: u64) -> Option<DateTime<Utc>> {
+    let secs = i64::try_from(secs).ok()?;
+    Utc::now().checked_add_signed(TimeDelta::try_seconds(secs)?)
+}
+
 #[derive(Deserialize)]
 struct PingQuery {
     name: u32,
@@ -52,3 +76,16 @@ struct PingQuery {
 struct RespPong {
     pong: u32,
 }
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_expires_after() {
+        let at = expires_after(60).unwrap();
+        assert!(at > Utc::now());
+        assert!(expires_after(u64::MAX).is_none());
+        assert!(expires_after(i64::MAX as u64).is_none());
+    }
+}
@@ -0,0 +1,145 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, State},
+    routing::{get, post, put},
+    Json, Router,
+};
+use axum_core::response::{IntoResponse, Response};
+use http::StatusCode;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::{
+    http::AppState,
+    listener_token::{self, TokenSigner},
+    pcp::{Channel, ChannelType, GnuId},
+};
+
+use super::expires_after;
+
+// トークンの有効期限の既定値(sec)
+const DEFAULT_EXPIRES_IN: u64 = 24 * 60 * 60;
+
+pub(super) struct PrivateSvc;
+
+impl PrivateSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/", get(list_private))
+            .route("/{id}", put(set_private).delete(unset_private))
+            .route("/{id}/tokens", post(issue_token))
+    }
+}
+
+fn find_channel(state: &AppState, id: &str) -> Result<Channel, StatusCode> {
+    let id = GnuId::from_str(id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    state.channel_manager.get(&id).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// 限定公開のチャンネルID
+async fn list_private(State(state): State<AppState>) -> impl IntoResponse {
+    let ids = state
+        .channel_manager
+        .map_collect(|(id, ch)| ch.is_private().then_some(*id))
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    Json(ids)
+}
+
+async fn set_private(State(state): State<AppState>, Path(id): Path<String>) -> StatusCode {
+    match find_channel(&state, &id) {
+        Ok(ch) => {
+            info!("channel CID:{} is now private", ch.id());
+            ch.set_private(true);
+            StatusCode::NO_CONTENT
+        }
+        Err(status) => status,
+    }
+}
+
+async fn unset_private(State(state): State<AppState>, Path(id): Path<String>) -> StatusCode {
+    match find_channel(&state, &id) {
+        // リレーは上流が限定公開のままなので外せない
+        Ok(ch) if ch.access_token().is_some() => StatusCode::CONFLICT,
+        Ok(ch) => {
+            info!("channel CID:{} is now public", ch.id());
+            ch.set_private(false);
+            StatusCode::NO_CONTENT
+        }
+        Err(status) => status,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IssueToken {
+    /// 秒数
+    expires_in: Option<u64>,
+}
+
+async fn issue_token(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(req): Json<IssueToken>,
+) -> Response {
+    let ch = match find_channel(&state, &id) {
+        Ok(ch) => ch,
+        Err(status) => return status.into_response(),
+    };
+    // 鍵を持っているのは配信しているPCだけ
+    if !matches!(ch.channel_type(), ChannelType::Broadcast) {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": { "id": "not a broadcast channel" } })),
+        )
+            .into_response();
+    }
+
+    let Some(expires_at) = expires_after(req.expires_in.unwrap_or(DEFAULT_EXPIRES_IN)) else {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": { "expires_in": "too large" } })),
+        )
+            .into_response();
+    };
+    let secret = match secret(&state) {
+        Ok(secret) => secret,
+        Err(status) => return status.into_response(),
+    };
+    let token = TokenSigner::new(&secret).issue(ch.id(), expires_at);
+    // 発行したら限定公開になる
+    ch.set_private(true);
+    info!("issued token for CID:{} expires at {expires_at}", ch.id());
+
+    let auth = urlencoding::encode(&token).into_owned();
+    let extension = ch.info().unwrap_or_default().extension();
+    (
+        StatusCode::CREATED,
+        Json(json!({
+            "token": token,
+            "expires_at": expires_at,
+            "playlist": format!("/pls/{}.m3u?auth={auth}", ch.id()),
+            "stream": format!("/stream/{}{extension}?auth={auth}", ch.id()),
+        })),
+    )
+        .into_response()
+}
+
+// 無ければ作って設定ファイルに保存する
+fn secret(state: &AppState) -> Result<String, StatusCode> {
+    let mut config = state.config();
+    if let Some(secret) = &config.token_secret {
+        return Ok(secret.clone());
+    }
+    let secret = listener_token::generate_secret();
+    config.token_secret = Some(secret.clone());
+    if let Err(e) = config.save_file(&state.config_path) {
+        error!("failed to save config {:?}: {e}", state.config_path);
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    *state.config.write().unwrap() = config;
+    Ok(secret)
+}
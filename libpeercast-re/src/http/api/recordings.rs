@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use axum_core::response::IntoResponse;
+use http::StatusCode;
+use tracing::error;
+
+use crate::{error::RecordError, http::AppState, pcp::GnuId, recorder::RecordingStatus};
+
+pub(super) struct RecordingsSvc;
+
+impl RecordingsSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new().route("/", get(list_recordings)).route(
+            "/{id}",
+            get(get_recording)
+                .post(start_recording)
+                .delete(stop_recording),
+        )
+    }
+}
+
+async fn list_recordings(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.recorder_manager.list())
+}
+
+async fn get_recording(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<RecordingStatus>, StatusCode> {
+    let id = GnuId::from_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    match state.recorder_manager.status(&id) {
+        Some(status) => Ok(Json(status)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn start_recording(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<RecordingStatus>, StatusCode> {
+    let id = GnuId::from_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let Some(channel) = state.channel_manager.get(&id) else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    state
+        .recorder_manager
+        .start(&channel)
+        .map(Json)
+        .map_err(record_error_status)
+}
+
+async fn stop_recording(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<RecordingStatus>, StatusCode> {
+    let id = GnuId::from_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .recorder_manager
+        .stop(&id)
+        .map(Json)
+        .map_err(record_error_status)
+}
+
+fn record_error_status(e: RecordError) -> StatusCode {
+    match e {
+        RecordError::AlreadyRecording => StatusCode::CONFLICT,
+        RecordError::NotRecording => StatusCode::NOT_FOUND,
+        RecordError::Io(e) => {
+            error!("recording failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
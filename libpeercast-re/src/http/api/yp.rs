@@ -0,0 +1,94 @@
+use std::str::FromStr;
+
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use axum_core::response::IntoResponse;
+use http::StatusCode;
+use serde_json::json;
+use tracing::info;
+
+use crate::{
+    http::AppState,
+    pcp::{ChannelType, GnuId, RelayTaskConfig, SourceTaskConfig, TaskStatus},
+    yp::YpFilter,
+    ConnectionId,
+};
+
+pub(super) struct YpSvc;
+
+impl YpSvc {
+    pub(super) fn new() -> Router<AppState> {
+        Router::new()
+            .route("/channels", get(list_channels))
+            .route("/channels/{id}", get(get_channel))
+            .route("/channels/{id}/play", post(play_channel))
+    }
+}
+
+async fn list_channels(
+    State(state): State<AppState>,
+    Query(filter): Query<YpFilter>,
+) -> impl IntoResponse {
+    Json(json!({
+        "updated_at": state.yp_browser.updated_at(),
+        "channels": state.yp_browser.list(&filter),
+    }))
+}
+
+async fn get_channel(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = GnuId::from_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    state
+        .yp_browser
+        .get(&id)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// YPに載っているtipを使ってリレーを始め、再生用のURLを返す
+async fn play_channel(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let id = GnuId::from_str(&id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let yp_channel = state.yp_browser.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    // tipが分からないと繋ぎに行けない
+    let tip = yp_channel
+        .tracker_addr
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+
+    let ch = state.channel_manager.create_or_get(
+        id,
+        ChannelType::Relay,
+        Some(yp_channel.channel_info.clone()),
+        Some(yp_channel.track_info.clone()),
+    );
+    match ch.status() {
+        TaskStatus::Receiving | TaskStatus::Searching { .. } | TaskStatus::Init => {}
+        _ => {
+            info!("play channel CID:{id} tip:{tip}");
+            let task_config = SourceTaskConfig::Relay(RelayTaskConfig {
+                addr: tip,
+                self_addr: None,
+                token: None,
+            });
+            if !ch.connect(ConnectionId::new(), task_config) {
+                // 既にタスクがある場合は繋ぎ直す
+                ch.retry();
+            }
+        }
+    }
+
+    let extension = ch.info().unwrap_or_default().extension();
+    Ok(Json(json!({
+        "channel_id": id,
+        "tip": tip,
+        "playlist": format!("/pls/{id}.m3u?tip={tip}"),
+        "stream": format!("/stream/{id}{extension}"),
+    })))
+}
@@ -0,0 +1,301 @@
+//! 管理画面・APIの認証
+//!
+//! local_addressのネットワークからは無条件で通す。それ以外はBasic認証か、
+//! /loginで発行したセッショントークン(Cookie or Bearer)が必要
+//! パスワードのハッシュ確認は重いので、ブラウザからはセッションを使うこと
+//! 続けて失敗したIPからはしばらくパスワードを確認せずに断る
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::headers::{
+    authorization::{Basic, Bearer},
+    Authorization, Cookie, HeaderMapExt,
+};
+use http::{header, HeaderMap, StatusCode};
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+
+use super::{AppState, MyConnectInfo};
+
+const SESSION_COOKIE: &str = "peercast_session";
+const SESSION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// これだけ続けて失敗したIPは締め出す
+const MAX_AUTH_FAILURES: u32 = 5;
+/// 最後に失敗してからこの間は締め出したまま
+const AUTH_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+
+////////////////////////////////////////////////////////////////////////////////
+// SessionStore
+//
+#[derive(Debug, Default)]
+pub(crate) struct SessionStore {
+    sessions: Mutex<HashMap<String, Instant>>,
+}
+
+impl SessionStore {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    /// 新しいトークンを発行する
+    pub(crate) fn create(&self) -> String {
+        let mut bytes = [0_u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.insert(token.clone(), Instant::now() + SESSION_TTL);
+        token
+    }
+
+    /// 期限切れのものはついでに消す
+    pub(crate) fn verify(&self, token: &str) -> bool {
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, expires_at| *expires_at > now);
+        sessions.contains_key(token)
+    }
+
+    pub(crate) fn remove(&self, token: &str) -> bool {
+        self.sessions.lock().unwrap().remove(token).is_some()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// AuthFailures
+//
+/// IPごとの続けて失敗した回数と最後に失敗した時刻
+#[derive(Debug, Default)]
+pub(crate) struct AuthFailures {
+    ips: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl AuthFailures {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    fn is_locked(&self, ip: IpAddr) -> bool {
+        self.is_locked_at(ip, Instant::now())
+    }
+
+    // 締め出しが終わったものはついでに消す
+    fn is_locked_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut ips = self.ips.lock().unwrap();
+        ips.retain(|_, (_, last)| now.saturating_duration_since(*last) < AUTH_LOCKOUT);
+        ips.get(&ip)
+            .is_some_and(|(failures, _)| *failures >= MAX_AUTH_FAILURES)
+    }
+
+    fn failed(&self, ip: IpAddr) {
+        self.failed_at(ip, Instant::now())
+    }
+
+    fn failed_at(&self, ip: IpAddr, now: Instant) {
+        let mut ips = self.ips.lock().unwrap();
+        let (failures, last) = ips.entry(ip).or_insert((0, now));
+        *failures += 1;
+        *last = now;
+        if *failures == MAX_AUTH_FAILURES {
+            warn!(
+                "lock out {ip} for {AUTH_LOCKOUT:?}: {MAX_AUTH_FAILURES} authentication failures"
+            );
+        }
+    }
+
+    fn succeeded(&self, ip: IpAddr) {
+        self.ips.lock().unwrap().remove(&ip);
+    }
+}
+
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+        return Some(bearer.token().to_string());
+    }
+    headers
+        .typed_get::<Cookie>()
+        .and_then(|cookie| cookie.get(SESSION_COOKIE).map(String::from))
+}
+
+// pbkdf2の確認はCPUを使うのでブロッキングスレッドで行う
+async fn authenticate(config: Config, username: String, password: String) -> bool {
+    tokio::task::spawn_blocking(move || config.authenticate(&username, &password).is_ok())
+        .await
+        .unwrap_or(false)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Middleware
+//
+pub(super) async fn require_auth(
+    State(state): State<AppState>,
+    ConnectInfo(info): ConnectInfo<MyConnectInfo>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = info.remote.ip();
+    let config = state.config();
+    if config.local_address.iter().any(|net| net.contains(&ip)) {
+        return next.run(request).await;
+    }
+
+    let headers = request.headers();
+    if let Some(token) = session_token(headers) {
+        if state.sessions.verify(&token) {
+            return next.run(request).await;
+        }
+    }
+    if let Some(Authorization(basic)) = headers.typed_get::<Authorization<Basic>>() {
+        if state.auth_failures.is_locked(ip) {
+            return StatusCode::TOO_MANY_REQUESTS.into_response();
+        }
+        let (username, password) = (basic.username().into(), basic.password().into());
+        if authenticate(config, username, password).await {
+            state.auth_failures.succeeded(ip);
+            return next.run(request).await;
+        }
+        warn!("authentication failed from {ip}");
+        state.auth_failures.failed(ip);
+    }
+
+    debug!("unauthorized access from {ip} to {}", request.uri());
+    unauthorized()
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(
+            header::WWW_AUTHENTICATE,
+            r#"Basic realm="PeerCast", charset="UTF-8""#,
+        )],
+        "Unauthorized",
+    )
+        .into_response()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Login / Logout
+//
+#[derive(Debug, Deserialize)]
+pub(super) struct LoginRequest {
+    #[serde(default)]
+    username: String,
+    password: String,
+}
+
+pub(super) async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(info): ConnectInfo<MyConnectInfo>,
+    Json(req): Json<LoginRequest>,
+) -> Response {
+    let ip = info.remote.ip();
+    if state.auth_failures.is_locked(ip) {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    }
+    if !authenticate(state.config(), req.username, req.password).await {
+        warn!("login failed from {ip}");
+        state.auth_failures.failed(ip);
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    state.auth_failures.succeeded(ip);
+
+    let token = state.sessions.create();
+    info!("login from {ip}");
+    let cookie = format!(
+        "{SESSION_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+        SESSION_TTL.as_secs()
+    );
+    (
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({ "token": token })),
+    )
+        .into_response()
+}
+
+pub(super) async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = session_token(&headers) {
+        state.sessions.remove(&token);
+    }
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0");
+    ([(header::SET_COOKIE, cookie)], StatusCode::NO_CONTENT).into_response()
+}
+
+#[cfg(test)]
+mod t {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn test_session_store() {
+        let store = SessionStore::new();
+        let token = store.create();
+        assert_eq!(token.len(), 64);
+        assert!(store.verify(&token));
+        assert!(!store.verify("invalid"));
+        assert!(store.remove(&token));
+        assert!(!store.verify(&token));
+    }
+
+    #[test]
+    fn test_session_token() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_token(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; peercast_session=abcd"),
+        );
+        assert_eq!(session_token(&headers).as_deref(), Some("abcd"));
+
+        // Bearerを優先する
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer efgh"),
+        );
+        assert_eq!(session_token(&headers).as_deref(), Some("efgh"));
+    }
+
+    #[test]
+    fn test_auth_failures() {
+        let failures = AuthFailures::new();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Instant::now();
+
+        for _ in 0..MAX_AUTH_FAILURES - 1 {
+            failures.failed_at(ip, now);
+        }
+        assert!(!failures.is_locked_at(ip, now));
+        failures.failed_at(ip, now);
+        assert!(failures.is_locked_at(ip, now));
+        assert!(!failures.is_locked_at(other, now));
+        // 最後の失敗から時間が経てば解除される
+        assert!(failures.is_locked_at(ip, now + AUTH_LOCKOUT - Duration::from_secs(1)));
+        assert!(!failures.is_locked_at(ip, now + AUTH_LOCKOUT));
+
+        // 成功したら数え直す
+        for _ in 0..MAX_AUTH_FAILURES - 1 {
+            failures.failed_at(ip, now);
+        }
+        failures.succeeded(ip);
+        failures.failed_at(ip, now);
+        assert!(!failures.is_locked_at(ip, now));
+    }
+}
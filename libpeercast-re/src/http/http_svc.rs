@@ -1,20 +1,29 @@
 use std::{
-    collections::HashMap, convert::Infallible, future, net::SocketAddr, path::PathBuf,
-    str::FromStr, sync::Arc, task::Poll, time::Duration,
+    collections::HashMap,
+    convert::Infallible,
+    future,
+    net::SocketAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    task::Poll,
+    time::Duration,
 };
 
 use askama::filters::format;
 use axum::{
     body::{self, Body},
     extract::{connect_info::Connected, ConnectInfo, Path, Query, Request, State},
-    http::HeaderValue,
+    http::{HeaderMap, HeaderValue},
+    middleware,
     response::{Html, IntoResponse, Redirect, Response},
     routing::{self, get},
-    Router,
+    Extension, Router,
 };
 use axum_core::BoxError;
 use axum_extra::extract::Host;
 use bytes::Bytes;
+use chrono::Utc;
 use futures_util::{future::Pending, task::SpawnExt, Stream};
 use hyper::{rt::Write, upgrade::Upgraded, StatusCode, Uri};
 use hyper_util::rt::TokioIo;
@@ -35,21 +44,35 @@ use tower_http::{
 use tracing::{debug, error, info, trace, Span};
 
 use crate::{
+    ban::BanList,
+    bandwidth::{UploadLimitConfig, UploadLimiter},
     codec::FlvWriter,
     config::Config,
-    http::{middleware::RestrictIpLayer },
+    connection_registry::{ConnectionLimits, Protocol},
+    event::ConnectionKind,
+    listener_token::{self, TokenError},
+    metrics,
     pcp::{
         ChannelInfo, ChannelManager, ChannelMessage, ChannelType, GnuId, RelayTaskConfig,
         SourceTaskConfig, TaskStatus,
     },
+    recorder::RecorderManager,
     rtmp::{connection::Connection, stream_manager::StreamManagerMessage},
+    util::forwarded::ForwardedOrigin,
+    yp::YpBrowser,
     ConnectionId,
 };
 
 #[cfg(debug_assertions)]
 use super::UiProxyMode;
 
-use super::{Api, AppState, MyConnectInfo};
+use super::{
+    admin::AdminSvc,
+    auth::{self, AuthFailures, SessionStore},
+    middleware::forwarded_client,
+    playlist::PlaylistFormat,
+    Api, AppState, MyConnectInfo,
+};
 
 const VITE_UI_PORT: u16 = 5173;
 const SWAGGER_UI_PORT: u16 = 8002;
@@ -59,9 +82,12 @@ pub struct HttpSvc;
 impl HttpSvc {
     pub fn new(
         config_path: PathBuf,
-        config: Config,
+        config: Arc<RwLock<Config>>,
         session_id: GnuId,
         channel_manager: Arc<ChannelManager>,
+        recorder_manager: Arc<RecorderManager>,
+        yp_browser: Arc<YpBrowser>,
+        ban_list: Arc<BanList>,
         manager_sender: Arc<mpsc::UnboundedSender<StreamManagerMessage>>,
     ) -> Router<()> {
         #[cfg(debug_assertions)]
@@ -76,7 +102,8 @@ impl HttpSvc {
             Err(_) => UiProxyMode::Embed,
         };
 
-        let port = config.server_port;
+        let current = config.read().unwrap().clone();
+        let port = current.server_port;
         let mut origins = vec![format!("http://localhost:{port}")
             .parse::<HeaderValue>()
             .unwrap()];
@@ -97,49 +124,87 @@ impl HttpSvc {
                     .unwrap(),
             );
         }
-        let headers = [hyper::header::CONTENT_TYPE];
-
-        // local_address
-        let allow_ips = config.local_address.clone();
+        let headers = [hyper::header::CONTENT_TYPE, hyper::header::AUTHORIZATION];
 
         debug!(cor_origins=?origins);
         debug!(cor_headers=?headers);
-        debug!(allow_ips=?allow_ips);
+        debug!(local_address=?current.local_address);
 
-        Router::new()
+        // PCPの下流とバケットを共有するのでChannelManagerが持っているものに設定を入れる
+        let upload_limiter = channel_manager.upload_limiter();
+        upload_limiter.reconfigure(UploadLimitConfig::from_config(&current));
+        let state = AppState {
+            connections: channel_manager.connections(),
+            channel_manager,
+            recorder_manager,
+            yp_browser,
+            ban_list,
+            upload_limiter,
+            manager_sender,
+            sessions: Arc::new(SessionStore::new()),
+            auth_failures: Arc::new(AuthFailures::new()),
+            started_at: Utc::now(),
+            //
+            config_path,
+            config,
+            session_id,
+            //
+            #[cfg(debug_assertions)]
+            proxy_mode,
+        };
+
+        // 管理用はlocal_addressからのアクセスか認証済みの場合だけ
+        let admin = Router::new()
             .route("/", get(Self::handler))
-            .route("/pls/:id", get(Self::playlist))
-            .route("/stream/:id", get(Self::stream))
+            .route("/metrics", get(Self::metrics))
             // .route("/demo/throttle", get(Demo::throttle))
             // .route("/ui", get(|| async { Redirect::permanent("/ui/") }))
             // .nest("/ui/", Ui::new())
             .nest("/api", Api::new())
+            .merge(AdminSvc::new())
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth::require_auth,
+            ));
+
+        Router::new()
+            .route("/pls/{id}", get(Self::playlist))
+            .route("/stream/{id}", get(Self::stream))
+            .route("/token/{id}", get(Self::verify_token))
+            .route("/login", routing::post(auth::login))
+            .route("/logout", routing::post(auth::logout))
+            .merge(admin)
             .fallback(Self::not_found)
-            .layer(TraceLayer::new_for_http().on_body_chunk(
-                |chunk: &Bytes, _latency: Duration, _span: &Span| {
-                    tracing::debug!("streaming {} bytes", chunk.len());
-                },
-            ))
-            .layer(RestrictIpLayer {
-                white_nets: allow_ips,
-            })
+            .layer(
+                TraceLayer::new_for_http()
+                    .make_span_with(|req: &Request| {
+                        let client = req
+                            .extensions()
+                            .get::<ConnectInfo<MyConnectInfo>>()
+                            .map(|ConnectInfo(info)| info.remote);
+                        tracing::debug_span!(
+                            "request",
+                            method = %req.method(),
+                            uri = %req.uri(),
+                            client = ?client,
+                        )
+                    })
+                    .on_body_chunk(|chunk: &Bytes, _latency: Duration, _span: &Span| {
+                        tracing::debug!("streaming {} bytes", chunk.len());
+                    }),
+            )
             .layer(
                 CorsLayer::new()
                     .allow_origin(origins)
                     .allow_methods(cors::Any)
                     .allow_headers(headers),
             )
-            .with_state(AppState {
-                channel_manager,
-                manager_sender,
-                //
-                config_path,
-                config,
-                session_id,
-                //
-                #[cfg(debug_assertions)]
-                proxy_mode,
-            })
+            // 他のlayerより先にクライアントのアドレスを確定させる
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                forwarded_client,
+            ))
+            .with_state(state)
     }
 
     async fn not_found(req: Request) -> Html<&'static str> {
@@ -152,126 +217,261 @@ impl HttpSvc {
         Html("<h1>Hello, World!</h1>")
     }
 
-    // async fn playlist(req: Request) -> impl IntoResponse {
+    async fn metrics(
+        State(AppState {
+            channel_manager,
+            connections,
+            ..
+        }): State<AppState>,
+    ) -> impl IntoResponse {
+        metrics::observe_channels(&channel_manager);
+        metrics::observe_connections(&connections);
+        (
+            [(hyper::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+            metrics::gather(),
+        )
+    }
+
+    // http://[ADDR]:[PORT]/pls/[GnuID].[m3u|pls|asx|xspf]?tip=[IP:PORT]&auth=[TOKEN]
     async fn playlist(
-        ConnectInfo(MyConnectInfo {
-            // local,
-            remote,
-            connection_id,
-            shutdown,
-        }): ConnectInfo<MyConnectInfo>,
+        ConnectInfo(MyConnectInfo { connection_id, .. }): ConnectInfo<MyConnectInfo>,
         Host(host): Host,
+        Extension(origin): Extension<ForwardedOrigin>,
+        headers: HeaderMap,
         Path(channel_id): Path<String>,
         Query(params): Query<HashMap<String, String>>,
         State(AppState {
             channel_manager,
-            session_id,
             config,
             ..
         }): State<AppState>,
     ) -> impl IntoResponse {
-        // debug!(?connection_id, ?host, ?channel_id, ?params);
-        let split_chid = channel_id.split(".").collect::<Vec<&str>>();
-        let Some((channel_id, extentions)) = split_chid.split_first() else {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        let (channel_id, extension) = match channel_id.split_once('.') {
+            Some((id, ext)) => (id, Some(ext)),
+            None => (channel_id.as_str(), None),
         };
-
         let Ok(channel_id) = GnuId::from_str(channel_id) else {
-            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            return Err(StatusCode::BAD_REQUEST);
+        };
+        let format = extension
+            .and_then(PlaylistFormat::from_extension)
+            .or_else(|| {
+                headers
+                    .get(hyper::header::ACCEPT)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(PlaylistFormat::from_accept)
+            })
+            .unwrap_or(PlaylistFormat::M3u);
+
+        let tip = match params.get("tip") {
+            None => None,
+            Some(tip) => match tip.parse::<SocketAddr>() {
+                Ok(tip) => Some(tip),
+                Err(e) => {
+                    error!("tipに接続先のIpアドレスが含まれていません: {tip} ({e})");
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+            },
         };
 
-        let ch = match channel_manager.get(&channel_id) {
-            Some(ch) => ch,
-            None => {
-                let Ok(connect_to) = params["tip"].parse::<SocketAddr>() else {
-                    error!("tipに接続先のIpアドレスが含まれていません");
-                    return Err(StatusCode::INTERNAL_SERVER_ERROR);
-                };
-                let ch_type = ChannelType::Relay;
-                channel_manager.create_or_get(channel_id, ch_type, None, None)
+        let ch = match (channel_manager.get(&channel_id), tip) {
+            (Some(ch), _) => ch,
+            (None, Some(_)) => {
+                channel_manager.create_or_get(channel_id, ChannelType::Relay, None, None)
             }
+            // 探しに行く手段が無い
+            (None, None) => return Err(StatusCode::NOT_FOUND),
         };
 
-        // TODO: Channelから情報もってきて適宜処理する
-        match ch.status() {
-            TaskStatus::Receiving => { /* pass */ }
-            // FIXME: session_idをどこかで管理すること
-            _ => {
-                // let _ = ch.connect(ConnectionId::new(), session_id, connect_to);
-                let connect_to = "192.168.10.230:61744".parse().unwrap();
+        let token = params.get("auth").map(String::as_str);
+        let secret = config.read().unwrap().token_secret.clone();
+        if let Err(e) = listener_token::authorize(&ch, secret.as_deref(), token).await {
+            info!("reject playlist CID:{channel_id}: {e}");
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        match (ch.status(), ch.channel_type(), tip) {
+            (TaskStatus::Receiving | TaskStatus::Searching { .. } | TaskStatus::Init, _, _) => {}
+            (_, ChannelType::Broadcast, _) => {}
+            (_, ChannelType::Relay, Some(addr)) => {
+                // 上流がトークンを求めるなら下流も同じトークンで確認する
+                if let Some(token) = token {
+                    listener_token::keep_relay_token(&ch, addr, token).await;
+                }
                 let task_config = SourceTaskConfig::Relay(RelayTaskConfig {
-                    addr: connect_to,
-                    self_addr: todo!(),
+                    addr,
+                    self_addr: None,
+                    token: token.map(Into::into),
                 });
                 let _ = ch.connect(connection_id, task_config);
             }
-        };
-        drop(ch);
-
-        // TODO: chから拡張子をゲットする
-        // let _extensions = extentions
-        //     .first()
-        //     .map_or_else(|| String::new(), |ext| format!(".{}", ext));
-        let (host, port) = match host.parse::<Uri>() {
-            Ok(host_url) => {
-                //
-                let host = host_url.host().unwrap_or_else(|| "localhost").to_string();
-                let port = host_url
-                    .port()
-                    .map(|port| port.as_u16())
-                    .unwrap_or_else(|| config.server_port);
-                (host, port)
-            }
-            Err(e) => {
-                error!("Invalid Uri {}", e);
-                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            (_, ChannelType::Relay, None) => {
+                let _ = ch.retry();
             }
         };
 
-        // < Content-Type:audio/x-mpegurl
-        // #EXTM3U
-        // #EXTINF:-1, [CHANNEL_NAME]
-        // http://[ADDR]:[PORT]/stream/[GnuID].flv
-        let m3u_str = indoc::formatdoc! {"
-            #EXTM3U
-            #EXTINF:-1, [CHANNEL_NAME]
-            http://{host}:{port}/stream/{channel_id}
-        "};
+        // プロキシ経由ならプロキシが受けたHostとスキームで作る
+        let scheme = origin.proto.unwrap_or_else(|| "http".into());
+        let authority = match origin.host {
+            Some(host) => host,
+            None => match host.parse::<Uri>() {
+                Ok(host_url) => {
+                    //
+                    let host = host_url.host().unwrap_or_else(|| "localhost").to_string();
+                    let port = host_url
+                        .port()
+                        .map(|port| port.as_u16())
+                        .unwrap_or_else(|| config.read().unwrap().server_port);
+                    format!("{host}:{port}")
+                }
+                Err(e) => {
+                    error!("Invalid Uri {}", e);
+                    return Err(StatusCode::BAD_REQUEST);
+                }
+            },
+        };
 
-        // trace!(?connection_id, ?m3u_str);
+        let info = ch.info().unwrap_or_default();
+        let name = match info.name.as_str() {
+            "" => channel_id.to_string(),
+            name => name.to_string(),
+        };
+        let extension = info.extension();
+        let mut url = format!("{scheme}://{authority}/stream/{channel_id}{extension}");
+        // 認証付きのURLで来た場合はストリームにも引き継ぐ
+        if let Some(auth) = params.get("auth") {
+            url.push_str(&format!("?auth={}", urlencoding::encode(auth)));
+        }
 
         Ok((
             StatusCode::OK,
-            [(hyper::header::CONTENT_TYPE, "audio/x-mpegurl")],
-            m3u_str,
+            [(hyper::header::CONTENT_TYPE, format.content_type())],
+            format.render(&name, &url),
         ))
     }
 
+    // 下流のリレーが知らないトークンを確認しに来る
+    // http://[ADDR]:[PORT]/token/[GnuID]?auth=[TOKEN]
+    async fn verify_token(
+        Path(channel_id): Path<String>,
+        Query(params): Query<HashMap<String, String>>,
+        State(state): State<AppState>,
+    ) -> StatusCode {
+        let Ok(channel_id) = GnuId::from_str(&channel_id) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        let Some(ch) = state.channel_manager.get(&channel_id) else {
+            return StatusCode::NOT_FOUND;
+        };
+        let secret = state.config().token_secret;
+        let token = params.get("auth").map(String::as_str);
+        match listener_token::authorize(&ch, secret.as_deref(), token).await {
+            Ok(()) => StatusCode::NO_CONTENT,
+            Err(TokenError::Upstream) => StatusCode::BAD_GATEWAY,
+            Err(e) => {
+                info!("reject token CID:{channel_id}: {e}");
+                StatusCode::FORBIDDEN
+            }
+        }
+    }
+
     // http://192.168.1.10:17144/stream/85B32473FE39A93B60276926BB966CEA.flv
     async fn stream(
         ConnectInfo(conn): ConnectInfo<MyConnectInfo>,
+        headers: HeaderMap,
         Path(channel_id): Path<String>,
+        Query(params): Query<HashMap<String, String>>,
         State(state): State<AppState>,
     ) -> impl IntoResponse {
         trace!(?channel_id);
-        let Ok(channel_id) = GnuId::from_str(&channel_id) else {
+        // 拡張子は付いていても無視する
+        let channel_id = channel_id.split('.').next().unwrap_or_default();
+        let Ok(channel_id) = GnuId::from_str(channel_id) else {
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         };
         let Some(channel) = state.channel_manager.get(&channel_id) else {
             return Err(StatusCode::NOT_FOUND);
         };
 
+        let config = state.config();
+        let token = params.get("auth").map(String::as_str);
+        if let Err(e) =
+            listener_token::authorize(&channel, config.token_secret.as_deref(), token).await
+        {
+            info!("reject listener {} CID:{channel_id}: {e}", conn.remote);
+            let body = format!("403 Forbidden: {e}\n");
+            return Ok((StatusCode::FORBIDDEN, body).into_response());
+        }
+
+        // 上限を超えていたら理由を書いて断る
+        let mut limits = ConnectionLimits::from_config(&config);
+        // local_addressからの接続にはIP毎の上限を掛けない
+        if config
+            .local_address
+            .iter()
+            .any(|net| net.contains(&conn.remote.ip()))
+        {
+            limits.max_connections_per_ip = 0;
+        }
+        let handle = match state.connections.try_register(
+            conn.connection_id,
+            Protocol::HttpListener,
+            conn.remote,
+            channel_id,
+            &limits,
+        ) {
+            Ok(handle) => handle,
+            Err(e) => {
+                info!("reject listener {} CID:{channel_id}: {e}", conn.remote);
+                let body = format!("503 Service Unavailable: {e}\n");
+                return Ok((StatusCode::SERVICE_UNAVAILABLE, body).into_response());
+            }
+        };
+
+        let mime_type = channel.info().unwrap_or_default().mime_type();
         let mut streamer = channel.channel_stream(conn.connection_id);
         trace!("streamer={:?}", &streamer);
         drop(channel);
 
-        let resp = Response::builder()
-            .status(StatusCode::OK)
-            .header(hyper::header::CONTENT_TYPE, "video/x-flv")
-            .body(Body::from_stream(streamer))
-            .unwrap();
+        // bodyと一緒にdropされて、切断時にConnectionLeftが流れる
+        let guard = state.channel_manager.events().connection_guard(
+            channel_id,
+            conn.connection_id,
+            ConnectionKind::Listener,
+            conn.remote,
+        );
+        if let Some(user_agent) = headers
+            .get(hyper::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+        {
+            handle.set_user_agent(user_agent);
+        }
+        // 切断を要求されたら次のチャンクで打ち切る
+        let streamer = streamer.take_while(move |chunk| {
+            let _guard = &guard;
+            if let Ok(bytes) = chunk {
+                handle.add_bytes_out(bytes.len());
+            }
+            handle.quit_code().is_none()
+        });
+        // 上り帯域の上限を超えないように送る前に待つ
+        let throttle = state.upload_limiter.throttle(&channel_id);
+        let streamer = futures_util::StreamExt::then(streamer, move |chunk| {
+            let throttle = throttle.clone();
+            async move {
+                if let Ok(bytes) = &chunk {
+                    throttle.acquire(bytes.len()).await;
+                }
+                chunk
+            }
+        });
 
-        Ok(resp)
+        Ok((
+            StatusCode::OK,
+            [(hyper::header::CONTENT_TYPE, mime_type)],
+            Body::from_stream(streamer),
+        )
+            .into_response())
     }
 }
 
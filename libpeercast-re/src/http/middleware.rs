@@ -3,16 +3,53 @@ use std::{
     task::{Context, Poll},
 };
 
-use axum::{body::HttpBody, extract::ConnectInfo, http::HeaderValue};
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, State},
+    http::HeaderValue,
+    middleware::Next,
+};
 use axum_core::{body::Body, extract::Request, response::Response};
 use futures_util::{future::BoxFuture, Future};
 use hyper::StatusCode;
 use ipnet::IpNet;
 use tower::{Layer, Service};
 
-use crate::error;
+use crate::{
+    error,
+    util::forwarded::{client_addr, ForwardedOrigin},
+};
+
+use super::{AppState, MyConnectInfo};
+
+/// 信頼するプロキシから来た場合は、ConnectInfoのremoteをプロキシの向こうのクライアントに置き換える
+/// 後ろのRestrictIpLayerや認証、IP毎の上限、アクセスログはこれを見る
+/// プレイリストのURL用にForwardedOriginも入れておく
+pub(super) async fn forwarded_client(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response<Body> {
+    let trusted = state.config.read().unwrap().trusted_proxies.clone();
+    let Some(ConnectInfo(info)) = request.extensions().get::<ConnectInfo<MyConnectInfo>>() else {
+        return next.run(request).await;
+    };
+    let peer = info.remote;
+    let client = client_addr(request.headers(), peer, &trusted);
+    let origin = ForwardedOrigin::from_headers(request.headers(), peer.ip(), &trusted);
 
-use super::MyConnectInfo;
+    if client != peer {
+        tracing::trace!("forwarded client {client} via {peer}");
+        if let Some(ConnectInfo(info)) = request
+            .extensions_mut()
+            .get_mut::<ConnectInfo<MyConnectInfo>>()
+        {
+            info.remote = client;
+        }
+    }
+    request.extensions_mut().insert(origin);
+    next.run(request).await
+}
 
 #[derive(Clone)]
 pub struct RestrictIpLayer {
@@ -1,16 +1,20 @@
+mod admin;
 mod api;
+mod auth;
 mod http_svc;
 mod middleware;
+mod playlist;
 
 use std::{
     net::{IpAddr, SocketAddr},
     path::PathBuf,
-    sync::{Arc, Mutex},
+    sync::{Arc, Mutex, RwLock},
 };
 
 use axum::extract::{connect_info::Connected, ConnectInfo};
 use axum_core::response::IntoResponse;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use http::StatusCode;
 pub use http_svc::HttpSvc;
 use hyper_util::rt::TokioIo;
@@ -20,10 +24,15 @@ use sync_wrapper::SyncWrapper;
 use tokio::{net::TcpStream, sync::mpsc};
 
 use crate::{
+    ban::BanList,
+    bandwidth::UploadLimiter,
     config::Config,
+    connection_registry::ConnectionRegistry,
     pcp::{ChannelManager, GnuId},
+    recorder::RecorderManager,
     rtmp::stream_manager::StreamManagerMessage,
     util::Shutdown,
+    yp::YpBrowser,
     ConnectionId,
 };
 
@@ -89,17 +98,32 @@ impl Connected<MyConnectInfo> for MyConnectInfo {
 #[derive(Clone)]
 pub(self) struct AppState {
     config_path: PathBuf,
-    config: Config,
+    // PUT /api/configで書き換わる
+    config: Arc<RwLock<Config>>,
     //
     session_id: GnuId,
     channel_manager: Arc<ChannelManager>,
+    recorder_manager: Arc<RecorderManager>,
+    yp_browser: Arc<YpBrowser>,
+    ban_list: Arc<BanList>,
+    upload_limiter: Arc<UploadLimiter>,
+    connections: Arc<ConnectionRegistry>,
     //
     manager_sender: Arc<mpsc::UnboundedSender<StreamManagerMessage>>,
+    sessions: Arc<auth::SessionStore>,
+    auth_failures: Arc<auth::AuthFailures>,
+    started_at: DateTime<Utc>,
 
     #[cfg(debug_assertions)]
     proxy_mode: UiProxyMode,
 }
 
+impl AppState {
+    fn config(&self) -> Config {
+        self.config.read().unwrap().clone()
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // Check Ip
 //
@@ -0,0 +1,152 @@
+//! /pls/ で返すプレイリスト
+//!
+//! 形式は /pls/[GnuID].[ext] の拡張子か、無ければAcceptヘッダーで決める
+
+use html_escape::encode_double_quoted_attribute;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum PlaylistFormat {
+    M3u,
+    Pls,
+    Asx,
+    Xspf,
+}
+
+impl PlaylistFormat {
+    pub(super) fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "m3u" | "m3u8" => Some(Self::M3u),
+            "pls" => Some(Self::Pls),
+            "asx" => Some(Self::Asx),
+            "xspf" => Some(Self::Xspf),
+            _ => None,
+        }
+    }
+
+    /// 一番最初に見つかった対応形式
+    pub(super) fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(|media_range| {
+            let mime = media_range.split(';').next().unwrap_or_default().trim();
+            match mime {
+                "audio/x-mpegurl" | "audio/mpegurl" | "application/vnd.apple.mpegurl" => {
+                    Some(Self::M3u)
+                }
+                "audio/x-scpls" => Some(Self::Pls),
+                "video/x-ms-asf" | "video/x-ms-asx" => Some(Self::Asx),
+                "application/xspf+xml" => Some(Self::Xspf),
+                _ => None,
+            }
+        })
+    }
+
+    pub(super) fn content_type(&self) -> &'static str {
+        match self {
+            Self::M3u => "audio/x-mpegurl",
+            Self::Pls => "audio/x-scpls",
+            Self::Asx => "video/x-ms-asf",
+            Self::Xspf => "application/xspf+xml",
+        }
+    }
+
+    pub(super) fn render(&self, name: &str, url: &str) -> String {
+        match self {
+            Self::M3u => {
+                let name = single_line(name);
+                indoc::formatdoc! {"
+                    #EXTM3U
+                    #EXTINF:-1, {name}
+                    {url}
+                "}
+            }
+            Self::Pls => {
+                let name = single_line(name);
+                indoc::formatdoc! {"
+                    [playlist]
+                    NumberOfEntries=1
+                    File1={url}
+                    Title1={name}
+                    Length1=-1
+                    Version=2
+                "}
+            }
+            Self::Asx => {
+                let (name, url) = (
+                    encode_double_quoted_attribute(name),
+                    encode_double_quoted_attribute(url),
+                );
+                indoc::formatdoc! {r#"
+                    <ASX version="3.0">
+                    <TITLE>{name}</TITLE>
+                    <ENTRY>
+                    <TITLE>{name}</TITLE>
+                    <REF href="{url}" />
+                    </ENTRY>
+                    </ASX>
+                "#}
+            }
+            Self::Xspf => {
+                let (name, url) = (
+                    encode_double_quoted_attribute(name),
+                    encode_double_quoted_attribute(url),
+                );
+                indoc::formatdoc! {r#"
+                    <?xml version="1.0" encoding="UTF-8"?>
+                    <playlist version="1" xmlns="http://xspf.org/ns/0/">
+                    <trackList>
+                    <track>
+                    <location>{url}</location>
+                    <title>{name}</title>
+                    </track>
+                    </trackList>
+                    </playlist>
+                "#}
+            }
+        }
+    }
+}
+
+// 行単位の形式ではチャンネル名の改行で行を増やされないようにする
+fn single_line(name: &str) -> String {
+    name.replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_playlist_format() {
+        assert_eq!(
+            PlaylistFormat::from_extension("PLS"),
+            Some(PlaylistFormat::Pls)
+        );
+        assert_eq!(PlaylistFormat::from_extension("flv"), None);
+        assert_eq!(
+            PlaylistFormat::from_accept("text/html, application/xspf+xml;q=0.9"),
+            Some(PlaylistFormat::Xspf)
+        );
+        assert_eq!(PlaylistFormat::from_accept("*/*"), None);
+
+        let url = "http://localhost:7144/stream/0123.flv?auth=a&b";
+        assert_eq!(
+            PlaylistFormat::M3u.render("テスト", url),
+            format!("#EXTM3U\n#EXTINF:-1, テスト\n{url}\n")
+        );
+        assert!(PlaylistFormat::Pls
+            .render("テスト", url)
+            .contains(&format!("File1={url}\nTitle1=テスト\n")));
+        let asx = PlaylistFormat::Asx.render("<ch>", url);
+        assert!(asx.contains("<TITLE>&lt;ch&gt;</TITLE>"));
+        assert!(asx.contains("?auth=a&amp;b"));
+
+        // 改行で別のURLを差し込まれない
+        let name = "ch\r\nhttp://evil.example/";
+        let m3u = PlaylistFormat::M3u.render(name, url);
+        assert_eq!(m3u.lines().count(), 3);
+        assert!(m3u.contains("#EXTINF:-1, ch  http://evil.example/\n"));
+        let pls = PlaylistFormat::Pls.render(name, url);
+        assert!(pls.contains("Title1=ch  http://evil.example/\n"));
+        let xspf = PlaylistFormat::Xspf.render("ch", url);
+        assert!(xspf.contains("<title>ch</title>"));
+    }
+}
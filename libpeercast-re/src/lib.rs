@@ -19,13 +19,25 @@ pub const PKG_SERVANT_VERSION_EX_NUMBER: Lazy<u16> = Lazy::new(|| {
     major * 100 + minor
 });
 
+pub mod ban;
+
+pub mod bandwidth;
+
 pub mod config;
 
 mod conn;
 pub use conn::ConnectionId;
 
+pub mod connection_registry;
+
 pub mod error;
 
+pub mod event;
+
+pub mod flood;
+
+pub mod metrics;
+
 pub mod codec;
 
 /// Peercast Protocol
@@ -33,8 +45,18 @@ pub mod pcp;
 
 pub mod http;
 
+/// 限定公開チャンネルの視聴トークン
+pub mod listener_token;
+
 pub mod rtmp;
 
+pub mod tls;
+
+pub mod recorder;
+
+/// YPのチャンネル一覧
+pub mod yp;
+
 pub mod app {
     pub mod cui;
     mod cui_dl;
@@ -0,0 +1,418 @@
+//! 限定公開チャンネルの視聴トークン
+//!
+//! "{有効期限(unix秒)}.{HMAC-SHA256("{チャンネルID}:{有効期限}")のhex}" の形式
+//! 配信しているPCが発行する。リレーは鍵を持っていないので、上流に渡したトークンはそのまま通し、
+//! 知らないトークンは上流の `/token/{チャンネルID}` に確認してその結果を覚えておく
+
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex, time::Duration};
+
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+use tracing::debug;
+
+use crate::pcp::{Channel, ChannelType, GnuId};
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// 上流に断られたトークンを覚えておく時間
+const REJECTED_CACHE_SECS: i64 = 60;
+/// チャンネルごとに覚えておくトークンの数
+const MAX_CACHED_TOKENS: usize = 256;
+/// チャンネルごとに同時に上流へ確認する数。超えた分は確認せずに断る
+const MAX_UPSTREAM_CHECKS: usize = 4;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    #[error("token required")]
+    Missing,
+
+    #[error("malformed token")]
+    Malformed,
+
+    #[error("token expired")]
+    Expired,
+
+    #[error("invalid token")]
+    InvalidSignature,
+
+    #[error("could not verify token upstream")]
+    Upstream,
+}
+
+/// 署名鍵を新しく作る
+pub fn generate_secret() -> String {
+    let mut bytes = [0_u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    encode_hex(&bytes)
+}
+
+#[derive(Debug, Clone)]
+pub struct TokenSigner {
+    secret: Vec<u8>,
+}
+
+impl TokenSigner {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    pub fn issue(&self, channel_id: GnuId, expires_at: DateTime<Utc>) -> String {
+        let expires = expires_at.timestamp();
+        let mac = self.mac(channel_id, expires).finalize().into_bytes();
+        format!("{expires}.{}", encode_hex(&mac))
+    }
+
+    /// 正しければ有効期限を返す
+    pub fn verify(&self, channel_id: GnuId, token: &str) -> Result<DateTime<Utc>, TokenError> {
+        self.verify_at(channel_id, token, Utc::now())
+    }
+
+    fn verify_at(
+        &self,
+        channel_id: GnuId,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>, TokenError> {
+        let (expires, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let expires = expires.parse::<i64>().map_err(|_| TokenError::Malformed)?;
+        let signature = decode_hex(signature).ok_or(TokenError::Malformed)?;
+        // 比較は定数時間で行う
+        self.mac(channel_id, expires)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::InvalidSignature)?;
+
+        let expires_at = to_datetime(expires)?;
+        if expires_at <= now {
+            return Err(TokenError::Expired);
+        }
+        Ok(expires_at)
+    }
+
+    fn mac(&self, channel_id: GnuId, expires: i64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC can take key of any size");
+        mac.update(format!("{channel_id}:{expires}").as_bytes());
+        mac
+    }
+}
+
+/// 署名は確認せずに有効期限だけを読む
+pub fn expires_at(token: &str) -> Result<DateTime<Utc>, TokenError> {
+    let (expires, _) = token.split_once('.').ok_or(TokenError::Malformed)?;
+    to_datetime(expires.parse().map_err(|_| TokenError::Malformed)?)
+}
+
+/// チャンネルを視聴・リレーしてよいか。限定公開でなければ何も確認しない
+/// secretは設定ファイルのtoken_secret
+pub async fn authorize(
+    channel: &Channel,
+    secret: Option<&str>,
+    token: Option<&str>,
+) -> Result<(), TokenError> {
+    let addr = match authorize_at(channel, secret, token, Utc::now())? {
+        Verdict::Authorized => return Ok(()),
+        Verdict::AskUpstream(addr) => addr,
+    };
+    // AskUpstreamになるのはトークンがある時だけ
+    let token = token.unwrap_or_default();
+    let Ok(_permit) = channel.token_cache().checks.try_acquire() else {
+        debug!("too many upstream token checks CID:{}", channel.id());
+        return Err(TokenError::Upstream);
+    };
+    let result = verify_upstream(addr, channel.id(), token).await;
+    debug!(
+        "verified token upstream {addr} CID:{}: {result:?}",
+        channel.id()
+    );
+    channel.token_cache().insert(token, result, Utc::now());
+    result
+}
+
+/// tipに繋ぐ時に渡されたトークンを下流の確認にも使うか決める
+/// 既に限定公開か、上流がトークン無しでは断る時だけ覚えて限定公開にする
+pub async fn keep_relay_token(channel: &Channel, upstream: SocketAddr, token: &str) {
+    if channel.is_private() {
+        channel.set_access_token(Some(token.into()));
+        return;
+    }
+    match verify_upstream(upstream, channel.id(), "").await {
+        Err(TokenError::InvalidSignature) => channel.set_access_token(Some(token.into())),
+        r => debug!("upstream {upstream} does not require token: {r:?}"),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Authorized,
+    /// 手元では分からないので上流に確認する
+    AskUpstream(SocketAddr),
+}
+
+fn authorize_at(
+    channel: &Channel,
+    secret: Option<&str>,
+    token: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Verdict, TokenError> {
+    if !channel.is_private() {
+        return Ok(Verdict::Authorized);
+    }
+    let token = token.filter(|t| !t.is_empty()).ok_or(TokenError::Missing)?;
+    match channel.channel_type() {
+        ChannelType::Broadcast => {
+            // 鍵が無ければまだ一度も発行していない
+            let secret = secret.ok_or(TokenError::InvalidSignature)?;
+            TokenSigner::new(secret)
+                .verify_at(channel.id(), token, now)
+                .map(|_| Verdict::Authorized)
+        }
+        ChannelType::Relay => {
+            if expires_at(token)? <= now {
+                return Err(TokenError::Expired);
+            }
+            if channel.access_token().as_deref() == Some(token) {
+                return Ok(Verdict::Authorized);
+            }
+            if let Some(result) = channel.token_cache().get(token, now) {
+                return result.map(|_| Verdict::Authorized);
+            }
+            channel
+                .upstream_addr()
+                .map(Verdict::AskUpstream)
+                .ok_or(TokenError::InvalidSignature)
+        }
+    }
+}
+
+// 上流も限定公開のリレーなら、そこから更に上流へ確認してもらう
+async fn verify_upstream(
+    addr: SocketAddr,
+    channel_id: GnuId,
+    token: &str,
+) -> Result<(), TokenError> {
+    let url = format!(
+        "http://{addr}/token/{channel_id}?auth={}",
+        urlencoding::encode(token)
+    );
+    let client = reqwest::Client::builder()
+        .user_agent(crate::PKG_AGENT)
+        .timeout(UPSTREAM_TIMEOUT)
+        .build()
+        .map_err(|_| TokenError::Upstream)?;
+    match client.get(url).send().await {
+        Ok(res) if res.status().is_success() => Ok(()),
+        Ok(res) if res.status() == reqwest::StatusCode::FORBIDDEN => {
+            Err(TokenError::InvalidSignature)
+        }
+        Ok(res) => {
+            debug!("unexpected status from {addr}: {}", res.status());
+            Err(TokenError::Upstream)
+        }
+        Err(e) => {
+            debug!("failed to verify token at {addr}: {e}");
+            Err(TokenError::Upstream)
+        }
+    }
+}
+
+/// 上流に確認したトークンの結果
+/// 通ったものはトークンの有効期限まで、断られたものは少しの間だけ覚えておく
+/// 上流に繋がらなかった時は覚えない
+#[derive(Debug)]
+pub struct TokenCache {
+    entries: Mutex<HashMap<String, (Result<(), TokenError>, DateTime<Utc>)>>,
+    checks: Semaphore,
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        Self {
+            entries: Default::default(),
+            checks: Semaphore::new(MAX_UPSTREAM_CHECKS),
+        }
+    }
+}
+
+impl TokenCache {
+    fn get(&self, token: &str, now: DateTime<Utc>) -> Option<Result<(), TokenError>> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(token)
+            .filter(|(_, until)| now < *until)
+            .map(|(result, _)| *result)
+    }
+
+    fn insert(&self, token: &str, result: Result<(), TokenError>, now: DateTime<Utc>) {
+        let until = match result {
+            Ok(()) => expires_at(token).ok(),
+            Err(TokenError::Upstream) => None,
+            Err(_) => now.checked_add_signed(TimeDelta::seconds(REJECTED_CACHE_SECS)),
+        };
+        let Some(until) = until else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, until)| now < *until);
+        // 一杯なら新しいトークンは覚えずに毎回確認する
+        if entries.len() >= MAX_CACHED_TOKENS && !entries.contains_key(token) {
+            return;
+        }
+        entries.insert(token.to_string(), (result, until));
+    }
+}
+
+fn to_datetime(secs: i64) -> Result<DateTime<Utc>, TokenError> {
+    Utc.timestamp_opt(secs, 0)
+        .single()
+        .ok_or(TokenError::Malformed)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod t {
+    use chrono::Duration;
+
+    use crate::pcp::ChannelManager;
+
+    use super::*;
+
+    #[test]
+    fn test_token_signer() {
+        let signer = TokenSigner::new("secret");
+        let id = GnuId::new();
+        let now = Utc::now();
+        let token = signer.issue(id, now + Duration::hours(1));
+
+        assert_eq!(
+            signer.verify_at(id, &token, now).unwrap().timestamp(),
+            (now + Duration::hours(1)).timestamp()
+        );
+        assert_eq!(
+            signer.verify_at(id, &token, now + Duration::hours(2)),
+            Err(TokenError::Expired)
+        );
+        // 別のチャンネル・別の鍵・有効期限の書き換え
+        assert_eq!(
+            signer.verify_at(GnuId::new(), &token, now),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            TokenSigner::new("other").verify_at(id, &token, now),
+            Err(TokenError::InvalidSignature)
+        );
+        let (_, sig) = token.split_once('.').unwrap();
+        let forged = format!("{}.{sig}", (now + Duration::days(365)).timestamp());
+        assert_eq!(
+            signer.verify_at(id, &forged, now),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(signer.verify_at(id, "abc", now), Err(TokenError::Malformed));
+        assert_eq!(
+            signer.verify_at(id, "1.xyz", now),
+            Err(TokenError::Malformed)
+        );
+    }
+
+    #[crate::test]
+    async fn test_authorize() {
+        let manager = ChannelManager::new(&GnuId::new());
+        let now = Utc::now();
+        let signer = TokenSigner::new("secret");
+
+        let ch = manager
+            .create(GnuId::new(), ChannelType::Broadcast, None, None)
+            .unwrap();
+        assert_eq!(authorize_at(&ch, None, None, now), Ok(Verdict::Authorized));
+        ch.set_private(true);
+        let token = signer.issue(ch.id(), now + Duration::hours(1));
+        assert_eq!(
+            authorize_at(&ch, Some("secret"), None, now),
+            Err(TokenError::Missing)
+        );
+        assert_eq!(
+            authorize_at(&ch, Some("secret"), Some(&token), now),
+            Ok(Verdict::Authorized)
+        );
+        assert_eq!(
+            authorize_at(&ch, None, Some(&token), now),
+            Err(TokenError::InvalidSignature)
+        );
+
+        // リレーは上流に渡したトークンを通し、それ以外は上流に確認する
+        let relay = manager
+            .create(GnuId::new(), ChannelType::Relay, None, None)
+            .unwrap();
+        let token = signer.issue(relay.id(), now + Duration::hours(1));
+        relay.set_access_token(Some(token.clone()));
+        assert!(relay.is_private());
+        assert_eq!(
+            authorize_at(&relay, None, Some(&token), now),
+            Ok(Verdict::Authorized)
+        );
+        let other = signer.issue(relay.id(), now + Duration::hours(2));
+        // 上流が分からなければ確認できない
+        assert_eq!(
+            authorize_at(&relay, None, Some(&other), now),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            authorize_at(&relay, None, Some(&token), now + Duration::hours(2)),
+            Err(TokenError::Expired)
+        );
+
+        // 確認した結果は覚えておく
+        let cache = relay.token_cache();
+        cache.insert(&other, Ok(()), now);
+        assert_eq!(
+            authorize_at(&relay, None, Some(&other), now),
+            Ok(Verdict::Authorized)
+        );
+        let rejected = signer.issue(relay.id(), now + Duration::hours(3));
+        cache.insert(&rejected, Err(TokenError::InvalidSignature), now);
+        assert_eq!(
+            authorize_at(&relay, None, Some(&rejected), now),
+            Err(TokenError::InvalidSignature)
+        );
+        assert_eq!(
+            cache.get(&rejected, now + Duration::seconds(REJECTED_CACHE_SECS)),
+            None
+        );
+        // 繋がらなかった結果は覚えない
+        let unreachable = signer.issue(relay.id(), now + Duration::hours(4));
+        cache.insert(&unreachable, Err(TokenError::Upstream), now);
+        assert_eq!(cache.get(&unreachable, now), None);
+
+        // 覚えておく数には上限がある
+        for i in 0..MAX_CACHED_TOKENS {
+            let token = signer.issue(
+                relay.id(),
+                now + Duration::hours(5) + Duration::seconds(i as i64),
+            );
+            cache.insert(&token, Ok(()), now);
+        }
+        let overflow = signer.issue(relay.id(), now + Duration::hours(6));
+        cache.insert(&overflow, Ok(()), now);
+        assert_eq!(cache.get(&overflow, now), None);
+        assert_eq!(cache.entries.lock().unwrap().len(), MAX_CACHED_TOKENS);
+    }
+}
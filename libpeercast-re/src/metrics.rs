@@ -0,0 +1,154 @@
+//! Prometheus向けのメトリクス
+//!
+//! カウンターは発生した所で増やし、接続数やチャンネル数のような現在値はgather()の時に数える
+//! プロセスの情報(CPU時間、メモリ、fd)はprometheusのprocess featureでデフォルトのレジストリに入る
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter_vec, register_int_gauge_vec, Encoder, IntCounter, IntCounterVec,
+    IntGaugeVec, TextEncoder,
+};
+use tracing::error;
+
+use crate::{
+    connection_registry::ConnectionRegistry,
+    error::HandshakeError,
+    pcp::{ChannelManager, GnuId, TaskStatus},
+};
+
+static HANDSHAKE_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "peercast_handshake_failures_total",
+        "Number of failed PCP handshakes",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+static YP_ANNOUNCES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "peercast_yp_announces_total",
+        "Number of channel announces to (or from) YP",
+        &["result"]
+    )
+    .unwrap()
+});
+
+static CHANNEL_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "peercast_channel_bytes_total",
+        "Bytes transferred per channel",
+        &["channel_id", "direction"]
+    )
+    .unwrap()
+});
+
+static CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "peercast_connections",
+        "Number of live connections",
+        &["protocol"]
+    )
+    .unwrap()
+});
+
+static CHANNELS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("peercast_channels", "Number of channels", &["status"]).unwrap()
+});
+
+pub fn handshake_failed(e: &HandshakeError) {
+    HANDSHAKE_FAILURES.with_label_values(&[e.kind()]).inc();
+}
+
+pub fn yp_announced(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    YP_ANNOUNCES.with_label_values(&[result]).inc();
+}
+
+/// (in, out)のカウンター。接続側で持っておいて使う
+pub(crate) fn channel_bytes(channel_id: &GnuId) -> (IntCounter, IntCounter) {
+    let id = channel_id.to_string();
+    (
+        CHANNEL_BYTES.with_label_values(&[&id, "in"]),
+        CHANNEL_BYTES.with_label_values(&[&id, "out"]),
+    )
+}
+
+pub(crate) fn remove_channel(channel_id: &GnuId) {
+    let id = channel_id.to_string();
+    for direction in ["in", "out"] {
+        let _ = CHANNEL_BYTES.remove_label_values(&[&id, direction]);
+    }
+}
+
+fn status_label(status: TaskStatus) -> &'static str {
+    match status {
+        TaskStatus::Init => "init",
+        TaskStatus::Searching { .. } => "searching",
+        TaskStatus::Receiving => "receiving",
+        TaskStatus::Idle => "idle",
+        TaskStatus::Finish => "finish",
+        TaskStatus::Error => "error",
+    }
+}
+
+/// チャンネルの状態毎の数を更新する
+pub fn observe_channels(channel_manager: &ChannelManager) {
+    let statuses = channel_manager.map_collect(|(_, ch)| status_label(ch.status()));
+    CHANNELS.reset();
+    for status in statuses {
+        CHANNELS.with_label_values(&[status]).inc();
+    }
+}
+
+/// プロトコル毎の接続数を更新する
+pub fn observe_connections(connections: &ConnectionRegistry) {
+    CONNECTIONS.reset();
+    for conn in connections.list() {
+        CONNECTIONS
+            .with_label_values(&[conn.protocol.as_str()])
+            .inc();
+    }
+}
+
+/// テキスト形式で全メトリクスを返す
+pub fn gather() -> String {
+    let mut buf = vec![];
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buf) {
+        error!("failed to encode metrics: {e}");
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+#[cfg(test)]
+mod t {
+    use crate::connection_registry::Protocol;
+
+    use super::*;
+
+    #[test]
+    fn test_gather() {
+        let remote = "192.168.0.2:7144".parse().unwrap();
+        let connections = ConnectionRegistry::new();
+        let handle = connections.register(crate::ConnectionId::new(), Protocol::RtmpPlayer, remote);
+        let channel_id = GnuId::new();
+        handle.set_channel(channel_id);
+        handle.add_bytes_out(1234);
+        handshake_failed(&HandshakeError::Timeout);
+        yp_announced(true);
+
+        observe_connections(&connections);
+        let text = gather();
+        assert!(text.contains(r#"peercast_connections{protocol="rtmp_player"} 1"#));
+        assert!(text.contains(r#"peercast_handshake_failures_total{kind="timeout"}"#));
+        assert!(text.contains(r#"peercast_yp_announces_total{result="success"}"#));
+        assert!(text.contains(&format!(
+            r#"peercast_channel_bytes_total{{channel_id="{channel_id}",direction="out"}} 1234"#
+        )));
+
+        remove_channel(&channel_id);
+        assert!(!gather().contains(&channel_id.to_string()));
+    }
+}
@@ -6,6 +6,7 @@ mod pcp_channel;
 mod pcp_channel_info;
 mod pcp_helo;
 mod pcp_host;
+mod pcp_message;
 mod pcp_ping_pong;
 mod pcp_quit;
 mod pcp_track_info;
@@ -22,7 +23,8 @@ pub use pcp_broadcast::{BroadcastGroup, PcpBroadcast};
 pub use pcp_channel::PcpChannel;
 pub use pcp_channel_info::PcpChannelInfo;
 pub use pcp_helo::PcpHelo;
-pub use pcp_host::PcpHost;
+pub use pcp_host::{HostFlags1, PcpHost};
+pub use pcp_message::PcpMessage;
 pub use pcp_ping_pong::{PcpPing, PcpPong};
 pub use pcp_quit::PcpQuit;
 pub use pcp_track_info::PcpTrackInfo;
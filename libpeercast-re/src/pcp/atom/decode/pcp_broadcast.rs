@@ -5,8 +5,8 @@ use crate::{
     error::AtomParseError,
     pcp::{
         atom::decode::{
-            decode_bytes, decode_gnuid, decode_i16, decode_i32, decode_u16, decode_u32, decode_u8,
-            PcpChannel,
+            decode_bytes, decode_gnuid, decode_i16, decode_i32, decode_string, decode_u16,
+            decode_u32, decode_u8, PcpChannel, PcpMessage,
         },
         Atom, GnuId, Id4,
     },
@@ -21,6 +21,8 @@ impl BroadcastGroup {
     pub const TO_ROOT: BroadcastGroup = BroadcastGroup(0x01);
     pub const TO_TRACKERS: BroadcastGroup = BroadcastGroup(0x02);
     pub const TO_RELAYS: BroadcastGroup = BroadcastGroup(0x04);
+    /// チャンネルのツリー全体(トラッカーとリレー)
+    pub const TO_TREE: BroadcastGroup = BroadcastGroup(0x06);
 
     fn has(&self, other: &BroadcastGroup) -> bool {
         (self.0 & other.0) != 0
@@ -37,6 +39,9 @@ impl BroadcastGroup {
     pub fn has_relays(&self) -> bool {
         self.has(&Self::TO_RELAYS)
     }
+    pub fn value(&self) -> u8 {
+        self.0
+    }
 }
 
 impl From<u8> for BroadcastGroup {
@@ -61,6 +66,8 @@ pub struct PcpBroadcast {
     pub channel_packet: Option<PcpChannel>,
     //
     pub host: Option<PcpHost>,
+    //
+    pub message: Option<PcpMessage>,
 }
 
 impl PcpBroadcast {
@@ -83,6 +90,11 @@ impl PcpBroadcast {
                     Id4::PCP_BCST_VERSION_EX_PREFIX => p.version_ex_prefix = Some(decode_bytes(a)?), // Bytes
                     Id4::PCP_BCST_CHANID => p.channel_id = Some(decode_gnuid(a)?),
                     Id4::PCP_BCST_GROUP => p.broadcast_group = Some(decode_u8(a)?.into()),
+                    Id4::PCP_MESG => {
+                        p.message = Some(PcpMessage {
+                            text: decode_string(a)?,
+                        })
+                    }
                     _ => {
                         warn!("unkown atom arrived :{:?}", &a)
                     }
@@ -91,6 +103,7 @@ impl PcpBroadcast {
                 match a.id() {
                     Id4::PCP_CHAN => p.channel_packet = Some(PcpChannel::parse(a)?),
                     Id4::PCP_HOST => p.host = Some(PcpHost::parse(a)?),
+                    Id4::PCP_MESG => p.message = Some(PcpMessage::parse(a)?),
                     _ => {
                         warn!("unkown atom arrived :{:?}", &a)
                     }
@@ -100,11 +113,48 @@ impl PcpBroadcast {
 
         Ok(p)
     }
+
+    /// 次に流すためにTTLを1減らしてHOPSを1増やしたAtomを返す
+    /// TTLが尽きた時と、リレーにもトラッカーにも宛てられていない時は流さない
+    pub fn forwarded(&self, atom: &Atom) -> Option<Atom> {
+        let group = self.broadcast_group.as_ref()?;
+        if !(group.has_relays() || group.has_trackers()) {
+            return None;
+        }
+        let ttl = self.ttl?.saturating_sub(1);
+        if ttl == 0 {
+            return None;
+        }
+        let hops = self.hops.unwrap_or(0).saturating_add(1);
+
+        let mut atom = atom.clone();
+        let parent = atom.as_parent_mut();
+        let mut has_hops = false;
+        for child in parent.childs_mut() {
+            match child.id() {
+                Id4::PCP_BCST_TTL => *child = Atom::Child((Id4::PCP_BCST_TTL, ttl).into()),
+                Id4::PCP_BCST_HOPS => {
+                    *child = Atom::Child((Id4::PCP_BCST_HOPS, hops).into());
+                    has_hops = true;
+                }
+                _ => {}
+            }
+        }
+        if !has_hops {
+            parent.push_child(Atom::Child((Id4::PCP_BCST_HOPS, hops).into()));
+        }
+        Some(atom)
+    }
 }
 
 #[cfg(test)]
 mod t {
-    use super::BroadcastGroup;
+    use crate::pcp::{
+        builder::{BroadcastBuilder, MessageBuilder},
+        GnuId,
+    };
+
+    use super::{BroadcastGroup, PcpBroadcast};
 
     #[test]
     fn test_groups() {
@@ -125,5 +175,50 @@ mod t {
         assert_eq!(g.has_root(), false);
         assert_eq!(g.has_trackers(), false);
         assert_eq!(g.has_relays(), true);
+
+        let g = BroadcastGroup::TO_TREE;
+        assert_eq!(g.is_all(), false);
+        assert_eq!(g.has_root(), false);
+        assert_eq!(g.has_trackers(), true);
+        assert_eq!(g.has_relays(), true);
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let session_id = GnuId::new();
+        let channel_id = GnuId::new();
+        let atom = BroadcastBuilder::to_tree_builder(session_id, channel_id)
+            .add(MessageBuilder::new("hello").build())
+            .build();
+
+        let bcst = PcpBroadcast::parse(&atom).unwrap();
+        assert_eq!(bcst.from_id, Some(session_id));
+        assert_eq!(bcst.channel_id, Some(channel_id));
+        assert_eq!(bcst.ttl, Some(11));
+        assert_eq!(bcst.broadcast_group.unwrap().value(), 0x06);
+        assert_eq!(bcst.message.unwrap().text, "hello");
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let atom = BroadcastBuilder::to_tree_builder(GnuId::new(), GnuId::new())
+            .add(MessageBuilder::new("hello").build())
+            .build();
+        let bcst = PcpBroadcast::parse(&atom).unwrap();
+        let forwarded = bcst.forwarded(&atom).unwrap();
+        let next = PcpBroadcast::parse(&forwarded).unwrap();
+        assert_eq!((next.ttl, next.hops), (Some(10), Some(1)));
+        assert_eq!(next.message.unwrap().text, "hello");
+
+        // TTLが尽きたら流さない
+        let mut bcst = PcpBroadcast::parse(&forwarded).unwrap();
+        bcst.ttl = Some(1);
+        assert!(bcst.forwarded(&forwarded).is_none());
+
+        // ルート宛ては下流に流さない
+        let atom = BroadcastBuilder::to_yp_builder(GnuId::new(), GnuId::new()).build();
+        let mut bcst = PcpBroadcast::parse(&atom).unwrap();
+        bcst.ttl = Some(11);
+        assert!(bcst.forwarded(&atom).is_none());
     }
 }
@@ -0,0 +1,94 @@
+use encoding_rs::SHIFT_JIS;
+use tracing::warn;
+
+use crate::{
+    error::AtomParseError,
+    pcp::{atom::decode::decode_string, Atom, ChildAtom, Id4},
+};
+
+// メッセージのデータ構造
+// Parent(PCP_MESG, vec[
+//  Child(PCP_MESG_ASCII, utf-8),  // 古いPeerCastはasciiだが今はutf-8
+//  Child(PCP_MESG_SJIS, sjis),
+// ])
+// Child(PCP_MESG, utf-8) で来ることもある
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PcpMessage {
+    pub text: String,
+}
+
+impl PcpMessage {
+    #[tracing::instrument]
+    pub fn parse(atom: &Atom) -> Result<PcpMessage, AtomParseError> {
+        if atom.id() != Id4::PCP_MESG {
+            return Err(AtomParseError::ValueError);
+        }
+        if atom.is_child() {
+            let text = decode_string(atom.as_child())?;
+            return Ok(PcpMessage { text });
+        }
+
+        let mut text = None;
+        for a in atom.as_parent().childs() {
+            if a.is_child() {
+                let a = a.as_child();
+                match a.id() {
+                    Id4::PCP_MESG_ASCII => text = Some(decode_string(a)?),
+                    // utf-8の方を優先する
+                    Id4::PCP_MESG_SJIS if text.is_none() => text = Some(decode_sjis(a)?),
+                    Id4::PCP_MESG_SJIS => {}
+                    _ => {
+                        warn!("unkown atom arrived :{:?}", &a)
+                    }
+                }
+            } else {
+                warn!("unkown atom arrived :{:?}", &a)
+            }
+        }
+
+        text.map(|text| PcpMessage { text })
+            .ok_or(AtomParseError::NotFoundValue)
+    }
+}
+
+/// Payload(Shift_JIS[u8; X]) to String
+fn decode_sjis(atom: &ChildAtom) -> Result<String, AtomParseError> {
+    let b = atom.payload();
+    let b = b.strip_suffix(b"\0").unwrap_or(&b[..]);
+    let (s, _, had_errors) = SHIFT_JIS.decode(b);
+    if had_errors {
+        warn!("invalid Shift_JIS message");
+    }
+    Ok(s.into_owned())
+}
+
+#[cfg(test)]
+mod t {
+    use bytes::Bytes;
+
+    use crate::pcp::{builder::MessageBuilder, Atom, ChildAtom, Id4, ParentAtom};
+
+    use super::PcpMessage;
+
+    #[test]
+    fn test_parse() {
+        let atom = MessageBuilder::new("こんにちは").build();
+        assert_eq!(PcpMessage::parse(&atom).unwrap().text, "こんにちは");
+
+        // "テスト" in Shift_JIS
+        let sjis = Bytes::from_static(b"\x83\x65\x83\x58\x83\x67\0");
+        let atom: Atom = ParentAtom::from((
+            Id4::PCP_MESG,
+            vec![ChildAtom::from((Id4::PCP_MESG_SJIS, sjis)).into()],
+        ))
+        .into();
+        assert_eq!(PcpMessage::parse(&atom).unwrap().text, "テスト");
+
+        let atom: Atom = ChildAtom::from((Id4::PCP_MESG, String::from("hello"))).into();
+        assert_eq!(PcpMessage::parse(&atom).unwrap().text, "hello");
+
+        let atom: Atom = ParentAtom::from((Id4::PCP_MESG, vec![])).into();
+        assert!(PcpMessage::parse(&atom).is_err());
+    }
+}
@@ -46,6 +46,29 @@ impl PcpTrackInfo {
         Ok(i)
     }
 
+    /// PCP_CHAN_PKT_METAのPayload(ICYのStreamTitle='...';StreamUrl='...';)を読む
+    /// PeerCastと同じくStreamTitleをtitle, StreamUrlをurlにする
+    pub fn parse_icy_metadata(payload: &[u8]) -> Option<PcpTrackInfo> {
+        let s = String::from_utf8_lossy(payload);
+        let mut rest = s.trim_end_matches('\0');
+
+        let mut i = PcpTrackInfo::default();
+        while let Some((key, value)) = rest.split_once("='") {
+            // 最後の項目は;が無いことがある
+            let (value, next) = value
+                .split_once("';")
+                .unwrap_or((value.trim_end_matches('\''), ""));
+            match key.trim() {
+                "StreamTitle" => i.title = Some(value.to_string()),
+                "StreamUrl" => i.url = Some(value.to_string()),
+                _ => {}
+            }
+            rest = next;
+        }
+
+        (i.title.is_some() || i.url.is_some()).then_some(i)
+    }
+
     pub fn merge_ref(&mut self, other: &Self) -> bool {
         merge_ref!(self, other, [title, creator, url, album, genre])
     }
@@ -56,3 +79,27 @@ impl PcpTrackInfo {
     getter!(&self, album);
     getter!(&self, genre);
 }
+
+#[cfg(test)]
+mod t {
+    use super::PcpTrackInfo;
+
+    #[test]
+    fn test_parse_icy_metadata() {
+        let i = PcpTrackInfo::parse_icy_metadata(
+            b"StreamTitle='Artist - It's a title';StreamUrl='http://example.com/';\0\0\0",
+        )
+        .unwrap();
+        assert_eq!(i.title.as_deref(), Some("Artist - It's a title"));
+        assert_eq!(i.url.as_deref(), Some("http://example.com/"));
+        assert_eq!(i.creator, None);
+
+        let i = PcpTrackInfo::parse_icy_metadata("StreamTitle='曲名'".as_bytes()).unwrap();
+        assert_eq!(i.title.as_deref(), Some("曲名"));
+        assert_eq!(i.url, None);
+
+        assert!(PcpTrackInfo::parse_icy_metadata(b"").is_none());
+        assert!(PcpTrackInfo::parse_icy_metadata(b"\0\0").is_none());
+        assert!(PcpTrackInfo::parse_icy_metadata(b"\x01\x02binary").is_none());
+    }
+}
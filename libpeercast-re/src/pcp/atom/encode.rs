@@ -65,6 +65,13 @@ impl From<(Id4, u32)> for ChildAtom {
         Self::new(id, &payload)
     }
 }
+impl From<(Id4, Id4)> for ChildAtom {
+    fn from((id, value): (Id4, Id4)) -> Self {
+        let payload = Bytes::copy_from_slice(&<[u8; 4]>::from(value)); // そのままの並び(BE)
+        Self::new(id, &payload)
+    }
+}
+
 impl From<(Id4, GnuId)> for ChildAtom {
     fn from((id, gnu_id): (Id4, GnuId)) -> Self {
         let value_u128: u128 = gnu_id.0;
@@ -13,6 +13,8 @@ pub struct BroadcastBuilder {
     channel_id: GnuId,
     //
     broadcast_group: BroadcastGroup,
+    //
+    atoms: Vec<Atom>,
 }
 
 impl BroadcastBuilder {
@@ -29,10 +31,17 @@ impl BroadcastBuilder {
             from_session_id,
             channel_id,
             broadcast_group,
+            atoms: vec![],
         }
     }
 
-    fn build(mut self) -> Atom {
+    /// BCSTで運ぶAtomを追加する
+    pub fn add(mut self, atom: Atom) -> Self {
+        self.atoms.push(atom);
+        self
+    }
+
+    pub fn build(mut self) -> Atom {
         let mut vec = vec![];
         vec.push(Atom::Child((Id4::PCP_BCST_TTL, self.ttl).into()));
         vec.push(Atom::Child((Id4::PCP_BCST_HOPS, self.hops).into()));
@@ -68,6 +77,10 @@ impl BroadcastBuilder {
         // bcst.SetBcstVersionEXNumber(ServantVersionEXNumber);
 
         vec.push(Atom::Child((Id4::PCP_BCST_CHANID, self.channel_id).into()));
+        vec.push(Atom::Child(
+            (Id4::PCP_BCST_GROUP, self.broadcast_group.value()).into(),
+        ));
+        vec.extend(self.atoms);
 
         // bcst.SetBcstFrom(channel.PeerCast.SessionID);
         // bcst.SetBcstChannelID(channel.ChannelID);
@@ -81,6 +94,16 @@ impl BroadcastBuilder {
     pub fn to_yp_builder(session_id: GnuId, channel_id: GnuId) -> BroadcastBuilder {
         BroadcastBuilder::new(1, 0, session_id, channel_id, BroadcastGroup::TO_ROOT)
     }
+
+    /// 自分のHOSTを上流のトラッカーに伝える時に利用する
+    pub fn to_trackers_builder(session_id: GnuId, channel_id: GnuId) -> BroadcastBuilder {
+        BroadcastBuilder::new(11, 0, session_id, channel_id, BroadcastGroup::TO_TRACKERS)
+    }
+
+    /// チャンネルのツリー全体に流す時に利用する
+    pub fn to_tree_builder(session_id: GnuId, channel_id: GnuId) -> BroadcastBuilder {
+        BroadcastBuilder::new(11, 0, session_id, channel_id, BroadcastGroup::TO_TREE)
+    }
 }
 
 // // トラッカーである自分からYPへの通知。
@@ -8,19 +8,107 @@ use bytes::Buf;
 use tracing::warn;
 use tracing_subscriber::field::debug;
 
-use crate::pcp::{session::Session, Atom, GnuId, Id4};
+use crate::{
+    pcp::{decode::HostFlags1, session::Session, Atom, GnuId, Id4},
+    PKG_SERVANT_VERSION, PKG_SERVANT_VERSION_EX_NUMBER, PKG_SERVANT_VERSION_EX_PREFIX,
+    PKG_SERVANT_VERSION_VP,
+};
 
-pub struct HostBuilder {}
+/// PCP_HOSTを作る。503で次の接続先を教える時や、BCSTで自分の状態を伝える時に使う
+pub struct HostBuilder {
+    channel_id: GnuId,
+    session_id: Option<GnuId>,
+    global_address: SocketAddr,
+    local_address: Option<SocketAddr>,
+    listener_count: u32,
+    relay_count: u32,
+    uptime: u32,
+    flags1: HostFlags1,
+}
 
 impl HostBuilder {
-    pub fn new() -> Self {
-        HostBuilder {}
+    pub fn new(channel_id: GnuId, global_address: SocketAddr) -> Self {
+        HostBuilder {
+            channel_id,
+            session_id: None,
+            global_address,
+            local_address: None,
+            listener_count: 0,
+            relay_count: 0,
+            uptime: 0,
+            flags1: HostFlags1::NONE,
+        }
+    }
+
+    /// 分からない時は付けない
+    pub fn session_id(mut self, session_id: GnuId) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+
+    /// 省略するとglobal_addressと同じ
+    pub fn local_address(mut self, local_address: SocketAddr) -> Self {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    pub fn count(mut self, listeners: u32, relays: u32) -> Self {
+        self.listener_count = listeners;
+        self.relay_count = relays;
+        self
     }
 
-    pub fn build() -> Atom {
-        let addr = Ipv4Addr::new(127, 0, 0, 1);
-        let ip_u32: u32 = addr.into();
-        todo!()
+    /// 秒
+    pub fn uptime(mut self, uptime: u32) -> Self {
+        self.uptime = uptime;
+        self
+    }
+
+    pub fn flags1(mut self, flags1: HostFlags1) -> Self {
+        self.flags1 = flags1;
+        self
+    }
+
+    pub fn build(&self) -> Atom {
+        let local_address = self.local_address.unwrap_or(self.global_address);
+        let mut vec = vec![];
+        vec.push(Atom::Child((Id4::PCP_HOST_CHANID, self.channel_id).into()));
+        if let Some(session_id) = self.session_id {
+            vec.push(Atom::Child((Id4::PCP_HOST_ID, session_id).into()));
+        }
+        // PeerCastと同じくグローバル、ローカルの順に並べる
+        for addr in [self.global_address, local_address] {
+            vec.push(Atom::Child((Id4::PCP_HOST_IP, addr.ip()).into()));
+            vec.push(Atom::Child((Id4::PCP_HOST_PORT, addr.port()).into()));
+        }
+        vec.push(Atom::Child(
+            (Id4::PCP_HOST_NUML, self.listener_count).into(),
+        ));
+        vec.push(Atom::Child((Id4::PCP_HOST_NUMR, self.relay_count).into()));
+        vec.push(Atom::Child((Id4::PCP_HOST_UPTIME, self.uptime).into()));
+        vec.push(Atom::Child(
+            (Id4::PCP_HOST_VERSION, PKG_SERVANT_VERSION).into(),
+        ));
+        vec.push(Atom::Child(
+            (Id4::PCP_HOST_VERSION_VP, PKG_SERVANT_VERSION_VP).into(),
+        ));
+        vec.push(Atom::Child(
+            (
+                Id4::PCP_HOST_VERSION_EX_PREFIX,
+                &PKG_SERVANT_VERSION_EX_PREFIX,
+            )
+                .into(),
+        ));
+        vec.push(Atom::Child(
+            (
+                Id4::PCP_HOST_VERSION_EX_NUMBER,
+                *PKG_SERVANT_VERSION_EX_NUMBER,
+            )
+                .into(),
+        ));
+        vec.push(Atom::Child((Id4::PCP_HOST_FLAGS1, self.flags1.0).into()));
+
+        Atom::Parent((Id4::PCP_HOST, vec).into())
     }
 }
 
@@ -197,3 +285,37 @@ fn get_2bytes(atom: &Atom) -> [u8; 2] {
     //FIXME: 良い書き方が分らん
     [b.get_u8(), b.get_u8()]
 }
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_host() {
+        let cid = GnuId::new();
+        let sid = GnuId::new();
+        let global: SocketAddr = "203.0.113.1:7144".parse().unwrap();
+        let local: SocketAddr = "192.168.0.2:7145".parse().unwrap();
+        let flags1 = HostFlags1::NONE.set_relay(true).set_recv(true);
+        let host = HostBuilder::new(cid, global)
+            .session_id(sid)
+            .local_address(local)
+            .count(3, 2)
+            .uptime(60)
+            .flags1(flags1)
+            .build();
+        let info = HostInfo::parse(&host);
+        assert_eq!(info.channel_id, Some(cid));
+        assert_eq!(info.session_id, sid);
+        assert_eq!(info.global_address, Some(global));
+        assert_eq!(info.local_address, Some(local));
+        assert_eq!((info.listener_count, info.relay_count), (3, 2));
+        assert_eq!(info.uptime, 60);
+        assert_eq!(info.flag1, flags1.0);
+
+        // 省略したらローカルもグローバルと同じ
+        let info = HostInfo::parse(&HostBuilder::new(cid, global).build());
+        assert_eq!(info.local_address, Some(global));
+        assert_eq!(info.flag1, 0);
+    }
+}
@@ -0,0 +1,17 @@
+use crate::pcp::{Atom, ChildAtom, Id4, ParentAtom};
+
+/// PCP_MESGを作る。文字コードはutf-8だけ
+pub struct MessageBuilder {
+    text: String,
+}
+
+impl MessageBuilder {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self { text: text.into() }
+    }
+
+    pub fn build(self) -> Atom {
+        let atoms = vec![ChildAtom::from((Id4::PCP_MESG_ASCII, self.text)).into()];
+        ParentAtom::from((Id4::PCP_MESG, atoms)).into()
+    }
+}
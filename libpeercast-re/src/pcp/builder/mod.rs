@@ -2,6 +2,7 @@ mod broadcast;
 mod channel_info;
 mod hello;
 mod host;
+mod message;
 mod ok;
 mod oleh;
 pub(self) mod parse_utils;
@@ -14,6 +15,7 @@ pub use broadcast::BroadcastBuilder;
 pub use channel_info::ChannelInfoBuilder;
 pub use hello::HelloBuilder;
 pub use host::{HostBuilder, HostInfo};
+pub use message::MessageBuilder;
 pub use ok::OkBuilder;
 pub use oleh::{OlehBuilder, OlehInfo};
 pub use ping_pong::{PingBuilder, PongBuilder};
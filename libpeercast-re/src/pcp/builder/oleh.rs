@@ -13,6 +13,7 @@ pub struct OlehBuilder {
     session_id: GnuId,
     remote_ip: IpAddr,
     remote_port: u16,
+    disable: bool,
 }
 
 #[allow(dead_code)]
@@ -26,9 +27,16 @@ impl OlehBuilder {
             session_id,
             remote_ip,
             remote_port,
+            disable: false,
         }
     }
 
+    /// BANしている相手に返す時
+    pub fn disable(mut self, disable: bool) -> Self {
+        self.disable = disable;
+        self
+    }
+
     pub fn build(&self) -> Atom {
         let mut vec = Vec::new();
         vec.push(Atom::AGENT.clone());
@@ -38,6 +46,9 @@ impl OlehBuilder {
         vec.push(Atom::VERSION.clone());
         vec.push(Atom::Child((Id4::PCP_HELO_REMOTEIP, self.remote_ip).into()));
         vec.push(Atom::Child((Id4::PCP_HELO_PORT, self.remote_port).into()));
+        if self.disable {
+            vec.push(Atom::Child((Id4::PCP_HELO_DISABLE, 1_i32).into()));
+        }
 
         Atom::Parent((Id4::PCP_OLEH, vec).into())
     }
@@ -14,6 +14,7 @@ pub enum QuitReason {
     NoHostOrOffAir,
     // NoHost, OffAir,を統合
     UserShutdown,
+    Banned,
 }
 
 pub struct QuitBuilder {
@@ -34,6 +35,7 @@ impl QuitBuilder {
             QuitReason::UnavailableError => QuitCode::UNAVAILABLE_ERROR,
             QuitReason::NoHostOrOffAir => QuitCode::NO_HOST_OR_OFFAIR,
             QuitReason::UserShutdown => QuitCode::USER_SHUTDOWN,
+            QuitReason::Banned => QuitCode::BANNED,
         };
 
         Atom::Child((Id4::PCP_QUIT, reason_u32).into())
@@ -56,6 +58,7 @@ impl QuitInfo {
             QuitCode::UNAVAILABLE_ERROR => QuitReason::UnavailableError,
             QuitCode::NO_HOST_OR_OFFAIR => QuitReason::NoHostOrOffAir,
             QuitCode::USER_SHUTDOWN => QuitReason::UserShutdown,
+            QuitCode::BANNED => QuitReason::Banned,
             _ => {
                 error!("Can't parse quit code. but NOT CATASTROPIC, return QuiteReason::Any");
                 QuitReason::Any
@@ -28,7 +28,10 @@ use tokio::{
 use tracing::{debug, error, trace, warn};
 
 use crate::{
-    codec::rtmp::flv::{self, TaggedData},
+    codec::rtmp::{
+        flv::{self, TaggedData},
+        tag_header::{AudioTagHeader, VideoTagHeader},
+    },
     pcp::{
         builder::{ChannelInfoBuilder, TrackInfoBuilder},
         classify::{self, ChanPktDataType},
@@ -223,7 +226,17 @@ impl BroadcastBrokerWoker {
             } => {
                 self.handle_data(atom, payload, pos, continuation);
             }
-            ChannelBrokerMessage::AtomBroadcast { direction, atom } => todo!(),
+            ChannelBrokerMessage::ArrivedChannelMeta {
+                atom,
+                payload,
+                pos,
+                track,
+            } => {
+                self.handle_meta(atom, payload, pos, track);
+            }
+            ChannelBrokerMessage::AtomBroadcast { direction, atom } => {
+                self.send_listener(ChannelMessage::BroadcastAtom { direction, atom })
+            }
             ChannelBrokerMessage::BroadcastEvent(event) => {
                 //
                 self.handle_rtmp_event(event)
@@ -330,6 +343,18 @@ impl BroadcastBrokerWoker {
         self.send_listener(msg)
     }
 
+    fn handle_meta(&self, atom: Atom, payload: Bytes, pos: u32, track: Option<TrackInfo>) {
+        if track.is_some() {
+            *self.track_info.write().unwrap() = track.clone();
+        }
+        self.send_listener(ChannelMessage::RelayChannelMeta {
+            atom,
+            pos,
+            payload,
+            track,
+        })
+    }
+
     // BroadcastTaskからRtmpに関するメッセージを受け取りAtomに変換してRecieverに送り出す
     fn handle_rtmp_event(&mut self, event: RtmpConnectionEvent) {
         // trace!(?event);
@@ -453,7 +478,7 @@ impl BroadcastBrokerWoker {
     }
 }
 
-fn create_atom(
+pub(crate) fn create_atom(
     broadcast_id: GnuId,
     chan_data_type: ChanPktDataType,
     info: Option<ChannelInfo>,
@@ -467,14 +492,14 @@ fn create_atom(
     let chan_pkt_childs: Vec<Atom> = match (&chan_data_type, continuation) {
         (&ChanPktDataType::Head, _) => {
             vec![
-                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_HEAD.0)).into(),
+                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_HEAD)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_POS, pos)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, data)).into(),
             ]
         }
         (&ChanPktDataType::Data, Some(true)) => {
             vec![
-                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_DATA.0)).into(),
+                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_DATA)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_POS, pos)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_CONTINUATION, 1_u8)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, data)).into(),
@@ -482,7 +507,14 @@ fn create_atom(
         }
         (&ChanPktDataType::Data, Some(false)) | (&ChanPktDataType::Data, None) => {
             vec![
-                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_DATA.0)).into(),
+                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_DATA)).into(),
+                ChildAtom::from((Id4::PCP_CHAN_PKT_POS, pos)).into(),
+                ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, data)).into(),
+            ]
+        }
+        (&ChanPktDataType::Meta, _) => {
+            vec![
+                ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_META)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_POS, pos)).into(),
                 ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, data)).into(),
             ]
@@ -503,7 +535,7 @@ fn create_atom(
             ))
             .into()
         }
-        ChanPktDataType::Data => {
+        ChanPktDataType::Data | ChanPktDataType::Meta => {
             //
             ParentAtom::from((
                 Id4::PCP_CHAN,
@@ -587,7 +619,7 @@ impl RtmpFlvnizer {
         }
 
         let mut magic_with_data = None;
-        if Self::is_avc_header(&data[0..5]) {
+        if Self::is_video_sequence_header(&data) {
             self.video_header = Some((timestamp, data.clone()));
             if !self.set_header() {
                 return None;
@@ -625,7 +657,7 @@ impl RtmpFlvnizer {
 
         let tagged_data = Self::flved(1, flv::DataType::AUDIO, timestamp, &data);
         let mut magic_with_data = None;
-        if Self::is_aac_header(&data[0..2]) {
+        if Self::is_audio_sequence_header(&data) {
             self.audio_header = Some((timestamp, data.clone()));
             if !self.set_header() {
                 return None;
@@ -731,54 +763,19 @@ impl RtmpFlvnizer {
     }
 
     // fn tag(data_type: Data) -> TaggedChunk {}
-    fn is_avc_header(head: &[u8]) -> bool {
-        trace!(is_avc_header=?head);
-        // FLV Video Tag [E.4.3 Video Tags, pp72]
-        // Field           ValueType
-        // FrameType       UB[4]
-        // CodecID         UB[4]
-        // AVCPacketType   if Codec ID == 7 UI8  // 1byte
-        // CompotionTime   if Codec ID == 7 SI24 // 3byte
-        // Field
-        let frame_type = (head[0] & 0xF0) >> 4;
-        let codec_id = (head[0] & 0x0F);
-        let avc_packet_type = if codec_id == 7 { Some(head[1]) } else { None };
-        let compotion_time = if codec_id == 7 {
-            let t = [0, head[2], head[3], head[4]].as_bytes().get_u32();
-            Some(t)
-        } else {
-            None
-        };
-
-        match avc_packet_type {
-            Some(0x00) => true,  // AVC seqence
-            Some(0x01) => false, // AVC NAUL
-            Some(0x02) => false, // AVC end sequence
-            Some(_) => false,
-            None => false,
-        }
+    // AVC/AACに加えて、Enhanced RTMPのHEVC/AV1/Opusなども判別する
+    fn is_video_sequence_header(data: &[u8]) -> bool {
+        let header = VideoTagHeader::read(data);
+        trace!(video_tag_header=?header);
+        header.is_some_and(|h| h.is_sequence_header())
     }
 
-    fn is_aac_header(head: &[u8]) -> bool {
-        match head[0] {
-            0xAF => {
-                //debug!("AAC,44 kHz,16bit samples,Stereo sound");
-            }
-            _ => {
-                debug!("AUDIO CODEC is unknown")
-            }
-        };
-        match head[1] {
-            0x00 => {
-                debug!("aac header");
-                true
-            }
-            0x01 => {
-                // trace!("aac packet");
-                false
-            }
-            _ => false,
+    fn is_audio_sequence_header(data: &[u8]) -> bool {
+        let header = AudioTagHeader::read(data);
+        if header.is_none() {
+            debug!("AUDIO CODEC is unknown")
         }
+        header.is_some_and(|h| h.is_sequence_header())
     }
 }
 
@@ -849,9 +846,94 @@ mod t {
         println!("{r:#?}");
     }
 
+    #[crate::test]
+    async fn test_broker_meta() {
+        let track_info = Arc::new(RwLock::new(None));
+        let broker = ChannelBroker::new(
+            ChannelType::Broadcast,
+            GnuId::new(),
+            Default::default(),
+            Arc::clone(&track_info),
+        );
+        let mut reciever = broker.channel_reciever(ConnectionId::new());
+
+        let payload = Bytes::from_static(b"StreamTitle='title';");
+        let atom = create_atom(
+            GnuId::new(),
+            ChanPktDataType::Meta,
+            None,
+            None,
+            0,
+            None,
+            &payload,
+        );
+        let track = TrackInfo {
+            title: "title".into(),
+            ..Default::default()
+        };
+        broker
+            .sender()
+            .send(ChannelBrokerMessage::ArrivedChannelMeta {
+                atom,
+                payload,
+                pos: 0,
+                track: Some(track),
+            });
+
+        let Some(ChannelMessage::RelayChannelMeta { track, .. }) = reciever.recv().await else {
+            panic!("meta must be relayed");
+        };
+        assert_eq!(track.unwrap().title, "title");
+        assert_eq!(track_info.read().unwrap().as_ref().unwrap().title, "title");
+    }
+
+    #[test]
+    fn test_create_atom_classify() {
+        let id = GnuId::new();
+        let data = Bytes::from_static(b"data");
+        let info = Some(ChannelInfo::new());
+        let track = Some(TrackInfo::new());
+
+        let atom = create_atom(id, ChanPktDataType::Head, info, track, 0, None, &data);
+        assert!(matches!(
+            classify::ClassifyAtom::classify(atom),
+            classify::ClassifyAtom::ChanPktHead { .. }
+        ));
+        let atom = create_atom(id, ChanPktDataType::Data, None, None, 4, Some(true), &data);
+        assert!(matches!(
+            classify::ClassifyAtom::classify(atom),
+            classify::ClassifyAtom::ChanPktData {
+                pos: 4,
+                continuation: Some(true),
+                ..
+            }
+        ));
+        let atom = create_atom(id, ChanPktDataType::Meta, None, None, 8, None, &data);
+        assert!(matches!(
+            classify::ClassifyAtom::classify(atom),
+            classify::ClassifyAtom::ChanPktMeta {
+                pos: 8,
+                track: None,
+                ..
+            }
+        ));
+    }
+
     #[crate::test]
     async fn test_channel_reciever() {
         assert_send::<ChannelReciever>();
         assert_sync::<ChannelReciever>();
     }
+
+    #[test]
+    fn test_flvnizer_sequence_header() {
+        // AVC/AAC
+        assert!(RtmpFlvnizer::is_video_sequence_header(&[0x17, 0x00, 0, 0, 0]));
+        assert!(!RtmpFlvnizer::is_video_sequence_header(&[0x17, 0x01, 0, 0, 0]));
+        assert!(RtmpFlvnizer::is_audio_sequence_header(&[0xAF, 0x00]));
+        // Enhanced RTMP: HEVC SequenceStart, AV1 CodedFrames, Opus SequenceStart
+        assert!(RtmpFlvnizer::is_video_sequence_header(b"\x90hvc1"));
+        assert!(!RtmpFlvnizer::is_video_sequence_header(b"\x91av01\x00"));
+        assert!(RtmpFlvnizer::is_audio_sequence_header(b"\x90Opus"));
+    }
 }
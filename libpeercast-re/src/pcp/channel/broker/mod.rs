@@ -17,6 +17,8 @@ use crate::{
 
 use self::{broadcast_broker::BroadcastBrokerWoker, relay_broker::RelayBrokerWorker};
 
+pub(crate) use broadcast_broker::create_atom;
+
 use super::{ChannelInfo, ChannelType, TrackInfo};
 
 //------------------------------------------------------------------------------
@@ -52,6 +54,12 @@ pub(crate) enum ChannelBrokerMessage {
         pos: u32,
         continuation: bool,
     },
+    ArrivedChannelMeta {
+        atom: Atom,
+        payload: Bytes,
+        pos: u32,
+        track: Option<TrackInfo>,
+    },
     AtomBroadcast {
         direction: AtomDirection,
         atom: Atom,
@@ -75,6 +83,18 @@ pub enum ChannelMessage {
         payload: Bytes,
         continuation: bool,
     },
+    /// trackはMETAから読み取れた場合だけ入っている
+    RelayChannelMeta {
+        atom: Atom,
+        pos: u32,
+        payload: Bytes,
+        track: Option<TrackInfo>,
+    },
+    /// BCSTをそのまま流す。上流/下流への転送はdirectionを見て各コネクションが決める
+    BroadcastAtom {
+        direction: AtomDirection,
+        atom: Atom,
+    },
     // AtomTrackerUpdate {
     //     info: Option<ChannelInfo>,
     //     track: Option<TrackInfo>,
@@ -95,7 +115,7 @@ pub enum ChannelMessage {
     // },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AtomDirection {
     UpToDown, // Upstream To Downstream
     DownToUp,
@@ -200,7 +200,15 @@ impl RelayBrokerWorker {
                 pos,
                 continuation,
             } => self.handle_arrived_channel_data(atom, pos, payload, continuation),
-            ChannelBrokerMessage::AtomBroadcast { direction, atom } => todo!(),
+            ChannelBrokerMessage::ArrivedChannelMeta {
+                atom,
+                payload,
+                pos,
+                track,
+            } => self.handle_arrived_channel_meta(atom, pos, payload, track),
+            ChannelBrokerMessage::AtomBroadcast { direction, atom } => {
+                self.send_listener(ChannelMessage::BroadcastAtom { direction, atom })
+            }
             ChannelBrokerMessage::BroadcastEvent(_) => todo!(),
         }
     }
@@ -295,6 +303,24 @@ impl RelayBrokerWorker {
         });
     }
 
+    fn handle_arrived_channel_meta(
+        &mut self,
+        atom: Atom,
+        pos: u32,
+        payload: Bytes,
+        track: Option<TrackInfo>,
+    ) {
+        if track.is_some() {
+            *self.track_info.write().unwrap() = track.clone();
+        }
+        self.send_listener(ChannelMessage::RelayChannelMeta {
+            atom,
+            pos,
+            payload,
+            track,
+        });
+    }
+
     /// brokerをlistenしているRelay, Readerにデータを配信する
     fn send_listener(&self, message: ChannelMessage) {
         for (id, sender) in &self.sender_by_connection_id {
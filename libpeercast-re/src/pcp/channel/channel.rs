@@ -2,7 +2,10 @@ use std::{
     collections::VecDeque,
     net::SocketAddr,
     pin::Pin,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
     time::SystemTime,
 };
 
@@ -18,15 +21,25 @@ use tokio::{
 use tracing::{debug, info, trace};
 
 use crate::{
-    pcp::{connection, GnuId},
+    bandwidth::UploadLimiter,
+    connection_registry::ConnectionRegistry,
+    event::{Event, EventBus},
+    listener_token::TokenCache,
+    pcp::{
+        builder::{BroadcastBuilder, MessageBuilder},
+        connection, GnuId,
+    },
+    util::util_mpsc::mpsc_send,
     ConnectionId,
 };
 
 use super::{
-    broker::ChannelBroker,
+    broker::{AtomDirection, ChannelBroker},
     channel_stream::ChannelStream,
-    src_task::{BroadcastTask, RelayTask, SourceTask, SourceTaskConfig, TaskStatus},
-    ChannelInfo, ChannelReciever, TrackInfo,
+    src_task::{
+        BroadcastTask, FileTask, HttpPullTask, RelayTask, SourceTask, SourceTaskConfig, TaskStatus,
+    },
+    ChannelBrokerMessage, ChannelInfo, ChannelReciever, TrackInfo,
 };
 
 //------------------------------------------------------------------------------
@@ -48,6 +61,12 @@ pub struct Channel {
     ch_type: ChannelType, // 作成したら状態は変わらないで良いともうけども・・・Configは変わる可能性あるか？
     channel_info: Arc<RwLock<Option<ChannelInfo>>>,
     track_info: Arc<RwLock<Option<TrackInfo>>>,
+    // 限定公開
+    private: Arc<AtomicBool>,
+    access_token: Arc<RwLock<Option<String>>>,
+    token_cache: Arc<TokenCache>,
+    // リレーの場合の接続先(tip)。知らないトークンの確認に使う
+    upstream_addr: Arc<RwLock<Option<SocketAddr>>>,
 
     // manager
     broker_task: Arc<ChannelBroker>,
@@ -62,6 +81,9 @@ pub struct Channel {
     hosts_tested: Arc<RwLock<Vec<SocketAddr>>>,
 
     //
+    events: EventBus,
+    connections: Arc<ConnectionRegistry>,
+    upload_limiter: Arc<UploadLimiter>,
     created_at: DateTime<Utc>,
 }
 
@@ -72,6 +94,9 @@ impl Channel {
         ch_type: ChannelType,
         channel_info: Option<ChannelInfo>,
         track_info: Option<TrackInfo>,
+        events: EventBus,
+        connections: Arc<ConnectionRegistry>,
+        upload_limiter: Arc<UploadLimiter>,
     ) -> Self {
         let channel_info = Arc::new(RwLock::new(channel_info));
         let track_info = Arc::new(RwLock::new(track_info));
@@ -88,6 +113,10 @@ impl Channel {
             )),
             channel_info,
             track_info,
+            private: Default::default(),
+            access_token: Default::default(),
+            token_cache: Default::default(),
+            upstream_addr: Default::default(),
             // SourceTask for RtmpSorce / RelayFrom
             source_task: Arc::new(RwLock::new(None)),
             //
@@ -97,6 +126,9 @@ impl Channel {
             hosts_tested: Default::default(),
 
             //
+            events,
+            connections,
+            upload_limiter,
             created_at: Utc::now(),
         }
     }
@@ -117,8 +149,12 @@ impl Channel {
     }
     pub fn set_info(&self, info: ChannelInfo) {
         let mut lock = self.channel_info.write().unwrap();
-        *lock = Some(info);
+        *lock = Some(info.clone());
         // TOOD: send info to task
+        self.events.publish(Event::ChannelInfo {
+            channel_id: self.id,
+            info,
+        });
     }
 
     pub fn track(&self) -> Option<TrackInfo> {
@@ -126,8 +162,39 @@ impl Channel {
     }
     pub fn set_track(&self, track: TrackInfo) {
         let mut lock = self.track_info.write().unwrap();
-        *lock = Some(track);
+        *lock = Some(track.clone());
         // TOOD: send info to task
+        self.events.publish(Event::ChannelTrack {
+            channel_id: self.id,
+            track,
+        });
+    }
+
+    /// 視聴・リレーにトークンが必要で、YPにも掲載しない
+    pub fn is_private(&self) -> bool {
+        self.private.load(Ordering::Acquire)
+    }
+    pub fn set_private(&self, private: bool) {
+        self.private.store(private, Ordering::Release);
+    }
+
+    /// リレーの場合は上流に渡したトークン。下流から来たトークンと比べる
+    pub fn access_token(&self) -> Option<String> {
+        self.access_token.read().unwrap().clone()
+    }
+    /// 上流にトークンを求められたリレーは限定公開として扱う
+    pub fn set_access_token(&self, token: Option<String>) {
+        let mut lock = self.access_token.write().unwrap();
+        if token.is_some() {
+            self.set_private(true);
+        }
+        *lock = token;
+    }
+    pub fn token_cache(&self) -> &TokenCache {
+        &self.token_cache
+    }
+    pub fn upstream_addr(&self) -> Option<SocketAddr> {
+        *self.upstream_addr.read().unwrap()
     }
 
     pub fn connect(&self, connection_id: ConnectionId, config: SourceTaskConfig) -> bool {
@@ -146,17 +213,89 @@ impl Channel {
                         Some(Box::new(task))
                     }
                     SourceTaskConfig::Relay(c) => {
-                        let mut task = RelayTask::new(self.session_id, self.id(), broker_sender);
+                        *self.upstream_addr.write().unwrap() = Some(c.addr);
+                        let mut task = RelayTask::new(
+                            self.session_id,
+                            self.id(),
+                            broker_sender,
+                            self.events.clone(),
+                            Arc::clone(&self.connections),
+                            Arc::clone(&self.upload_limiter),
+                        );
                         let _ = task.connect(config);
                         // let task: Pin<Box<dyn SourceTask>> = Box::pin(task);
                         Some(Box::new(task))
                     }
+                    SourceTaskConfig::HttpPull(_) => {
+                        let mut task = HttpPullTask::new(
+                            self.id(),
+                            Arc::clone(&self.channel_info),
+                            Arc::clone(&self.track_info),
+                            broker_sender,
+                        );
+                        let _ = task.connect(config);
+                        Some(Box::new(task))
+                    }
+                    SourceTaskConfig::File(_) => {
+                        let mut task = FileTask::new(
+                            self.id(),
+                            Arc::clone(&self.channel_info),
+                            Arc::clone(&self.track_info),
+                            broker_sender,
+                        );
+                        let _ = task.connect(config);
+                        Some(Box::new(task))
+                    }
                 };
+                self.watch_status(opt_task.as_ref().unwrap().subscribe_status());
                 true
             }
         }
     }
 
+    // TaskStatusが変わる度にイベントを流す。Taskが終わるか再接続で作り直されたら止まる
+    fn watch_status(&self, status_rx: Option<watch::Receiver<TaskStatus>>) {
+        let Some(mut status_rx) = status_rx else {
+            return;
+        };
+        let channel_id = self.id;
+        let events = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                let status = *status_rx.borrow_and_update();
+                events.publish(Event::ChannelStatus { channel_id, status });
+                if status_rx.changed().await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// チャンネルのツリー全体にPCP_MESGを流す
+    pub fn send_message(&self, text: String) {
+        let atom = BroadcastBuilder::to_tree_builder(self.session_id, self.id)
+            .add(MessageBuilder::new(text.clone()).build())
+            .build();
+        // 自分は上流から見れば下流、下流から見れば上流なので両方に流す
+        let sender = self.broker_task.sender();
+        for direction in [AtomDirection::DownToUp, AtomDirection::UpToDown] {
+            let atom = atom.clone();
+            mpsc_send(
+                &sender,
+                ChannelBrokerMessage::AtomBroadcast { direction, atom },
+            );
+        }
+        self.events.publish(Event::ChannelMessage {
+            channel_id: self.id,
+            from: Some(self.session_id),
+            text,
+        });
+    }
+
     pub fn stop(&self) {
         let mut opt_task = self.source_task.write().unwrap();
         match opt_task.take() {
@@ -169,7 +308,13 @@ impl Channel {
         let mut opt_task = self.source_task.write().unwrap();
         match opt_task.as_mut() {
             None => false,
-            Some(task) => task.retry(),
+            Some(task) => {
+                let retried = task.retry();
+                if retried {
+                    self.watch_status(task.subscribe_status());
+                }
+                retried
+            }
         }
     }
 
@@ -1,6 +1,10 @@
+use http::HeaderValue;
 use serde::Serialize;
 
-use crate::pcp::{atom::decode::PcpChannelInfo, Atom, Id4};
+use crate::{
+    codec::container::ContainerType,
+    pcp::{atom::decode::PcpChannelInfo, Atom, Id4},
+};
 
 use super::merge_field;
 
@@ -36,6 +40,31 @@ impl ChannelInfo {
         merge_field!(self, val, stream_ext);
         merge_field!(self, val, bitrate);
     }
+
+    /// typ, stream_type, stream_extの順に判別する。分からなければFLV扱い
+    pub fn container_type(&self) -> ContainerType {
+        ContainerType::from_type_name(&self.typ)
+            .or_else(|| ContainerType::from_content_type(&self.stream_type))
+            .or_else(|| ContainerType::from_extension(&self.stream_ext))
+            .unwrap_or(ContainerType::Flv)
+    }
+
+    /// HTTPで返すContent-Type
+    /// stream_typeはリモートから来るのでヘッダーに使えない値なら種類から決める
+    pub fn mime_type(&self) -> String {
+        match self.stream_type.as_str() {
+            s if !s.is_empty() && HeaderValue::from_str(s).is_ok() => s.into(),
+            _ => self.container_type().mime_type().into(),
+        }
+    }
+
+    /// /streamや/plsのURLに付ける拡張子
+    pub fn extension(&self) -> String {
+        match self.stream_ext.as_str() {
+            "" => self.container_type().stream_ext().into(),
+            s => s.into(),
+        }
+    }
 }
 
 impl From<&PcpChannelInfo> for ChannelInfo {
@@ -72,4 +101,23 @@ mod t {
         assert_eq!(ci.bitrate, info.bitrate.unwrap());
     }
 
+    #[test]
+    fn test_stream_type(){
+        let mut ci = ChannelInfo::new();
+        assert_eq!(ci.mime_type(), "video/x-flv");
+        assert_eq!(ci.extension(), ".flv");
+
+        ci.typ = "WEBM".into();
+        assert_eq!(ci.mime_type(), "video/webm");
+        assert_eq!(ci.extension(), ".webm");
+
+        ci.typ = "MKV".into();
+        ci.stream_ext = ".mkv".into();
+        assert_eq!(ci.mime_type(), "video/x-matroska");
+        assert_eq!(ci.extension(), ".mkv");
+
+        ci.stream_type = "video/x-matroska\r\nX-Injected: 1".into();
+        assert_eq!(ci.mime_type(), "video/x-matroska");
+    }
+
 }
\ No newline at end of file
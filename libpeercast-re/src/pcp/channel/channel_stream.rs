@@ -74,6 +74,12 @@ impl Stream for ChannelStream {
                             }
                         }
                     }
+                    // ストリームには関係ないので次を待つ
+                    ChannelMessage::RelayChannelMeta { .. }
+                    | ChannelMessage::BroadcastAtom { .. } => {
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    }
                 }
             }
         } // poll_recv(cx)
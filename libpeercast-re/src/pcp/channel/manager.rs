@@ -5,7 +5,12 @@ use std::{
 
 use tracing::info;
 
-use crate::pcp::GnuId;
+use crate::{
+    bandwidth::UploadLimiter,
+    connection_registry::ConnectionRegistry,
+    event::{Event, EventBus},
+    pcp::GnuId,
+};
 
 use super::{channel::ChannelType, Channel, ChannelInfo, TrackInfo};
 
@@ -15,6 +20,9 @@ use super::{channel::ChannelType, Channel, ChannelInfo, TrackInfo};
 pub struct ChannelManager {
     session_id: GnuId,
     channels: Arc<Mutex<HashMap<GnuId, Channel>>>,
+    events: EventBus,
+    connections: Arc<ConnectionRegistry>,
+    upload_limiter: Arc<UploadLimiter>,
 }
 
 impl ChannelManager {
@@ -22,6 +30,10 @@ impl ChannelManager {
         Arc::new(ChannelManager {
             session_id: session_id.clone(),
             channels: Default::default(),
+            events: EventBus::new(),
+            connections: Default::default(),
+            // 制限値は設定を読んだ側がreconfigureで入れる
+            upload_limiter: UploadLimiter::new(Default::default()),
         })
     }
 
@@ -29,6 +41,21 @@ impl ChannelManager {
         self.session_id.clone()
     }
 
+    /// チャンネルの作成・削除と各チャンネルのイベントが流れてくる
+    pub fn events(&self) -> EventBus {
+        self.events.clone()
+    }
+
+    /// 視聴者・リレー・RTMPなどの接続の一覧
+    pub fn connections(&self) -> Arc<ConnectionRegistry> {
+        Arc::clone(&self.connections)
+    }
+
+    /// 上り帯域の制限。HTTPの視聴者とPCPの下流で共有する
+    pub fn upload_limiter(&self) -> Arc<UploadLimiter> {
+        Arc::clone(&self.upload_limiter)
+    }
+
     pub fn channels_lock(&self, func: fn(channels: &mut HashMap<GnuId, Channel>)) {
         let mut lock = self.channels.lock().unwrap();
         func(&mut (*lock));
@@ -46,7 +73,16 @@ impl ChannelManager {
             Err(_) => todo!(),
         };
 
-        let channel = Channel::new(self.session_id, id, ch_type, channel_info, track_info);
+        let channel = Channel::new(
+            self.session_id,
+            id,
+            ch_type,
+            channel_info,
+            track_info,
+            self.events.clone(),
+            self.connections(),
+            self.upload_limiter(),
+        );
         match channels.insert(id, channel) {
             Some(old_ch) => {
                 channels.insert(id, old_ch);
@@ -55,6 +91,8 @@ impl ChannelManager {
             None => {
                 let ch = channels.get(&id).unwrap().clone();
                 info!("created channels. {:?}", &ch);
+                self.events
+                    .publish(Event::ChannelCreated { channel_id: id });
                 Some(ch)
             }
         }
@@ -74,12 +112,23 @@ impl ChannelManager {
             Some(ch) => ch.clone(),
             None => {
                 // channelが無かった場合
-                let channel = Channel::new(self.session_id, id, ch_type, channel_info, track_info);
+                let channel = Channel::new(
+                    self.session_id,
+                    id,
+                    ch_type,
+                    channel_info,
+                    track_info,
+                    self.events.clone(),
+                    self.connections(),
+                    self.upload_limiter(),
+                );
                 match channels.insert(id, channel) {
                     Some(id) => panic!("ChannelManager have same GnuID. {:?}", &self.channels),
                     None => {
                         let ch = channels.get(&id).unwrap().clone();
                         info!("created channels. {:?}", &ch);
+                        self.events
+                            .publish(Event::ChannelCreated { channel_id: id });
                         ch
                     }
                 }
@@ -93,7 +142,12 @@ impl ChannelManager {
             Err(_) => todo!(),
         };
         match channels.remove(&id) {
-            Some(_) => true,
+            Some(_) => {
+                crate::metrics::remove_channel(id);
+                self.events
+                    .publish(Event::ChannelDeleted { channel_id: *id });
+                true
+            }
             None => false,
         }
     }
@@ -132,6 +186,12 @@ impl std::fmt::Debug for ChannelManager {
 
 #[cfg(test)]
 mod t {
+    use crate::{
+        event::Event,
+        pcp::{channel::broker::AtomDirection, decode::PcpBroadcast, ChannelMessage},
+        ConnectionId,
+    };
+
     use super::*;
 
     #[crate::test]
@@ -144,4 +204,70 @@ mod t {
         let ch1 = manager.create_or_get(id, ch_type, Some(info), Default::default());
         let ch1_2 = manager.get(&id);
     }
+
+    #[crate::test]
+    async fn test_channel_manager_events() {
+        let manager = ChannelManager::new(&GnuId::new());
+        let mut rx = manager.events().subscribe();
+        let id = GnuId::new();
+
+        let ch = manager.create(id, ChannelType::Relay, None, None).unwrap();
+        ch.set_info(ChannelInfo::new());
+        assert!(manager.delete(&id));
+
+        let names = [
+            rx.recv().await.unwrap(),
+            rx.recv().await.unwrap(),
+            rx.recv().await.unwrap(),
+        ]
+        .map(|ev| ev.name());
+        assert_eq!(
+            names,
+            ["channel_created", "channel_info", "channel_deleted"]
+        );
+    }
+
+    #[crate::test]
+    async fn test_channel_send_message() {
+        let session_id = GnuId::new();
+        let manager = ChannelManager::new(&session_id);
+        let mut rx = manager.events().subscribe();
+        let id = GnuId::new();
+        let ch = manager.create(id, ChannelType::Relay, None, None).unwrap();
+        let mut reciever = ch.channel_reciever(ConnectionId::new());
+        let _created = rx.recv().await.unwrap();
+
+        ch.send_message("hello".into());
+
+        // 上流と下流の両方に同じBCSTが流れる
+        let mut directions = vec![];
+        for _ in 0..2 {
+            let Some(ChannelMessage::BroadcastAtom { direction, atom }) = reciever.recv().await
+            else {
+                panic!("BroadcastAtom must arrive");
+            };
+            let bcst = PcpBroadcast::parse(&atom).unwrap();
+            assert_eq!(bcst.from_id, Some(session_id));
+            assert_eq!(bcst.channel_id, Some(id));
+            assert_eq!(bcst.message.unwrap().text, "hello");
+            directions.push(direction);
+        }
+        assert_eq!(
+            directions,
+            [AtomDirection::DownToUp, AtomDirection::UpToDown]
+        );
+
+        match rx.recv().await.unwrap() {
+            Event::ChannelMessage {
+                channel_id,
+                from,
+                text,
+            } => {
+                assert_eq!(channel_id, id);
+                assert_eq!(from, Some(session_id));
+                assert_eq!(text, "hello");
+            }
+            ev => panic!("unexpected event {ev:?}"),
+        }
+    }
 }
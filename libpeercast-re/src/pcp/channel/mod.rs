@@ -12,12 +12,15 @@ mod src_task;
 mod track_info;
 
 pub(self) use broker::ChannelBrokerMessage;
-pub use broker::{ChannelMessage, ChannelReciever};
+pub use broker::{AtomDirection, ChannelMessage, ChannelReciever};
 pub use channel::{Channel, ChannelType};
 pub use channel_info::ChannelInfo;
 pub use manager::ChannelManager;
 pub use node_pool::{Node, NodePool};
-pub use src_task::{BroadcastTaskConfig, RelayTaskConfig, SourceTaskConfig, TaskStatus};
+pub use src_task::{
+    BroadcastTaskConfig, FileTaskConfig, HttpPullTaskConfig, RelayTaskConfig, SourceTaskConfig,
+    TaskStatus,
+};
 pub use track_info::TrackInfo;
 
 use crate::pcp::{atom, Id4};
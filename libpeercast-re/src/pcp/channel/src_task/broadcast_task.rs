@@ -95,6 +95,10 @@ impl SourceTask for BroadcastTask {
         *self.worker_status.as_ref().unwrap().borrow()
     }
 
+    fn subscribe_status(&self) -> Option<watch::Receiver<TaskStatus>> {
+        self.worker_status.clone()
+    }
+
     async fn status_changed(&mut self) -> Result<(), watch::error::RecvError> {
         todo!()
     }
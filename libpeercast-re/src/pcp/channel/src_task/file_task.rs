@@ -0,0 +1,365 @@
+////////////////////////////////////////////////////////////////////////////////
+// FileTask
+//
+// FLVファイルをタイムスタンプ通りの速度で読み出してチャンネルに流す
+// エンコーダー無しで配信を試すためのもの
+//
+use std::{
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use thiserror::Error;
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    codec::container::{ContainerSplitter, ContainerType, FlvSplitter, FlvTag, SplitError},
+    pcp::{channel::ChannelBrokerMessage, ChannelInfo, GnuId, TrackInfo},
+};
+
+use super::{packet_writer::PacketWriter, SourceTask, SourceTaskConfig, TaskStatus};
+
+// ループした時に最後のタグから空ける時間(ms)
+const LOOP_GAP_MS: u32 = 33;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct FileTaskConfig {
+    pub path: PathBuf,
+    /// 終端まで読んだら先頭に戻ってタイムスタンプを繋げる
+    pub looping: bool,
+}
+impl From<FileTaskConfig> for SourceTaskConfig {
+    fn from(value: FileTaskConfig) -> Self {
+        SourceTaskConfig::File(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct FileTask {
+    channel_id: GnuId,
+    channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+    track_info: Arc<RwLock<Option<TrackInfo>>>,
+    broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    config: Option<FileTaskConfig>,
+    //
+    worker_status: Option<watch::Receiver<TaskStatus>>,
+    worker_handle: Option<JoinHandle<()>>,
+}
+
+impl FileTask {
+    pub(crate) fn new(
+        channel_id: GnuId,
+        channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+        track_info: Arc<RwLock<Option<TrackInfo>>>,
+        broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    ) -> Self {
+        Self {
+            channel_id,
+            channel_info,
+            track_info,
+            broker_sender,
+            config: None,
+            worker_status: None,
+            worker_handle: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SourceTask for FileTask {
+    fn connect(&mut self, config: SourceTaskConfig) -> bool {
+        let SourceTaskConfig::File(config) = config else {
+            panic!("invalid config {:?}", config);
+        };
+        if let Some(handle) = self.worker_handle.take() {
+            handle.abort();
+        }
+
+        let (status_tx, status_rx) = watch::channel(TaskStatus::Init);
+        let worker = FileWorker {
+            channel_id: self.channel_id,
+            config: config.clone(),
+            writer: PacketWriter::new(
+                self.channel_id,
+                Arc::clone(&self.channel_info),
+                Arc::clone(&self.track_info),
+                self.broker_sender.clone(),
+            ),
+            splitter: FlvSplitter::new(),
+            status_tx,
+        };
+        self.config = Some(config);
+        self.worker_status = Some(status_rx);
+        self.worker_handle = Some(tokio::spawn(worker.start()));
+        true
+    }
+
+    fn retry(&mut self) -> bool {
+        match self.config.clone() {
+            Some(c) => self.connect(c.into()),
+            None => false,
+        }
+    }
+
+    fn status(&self) -> TaskStatus {
+        match &self.worker_status {
+            Some(status) => *status.borrow(),
+            None => TaskStatus::Init,
+        }
+    }
+
+    fn subscribe_status(&self) -> Option<watch::Receiver<TaskStatus>> {
+        self.worker_status.clone()
+    }
+
+    async fn status_changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.worker_status.as_mut().unwrap().changed().await
+    }
+
+    fn stop(&self) {
+        if let Some(handle) = &self.worker_handle {
+            handle.abort();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// FileWorker
+//
+#[derive(Debug, Error)]
+enum WorkerError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to read flv: {0}")]
+    Split(#[from] SplitError),
+    #[error("unexpected eof")]
+    UnexpectedEof,
+    #[error("channel broker closed")]
+    BrokerClosed,
+}
+
+struct FileWorker {
+    channel_id: GnuId,
+    config: FileTaskConfig,
+    writer: PacketWriter,
+    splitter: FlvSplitter,
+    status_tx: watch::Sender<TaskStatus>,
+}
+
+impl FileWorker {
+    async fn start(mut self) {
+        info!(
+            "START FileWorker CID:{} {:?}",
+            self.channel_id, self.config.path
+        );
+        self.writer.update_stream_type(ContainerType::Flv);
+        let _ = self.status_tx.send(TaskStatus::Receiving);
+
+        let started_at = Instant::now();
+        let mut offset = 0;
+        let mut is_first = true;
+        let status = loop {
+            match self.play_once(started_at, offset, is_first).await {
+                Ok(last_timestamp) if self.config.looping => {
+                    debug!("FileWorker CID:{} loop", self.channel_id);
+                    offset = last_timestamp + u64::from(LOOP_GAP_MS);
+                    is_first = false;
+                }
+                Ok(_) => break TaskStatus::Finish,
+                Err(WorkerError::BrokerClosed) => break TaskStatus::Finish,
+                Err(e) => {
+                    warn!("FileWorker CID:{} error: {e}", self.channel_id);
+                    break TaskStatus::Error;
+                }
+            }
+        };
+
+        let _ = self.status_tx.send(status);
+        debug!("SHUTDOWN FileWorker CID:{}", self.channel_id);
+    }
+
+    /// ファイルを最後まで流して、最後に送ったタグの開始からの経過時間(ms)を返す
+    async fn play_once(
+        &mut self,
+        started_at: Instant,
+        offset: u64,
+        is_first: bool,
+    ) -> Result<u64, WorkerError> {
+        let mut file = File::open(&self.config.path).await?;
+        let mut buf = BytesMut::with_capacity(READ_BUFFER_SIZE);
+
+        let magic = loop {
+            if let Some(magic) = FlvSplitter::read_magic(&mut buf)? {
+                break magic;
+            }
+            if file.read_buf(&mut buf).await? == 0 {
+                return Err(WorkerError::UnexpectedEof);
+            }
+        };
+        if is_first {
+            let packets = self.splitter.push(&magic)?;
+            self.send_packets(packets)?;
+        }
+
+        let mut first_timestamp = None;
+        let mut last_timestamp = offset;
+        loop {
+            let Some(tag) = FlvTag::read(&mut buf)? else {
+                if file.read_buf(&mut buf).await? == 0 {
+                    break;
+                }
+                continue;
+            };
+            // 2周目以降のシーケンスヘッダーは既に送ってある
+            if !is_first && tag.is_header() {
+                continue;
+            }
+
+            let base = *first_timestamp.get_or_insert(tag.timestamp());
+            let delta = tag.timestamp().saturating_sub(base);
+            let elapsed = offset + u64::from(delta);
+            tokio::time::sleep_until(started_at + Duration::from_millis(elapsed)).await;
+
+            // FLVのタイムスタンプは32bitなので長時間ループしたら一周させる
+            let timestamp = (offset as u32).wrapping_add(delta);
+            let packets = self.splitter.push(tag.with_timestamp(timestamp).bytes())?;
+            self.send_packets(packets)?;
+            last_timestamp = elapsed;
+        }
+
+        Ok(last_timestamp)
+    }
+
+    fn send_packets(
+        &mut self,
+        packets: Vec<crate::codec::container::ContainerPacket>,
+    ) -> Result<(), WorkerError> {
+        match self.writer.send(packets) {
+            true => Ok(()),
+            false => Err(WorkerError::BrokerClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use std::io::Write;
+
+    use crate::{
+        codec::rtmp::flv::DataType,
+        pcp::{channel::broker::ChannelBroker, ChannelMessage, ChannelType},
+        ConnectionId,
+    };
+
+    use super::*;
+
+    fn flv_tag(data_type: DataType, timestamp: u32, data: &[u8]) -> Vec<u8> {
+        let size = (data.len() as u32).to_be_bytes();
+        let t = timestamp.to_be_bytes();
+        let mut buf = vec![
+            data_type.0,
+            size[1],
+            size[2],
+            size[3],
+            t[1],
+            t[2],
+            t[3],
+            t[0],
+            0,
+            0,
+            0,
+        ];
+        buf.extend_from_slice(data);
+        buf.extend_from_slice(&((data.len() + 11) as u32).to_be_bytes());
+        buf
+    }
+
+    fn write_test_flv() -> PathBuf {
+        let mut flv = vec![b'F', b'L', b'V', 1, 0b0000_0101, 0, 0, 0, 9, 0, 0, 0, 0];
+        flv.extend(flv_tag(DataType::SCRIPT, 0, b"meta"));
+        flv.extend(flv_tag(DataType::VIDEO, 0, &[0x17, 0x00, 0, 0, 0]));
+        flv.extend(flv_tag(DataType::AUDIO, 0, &[0xAF, 0x00, 0x12]));
+        flv.extend(flv_tag(DataType::VIDEO, 0, &[0x17, 0x01, 0, 0, 0]));
+        flv.extend(flv_tag(DataType::VIDEO, 20, &[0x27, 0x01, 0, 0, 0]));
+
+        let path = std::env::temp_dir().join(format!("peercast-re-test-{}.flv", GnuId::new()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(&flv).unwrap();
+        path
+    }
+
+    #[crate::test]
+    async fn test_file_task_loop() {
+        let path = write_test_flv();
+
+        let id = GnuId::new();
+        let broker = ChannelBroker::new(
+            ChannelType::Broadcast,
+            id,
+            Default::default(),
+            Default::default(),
+        );
+        let mut reciever = broker.channel_reciever(ConnectionId::new());
+
+        let mut task = FileTask::new(id, Default::default(), Default::default(), broker.sender());
+        task.connect(
+            FileTaskConfig {
+                path: path.clone(),
+                looping: true,
+            }
+            .into(),
+        );
+
+        // Head, 1周目のData x2, 2周目のData x2
+        let mut data_timestamps = vec![];
+        let head = reciever.recv().await.unwrap();
+        assert!(matches!(head, ChannelMessage::RelayChannelHead { .. }));
+        while data_timestamps.len() < 4 {
+            match reciever.recv().await.unwrap() {
+                ChannelMessage::RelayChannelData { payload, .. } => {
+                    let mut buf = BytesMut::from(&payload[..]);
+                    let tag = FlvTag::read(&mut buf).unwrap().unwrap();
+                    data_timestamps.push(tag.timestamp());
+                }
+                ChannelMessage::RelayChannelHead { .. } => panic!("head must be sent once"),
+                ChannelMessage::RelayChannelMeta { .. } | ChannelMessage::BroadcastAtom { .. } => {}
+            }
+        }
+        assert_eq!(
+            data_timestamps,
+            vec![0, 20, 20 + LOOP_GAP_MS, 40 + LOOP_GAP_MS]
+        );
+        assert_eq!(task.status(), TaskStatus::Receiving);
+
+        task.stop();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[crate::test]
+    async fn test_file_task_not_found() {
+        let id = GnuId::new();
+        let (broker_sender, _broker_receiver) = mpsc::unbounded_channel();
+        let mut task = FileTask::new(id, Default::default(), Default::default(), broker_sender);
+        task.connect(
+            FileTaskConfig {
+                path: PathBuf::from("/not/found/file.flv"),
+                looping: false,
+            }
+            .into(),
+        );
+
+        while task.status() != TaskStatus::Error {
+            task.status_changed().await.unwrap();
+        }
+    }
+}
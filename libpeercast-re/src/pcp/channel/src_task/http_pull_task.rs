@@ -0,0 +1,289 @@
+////////////////////////////////////////////////////////////////////////////////
+// HttpPullTask
+//
+// HTTPでFLV/MPEG-TS/MKVを取得してチャンネルに流す
+//
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::{Bytes, BytesMut};
+use futures_util::StreamExt;
+use thiserror::Error;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tracing::{debug, error, info, warn};
+use url::Url;
+
+use crate::{
+    codec::container::{ContainerPacket, ContainerSplitter, ContainerType, SplitError},
+    pcp::{channel::ChannelBrokerMessage, ChannelInfo, GnuId, TrackInfo},
+};
+
+use super::{packet_writer::PacketWriter, SourceTask, SourceTaskConfig, TaskStatus};
+
+const HTTP_CONNECT_TIMEOUT: Duration = Duration::from_millis(5000);
+const RECONNECT_INTERVAL: Duration = Duration::from_millis(3000);
+
+#[derive(Debug, Clone)]
+pub struct HttpPullTaskConfig {
+    pub url: Url,
+    /// Noneの場合はContent-Type、拡張子、先頭のバイト列の順に判別する
+    pub container: Option<ContainerType>,
+}
+impl From<HttpPullTaskConfig> for SourceTaskConfig {
+    fn from(value: HttpPullTaskConfig) -> Self {
+        SourceTaskConfig::HttpPull(value)
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpPullTask {
+    channel_id: GnuId,
+    channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+    track_info: Arc<RwLock<Option<TrackInfo>>>,
+    broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    config: Option<HttpPullTaskConfig>,
+    //
+    worker_status: Option<watch::Receiver<TaskStatus>>,
+    worker_handle: Option<JoinHandle<()>>,
+}
+
+impl HttpPullTask {
+    pub(crate) fn new(
+        channel_id: GnuId,
+        channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+        track_info: Arc<RwLock<Option<TrackInfo>>>,
+        broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    ) -> Self {
+        Self {
+            channel_id,
+            channel_info,
+            track_info,
+            broker_sender,
+            config: None,
+            worker_status: None,
+            worker_handle: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl SourceTask for HttpPullTask {
+    fn connect(&mut self, config: SourceTaskConfig) -> bool {
+        let SourceTaskConfig::HttpPull(config) = config else {
+            panic!("invalid config {:?}", config);
+        };
+        if let Some(handle) = self.worker_handle.take() {
+            handle.abort();
+        }
+
+        let (status_tx, status_rx) = watch::channel(TaskStatus::Init);
+        let worker = HttpPullWorker {
+            channel_id: self.channel_id,
+            config: config.clone(),
+            writer: PacketWriter::new(
+                self.channel_id,
+                Arc::clone(&self.channel_info),
+                Arc::clone(&self.track_info),
+                self.broker_sender.clone(),
+            ),
+            status_tx,
+        };
+        self.config = Some(config);
+        self.worker_status = Some(status_rx);
+        self.worker_handle = Some(tokio::spawn(worker.start()));
+        true
+    }
+
+    fn retry(&mut self) -> bool {
+        match self.config.clone() {
+            Some(c) => self.connect(c.into()),
+            None => false,
+        }
+    }
+
+    fn status(&self) -> TaskStatus {
+        match &self.worker_status {
+            Some(status) => *status.borrow(),
+            None => TaskStatus::Init,
+        }
+    }
+
+    fn subscribe_status(&self) -> Option<watch::Receiver<TaskStatus>> {
+        self.worker_status.clone()
+    }
+
+    async fn status_changed(&mut self) -> Result<(), watch::error::RecvError> {
+        self.worker_status.as_mut().unwrap().changed().await
+    }
+
+    fn stop(&self) {
+        if let Some(handle) = &self.worker_handle {
+            handle.abort();
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// HttpPullWorker
+//
+#[derive(Debug, Error)]
+enum WorkerError {
+    #[error("http error: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("unsupported stream format")]
+    UnknownContainer,
+    #[error("failed to split stream: {0}")]
+    Split(#[from] SplitError),
+    #[error("channel broker closed")]
+    BrokerClosed,
+}
+
+struct HttpPullWorker {
+    channel_id: GnuId,
+    config: HttpPullTaskConfig,
+    writer: PacketWriter,
+    status_tx: watch::Sender<TaskStatus>,
+}
+
+impl HttpPullWorker {
+    async fn start(mut self) {
+        info!(
+            "START HttpPullWorker CID:{} {}",
+            self.channel_id, self.config.url
+        );
+        let client = match reqwest::Client::builder()
+            .connect_timeout(HTTP_CONNECT_TIMEOUT)
+            .build()
+        {
+            Ok(client) => client,
+            Err(e) => {
+                error!("HttpPullWorker failed to build http client: {e}");
+                let _ = self.status_tx.send(TaskStatus::Error);
+                return;
+            }
+        };
+
+        // EOFで切れても同じURLに接続し直す
+        loop {
+            let _ = self.status_tx.send(TaskStatus::Searching {
+                searched: 0,
+                all: 1,
+            });
+            match self.receive(&client).await {
+                Ok(()) => {
+                    info!("HttpPullWorker CID:{} reached EOF", self.channel_id);
+                    let _ = self.status_tx.send(TaskStatus::Idle);
+                }
+                Err(WorkerError::BrokerClosed) => break,
+                Err(e) => {
+                    warn!("HttpPullWorker CID:{} error: {e}", self.channel_id);
+                    let _ = self.status_tx.send(TaskStatus::Error);
+                }
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+
+        let _ = self.status_tx.send(TaskStatus::Finish);
+        debug!("SHUTDOWN HttpPullWorker CID:{}", self.channel_id);
+    }
+
+    async fn receive(&mut self, client: &reqwest::Client) -> Result<(), WorkerError> {
+        let response = client
+            .get(self.config.url.clone())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let container = self.config.container.or_else(|| {
+            response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .and_then(ContainerType::from_content_type)
+                .or_else(|| ContainerType::from_extension(self.config.url.path()))
+        });
+        let mut splitter: Option<Box<dyn ContainerSplitter>> = container.map(|c| {
+            self.writer.update_stream_type(c);
+            c.splitter()
+        });
+        // 判別できるまで溜めておく
+        let mut sniff_buf = BytesMut::new();
+
+        let _ = self.status_tx.send(TaskStatus::Receiving);
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk?;
+
+            if splitter.is_none() {
+                sniff_buf.extend_from_slice(&chunk);
+                if sniff_buf.len() < 4 {
+                    continue;
+                }
+                let c =
+                    ContainerType::from_magic(&sniff_buf).ok_or(WorkerError::UnknownContainer)?;
+                self.writer.update_stream_type(c);
+                let mut s = c.splitter();
+                let packets = s.push(&sniff_buf.split())?;
+                self.send_packets(packets)?;
+                splitter = Some(s);
+                continue;
+            }
+
+            let packets = splitter.as_mut().unwrap().push(&chunk)?;
+            self.send_packets(packets)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_packets(&mut self, packets: Vec<ContainerPacket>) -> Result<(), WorkerError> {
+        match self.writer.send(packets) {
+            true => Ok(()),
+            false => Err(WorkerError::BrokerClosed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use crate::pcp::channel::broker::ChannelBroker;
+    use crate::pcp::ChannelType;
+
+    use super::*;
+
+    #[ignore = "needs a http server"]
+    #[crate::test]
+    async fn test() {
+        let url = match std::env::var("PEERCAST_RE_DEBUG_HTTP_PULL_URL") {
+            Ok(s) => Url::parse(&s).unwrap(),
+            Err(_) => todo!(),
+        };
+
+        let id = GnuId::new();
+        let broker = ChannelBroker::new(
+            ChannelType::Broadcast,
+            id,
+            Default::default(),
+            Default::default(),
+        );
+        let mut task =
+            HttpPullTask::new(id, Default::default(), Default::default(), broker.sender());
+        task.connect(
+            HttpPullTaskConfig {
+                url,
+                container: None,
+            }
+            .into(),
+        );
+
+        let mut reciever = broker.channel_reciever(crate::ConnectionId::new());
+        while let Some(msg) = reciever.recv().await {
+            println!("{msg:?}");
+        }
+    }
+}
@@ -5,16 +5,24 @@ use super::{ChannelInfo, TrackInfo};
 
 pub(super) use broadcast_task::BroadcastTask;
 pub use broadcast_task::BroadcastTaskConfig;
+pub(super) use file_task::FileTask;
+pub use file_task::FileTaskConfig;
+pub(super) use http_pull_task::HttpPullTask;
+pub use http_pull_task::HttpPullTaskConfig;
 pub(super) use relay_task::RelayTask;
 pub use relay_task::RelayTaskConfig;
 
 mod broadcast_task;
+mod file_task;
+mod http_pull_task;
+mod packet_writer;
 mod relay_task;
 
 ////////////////////////////////////////////////////////////////////////////////
 /// TaskState
 ///
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TaskStatus {
     Init,
     Searching { searched: u32, all: u32 },
@@ -32,6 +40,8 @@ pub enum TaskStatus {
 pub enum SourceTaskConfig {
     Broadcast(BroadcastTaskConfig),
     Relay(RelayTaskConfig),
+    HttpPull(HttpPullTaskConfig),
+    File(FileTaskConfig),
 }
 
 #[async_trait]
@@ -51,6 +61,8 @@ pub(crate) trait SourceTask: Send + Sync + std::fmt::Debug {
     fn update_track(&self, info: TrackInfo) {}
 
     fn status(&self) -> TaskStatus;
+    /// 状態の変化を購読する。接続前はNone
+    fn subscribe_status(&self) -> Option<watch::Receiver<TaskStatus>>;
     async fn status_changed(&mut self) -> Result<(), watch::error::RecvError>;
 
     fn stop(&self);
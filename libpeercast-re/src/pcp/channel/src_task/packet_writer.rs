@@ -0,0 +1,167 @@
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    codec::container::{ContainerPacket, ContainerType},
+    pcp::{
+        channel::{broker::create_atom, ChannelBrokerMessage},
+        classify::ChanPktDataType,
+        ChannelInfo, GnuId, TrackInfo,
+    },
+    util::util_mpsc::mpsc_send,
+};
+
+/// ContainerPacketをPCPのAtomに包んでChannelBrokerへ送る
+#[derive(Debug)]
+pub(super) struct PacketWriter {
+    channel_id: GnuId,
+    channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+    track_info: Arc<RwLock<Option<TrackInfo>>>,
+    broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    // 配信開始からのストリーム上の位置
+    pos: u32,
+}
+
+impl PacketWriter {
+    pub(super) fn new(
+        channel_id: GnuId,
+        channel_info: Arc<RwLock<Option<ChannelInfo>>>,
+        track_info: Arc<RwLock<Option<TrackInfo>>>,
+        broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    ) -> Self {
+        Self {
+            channel_id,
+            channel_info,
+            track_info,
+            broker_sender,
+            pos: 0,
+        }
+    }
+
+    /// ChannelInfoに形式が書かれていなければ埋める
+    pub(super) fn update_stream_type(&self, container: ContainerType) {
+        let mut lock = self.channel_info.write().unwrap();
+        let info = lock.get_or_insert_with(ChannelInfo::new);
+        if info.typ.is_empty() {
+            info.typ = container.type_name().into();
+        }
+        if info.stream_type.is_empty() {
+            info.stream_type = container.mime_type().into();
+        }
+        if info.stream_ext.is_empty() {
+            info.stream_ext = container.stream_ext().into();
+        }
+    }
+
+    /// Brokerが閉じていたらfalse
+    pub(super) fn send(&mut self, packets: Vec<ContainerPacket>) -> bool {
+        for packet in packets {
+            let message = match packet {
+                ContainerPacket::Head { payload } => {
+                    let info = self.channel_info.read().unwrap().clone();
+                    let track = self.track_info.read().unwrap().clone();
+                    let atom = create_atom(
+                        self.channel_id,
+                        ChanPktDataType::Head,
+                        Some(info.unwrap_or_default()),
+                        Some(track.unwrap_or_default()),
+                        self.pos,
+                        None,
+                        &payload,
+                    );
+                    let message = ChannelBrokerMessage::ArrivedChannelHead {
+                        atom,
+                        payload: payload.clone(),
+                        pos: self.pos,
+                        info: None,
+                        track: None,
+                    };
+                    self.pos = self.pos.wrapping_add(payload.len() as u32);
+                    message
+                }
+                ContainerPacket::Data { payload, keyframe } => {
+                    let atom = create_atom(
+                        self.channel_id,
+                        ChanPktDataType::Data,
+                        None,
+                        None,
+                        self.pos,
+                        Some(!keyframe),
+                        &payload,
+                    );
+                    let message = ChannelBrokerMessage::ArrivedChannelData {
+                        atom,
+                        payload: payload.clone(),
+                        pos: self.pos,
+                        continuation: !keyframe,
+                    };
+                    self.pos = self.pos.wrapping_add(payload.len() as u32);
+                    message
+                }
+            };
+            if !mpsc_send(&self.broker_sender, message) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use bytes::Bytes;
+
+    use super::*;
+
+    #[crate::test]
+    async fn test_send_packets() {
+        let (broker_sender, mut broker_receiver) = mpsc::unbounded_channel();
+        let mut writer = PacketWriter::new(
+            GnuId::new(),
+            Default::default(),
+            Default::default(),
+            broker_sender,
+        );
+
+        writer.update_stream_type(ContainerType::Flv);
+        let sent = writer.send(vec![
+            ContainerPacket::Head {
+                payload: Bytes::from_static(b"head"),
+            },
+            ContainerPacket::Data {
+                payload: Bytes::from_static(b"data"),
+                keyframe: false,
+            },
+        ]);
+        assert!(sent);
+
+        let info = writer.channel_info.read().unwrap().clone().unwrap();
+        assert_eq!(info.typ, "FLV");
+        assert_eq!(info.stream_type, "video/x-flv");
+        assert_eq!(info.stream_ext, ".flv");
+
+        match broker_receiver.recv().await {
+            Some(ChannelBrokerMessage::ArrivedChannelHead { pos, payload, .. }) => {
+                assert_eq!(pos, 0);
+                assert_eq!(payload, Bytes::from_static(b"head"));
+            }
+            m => panic!("unexpected message {m:?}"),
+        }
+        match broker_receiver.recv().await {
+            Some(ChannelBrokerMessage::ArrivedChannelData {
+                pos, continuation, ..
+            }) => {
+                assert_eq!(pos, 4);
+                assert!(continuation);
+            }
+            m => panic!("unexpected message {m:?}"),
+        }
+
+        drop(broker_receiver);
+        assert!(!writer.send(vec![ContainerPacket::Data {
+            payload: Bytes::from_static(b"data"),
+            keyframe: true,
+        }]));
+    }
+}
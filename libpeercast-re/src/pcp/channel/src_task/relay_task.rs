@@ -1,4 +1,9 @@
-use std::{collections::VecDeque, net::SocketAddr, time::Duration};
+use std::{
+    collections::VecDeque,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bytes::{Bytes, BytesMut};
 use futures_util::FutureExt;
@@ -11,13 +16,20 @@ use tokio::{
 use tracing::{debug, error, info, warn};
 
 use crate::{
+    bandwidth::UploadLimiter,
+    connection_registry::{ConnectionRegistry, Protocol},
     error::{ConnectionError, HandshakeError},
+    event::{ConnectionKind, Event, EventBus},
+    metrics,
     pcp::{
-        builder::OlehInfo,
-        channel::{node_pool::HostCandidate, ChannelBrokerMessage},
+        builder::{BroadcastBuilder, HostBuilder, OlehInfo},
+        channel::{
+            broker::AtomDirection, node_pool::HostCandidate, ChannelBrokerMessage, ChannelMessage,
+        },
+        decode::HostFlags1,
         procedure::{HandshakeReturn, PcpHandshake},
         session::{Session, SessionConfig, SessionEvent, SessionResult},
-        Atom, ChannelInfo, GnuId, TrackInfo,
+        Atom, ChannelInfo, GnuId, Id4, TrackInfo,
     },
     util::util_mpsc::mpsc_send,
     ConnectionId,
@@ -33,6 +45,8 @@ use super::{SourceTask, SourceTaskConfig, TaskStatus};
 pub struct RelayTaskConfig {
     pub addr: SocketAddr,
     pub self_addr: Option<SocketAddr>,
+    /// 限定公開チャンネルの視聴トークン, 上流へのリクエストに付ける
+    pub token: Option<String>,
 }
 impl From<RelayTaskConfig> for SourceTaskConfig {
     fn from(value: RelayTaskConfig) -> Self {
@@ -49,6 +63,9 @@ pub struct RelayTask {
     session_id: GnuId,
     broadcast_id: GnuId,
     broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    events: EventBus,
+    connections: Arc<ConnectionRegistry>,
+    upload_limiter: Arc<UploadLimiter>,
     config: Option<RelayTaskConfig>,
     //
     worker_status: Option<watch::Receiver<TaskStatus>>,
@@ -60,11 +77,17 @@ impl RelayTask {
         session_id: GnuId,
         broadcast_id: GnuId,
         broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+        events: EventBus,
+        connections: Arc<ConnectionRegistry>,
+        upload_limiter: Arc<UploadLimiter>,
     ) -> Self {
         RelayTask {
             session_id,
             broadcast_id,
             broker_sender,
+            events,
+            connections,
+            upload_limiter,
             config: None,
             worker_status: None,
             worker_handle: None,
@@ -95,8 +118,8 @@ impl SourceTask for RelayTask {
         let (status_tx, status_rx) = watch::channel(TaskStatus::Init);
 
         match config {
-            SourceTaskConfig::Broadcast(_) => panic!("invalid config {:?}", config),
             SourceTaskConfig::Relay(c) => self.config = Some(c),
+            _ => panic!("invalid config {:?}", config),
         };
 
         let worker = ChannelTaskWoker::new(
@@ -105,7 +128,11 @@ impl SourceTask for RelayTask {
             self.session_id,
             self.config.as_ref().unwrap().self_addr.clone(),
             self.config.as_ref().unwrap().addr.clone(),
+            self.config.as_ref().unwrap().token.clone(),
             self.broker_sender.clone(),
+            self.events.clone(),
+            Arc::clone(&self.connections),
+            Arc::clone(&self.upload_limiter),
             status_tx,
         );
         let worker_handle = tokio::spawn(async { worker.start().await });
@@ -121,6 +148,10 @@ impl SourceTask for RelayTask {
         *self.worker_status.as_ref().unwrap().borrow()
     }
 
+    fn subscribe_status(&self) -> Option<watch::Receiver<TaskStatus>> {
+        self.worker_status.clone()
+    }
+
     async fn status_changed(&mut self) -> Result<(), watch::error::RecvError> {
         self.worker_status.as_mut().unwrap().changed().await
     }
@@ -142,18 +173,26 @@ struct ChannelTaskWoker {
     self_addr: Option<SocketAddr>,
     //
     root_addr: SocketAddr, // rootって言うのが正しいのかなぁ・・・
+    token: Option<String>,
     target_hosts: VecDeque<HostCandidate>,
     failed_hosts: VecDeque<HostCandidate>,
     //
     broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+    events: EventBus,
+    connections: Arc<ConnectionRegistry>,
+    upload_limiter: Arc<UploadLimiter>,
     //
     status_tx: watch::Sender<TaskStatus>,
     // shutdown: ShutdownRecvier,
     //
     session: Session,
+    // METAはtitleとurlしか持ってないので最後に受け取った曲情報に重ねる
+    track: Option<TrackInfo>,
 }
 
 const TCP_CONNECT_TIMEOUT: Duration = Duration::from_millis(5000);
+/// 上流に自分のHOSTを送る間隔
+const HOST_UPDATE_INTERVAL: Duration = Duration::from_secs(120);
 
 impl ChannelTaskWoker {
     fn new(
@@ -163,8 +202,12 @@ impl ChannelTaskWoker {
         session_id: GnuId,
         self_addr: Option<SocketAddr>,
         addr: SocketAddr,
+        token: Option<String>,
         //
         broker_sender: mpsc::UnboundedSender<ChannelBrokerMessage>,
+        events: EventBus,
+        connections: Arc<ConnectionRegistry>,
+        upload_limiter: Arc<UploadLimiter>,
         //
         status_tx: watch::Sender<TaskStatus>,
         // shutdown: ShutdownRecvier,
@@ -177,23 +220,43 @@ impl ChannelTaskWoker {
             self_addr,
             //
             root_addr: addr,
+            token,
             target_hosts: VecDeque::from([HostCandidate::server(GnuId::from(0), addr)]),
             failed_hosts: VecDeque::new(),
             //
             broker_sender,
+            events,
+            connections,
+            upload_limiter,
             //
             status_tx,
             // shutdown,
             session: Session::new(SessionConfig::new()),
+            track: None,
         }
     }
 
     async fn start(mut self) -> Result<(), ConnectionError> {
         // Peerに接続する
         // let (stream, read_buf, oleh) = self.connect_to_peer().await?;
-        let (stream, read_buf, oleh) = self.connect_to_peer_only_root().await?;
+        let (stream, read_buf, oleh) = self
+            .connect_to_peer_only_root()
+            .await
+            .inspect_err(metrics::handshake_failed)?;
 
         info!("connected success CID:{}", self.connection_id);
+        let mut conn_handle =
+            self.connections
+                .register(self.connection_id, Protocol::PcpRelayIn, self.root_addr);
+        conn_handle.set_channel(self.broadcast_id);
+        conn_handle.add_bytes_in(read_buf.len());
+        // 上流との接続が切れたらConnectionLeftが流れる
+        let _guard = self.events.connection_guard(
+            self.broadcast_id,
+            self.connection_id,
+            ConnectionKind::Relay,
+            self.root_addr,
+        );
 
         let (stream_reader, stream_writer) = tokio::io::split(stream);
 
@@ -226,6 +289,9 @@ impl ChannelTaskWoker {
 
         let _ = self.status_tx.send(TaskStatus::Receiving);
 
+        let connected_at = Instant::now();
+        let mut host_update = tokio::time::interval(HOST_UPDATE_INTERVAL);
+
         loop {
             // リモートにデータを送る
             let reaction = self
@@ -245,6 +311,7 @@ impl ChannelTaskWoker {
                     match message {
                         None => break,
                         Some(bytes) => {
+                            conn_handle.add_bytes_in(bytes.len());
                             results = self.session.handle_input(&bytes).map_err(|x| format!("error in message arrive")).unwrap();
                         }
                     }
@@ -254,6 +321,13 @@ impl ChannelTaskWoker {
                     // trace!("{}: Broker Message Arrived {:?}", &self.connection_id, &manager_message);
                     match manager_message {
                         None => break,
+                        // 下流や自分から来たBCSTを上流に送る
+                        Some(ChannelMessage::BroadcastAtom {
+                            direction: AtomDirection::DownToUp,
+                            atom,
+                        }) => {
+                            let _ = write_bytes_sender.send(atom);
+                        }
                     //     Some(message) => {
                     //         let (new_results, action) = self.handle_connection_message(message)?;
                     //         match action {
@@ -266,6 +340,16 @@ impl ChannelTaskWoker {
                         _ => {}
                     }
                 }
+                // リレーを受けられるかどうかを上流に伝える
+                _ = host_update.tick() => {
+                    let _ = write_bytes_sender.send(self.host_broadcast(&oleh, connected_at));
+                }
+                // APIから切断を要求された
+                quit_code = conn_handle.disconnect_requested() => {
+                    info!("disconnect requested CID:{} quit_code:{}", self.connection_id, quit_code);
+                    let _ = write_bytes_sender.send(Atom::Child((Id4::PCP_QUIT, quit_code).into()));
+                    break;
+                }
             }
         }
 
@@ -275,6 +359,38 @@ impl ChannelTaskWoker {
         Ok(())
     }
 
+    /// 上流のトラッカーに送る自分のHOST
+    fn host_broadcast(&self, oleh: &OlehInfo, connected_at: Instant) -> Atom {
+        // 上流から見えている自分のIPと、待ち受けているポート
+        let ip = oleh
+            .remote_ip
+            .or(self.self_addr.map(|addr| addr.ip()))
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let port = self.self_addr.map(|addr| addr.port()).unwrap_or(0);
+        let flags1 = host_flags(
+            self.upload_limiter.is_relay_full(&self.broadcast_id),
+            self.self_addr.is_none(),
+        );
+        let listeners = self
+            .connections
+            .count(Protocol::HttpListener, Some(&self.broadcast_id));
+        let relays = self
+            .connections
+            .count(Protocol::PcpRelayOut, Some(&self.broadcast_id));
+
+        let mut host = HostBuilder::new(self.broadcast_id, SocketAddr::new(ip, port))
+            .session_id(self.session_id)
+            .count(listeners, relays)
+            .uptime(connected_at.elapsed().as_secs() as u32)
+            .flags1(flags1);
+        if let Some(addr) = self.self_addr {
+            host = host.local_address(addr);
+        }
+        BroadcastBuilder::to_trackers_builder(self.session_id, self.broadcast_id)
+            .add(host.build())
+            .build()
+    }
+
     async fn connect_to_peer_only_root(
         &mut self,
     ) -> Result<(TcpStream, BytesMut, OlehInfo), HandshakeError> {
@@ -292,7 +408,7 @@ impl ChannelTaskWoker {
             BytesMut::with_capacity(4096),
             self.session_id,
         )
-        .outgoing(self.broadcast_id)
+        .outgoing(self.broadcast_id, self.token.as_deref())
         .await?;
 
         match handshake_result {
@@ -359,7 +475,7 @@ impl ChannelTaskWoker {
                 BytesMut::with_capacity(4096),
                 self.session_id,
             )
-            .outgoing(self.broadcast_id)
+            .outgoing(self.broadcast_id, self.token.as_deref())
             .await
             else {
                 error!(connection_id = ?self.connection_id, "timeout TcpStream::connect({:?})", target.addr());
@@ -472,7 +588,9 @@ impl ChannelTaskWoker {
                 track,
                 pos,
             } => {
-                //
+                if track.is_some() {
+                    self.track = track.clone();
+                }
                 let messages = ChannelBrokerMessage::ArrivedChannelHead {
                     atom,
                     payload: head_data,
@@ -500,6 +618,59 @@ impl ChannelTaskWoker {
                 mpsc_send(&self.broker_sender, messages);
                 Ok(ConnectionReaction::None)
             }
+            //
+            SessionEvent::ArrivedMetaData {
+                atom,
+                meta,
+                pos,
+                track,
+            } => {
+                let track = track.map(|pcp_track| {
+                    let mut track = self.track.clone().unwrap_or_default();
+                    track.merge_pcp(pcp_track);
+                    self.track = Some(track.clone());
+                    self.events.publish(Event::ChannelTrack {
+                        channel_id: self.broadcast_id,
+                        track: track.clone(),
+                    });
+                    track
+                });
+                let messages = ChannelBrokerMessage::ArrivedChannelMeta {
+                    atom,
+                    payload: meta,
+                    pos,
+                    track,
+                };
+                mpsc_send(&self.broker_sender, messages);
+                Ok(ConnectionReaction::None)
+            }
+            //
+            SessionEvent::ArrivedBroadcast { atom, broadcast } => {
+                if broadcast
+                    .channel_id
+                    .is_some_and(|id| id != self.broadcast_id)
+                {
+                    warn!("bcst for other channel arrived {:?}", broadcast.channel_id);
+                    return Ok(ConnectionReaction::None);
+                }
+                if let Some(message) = &broadcast.message {
+                    self.events.publish(Event::ChannelMessage {
+                        channel_id: self.broadcast_id,
+                        from: broadcast.from_id,
+                        text: message.text.clone(),
+                    });
+                }
+                // TTL/HOPSを書き換えて下流に流す
+                let Some(atom) = broadcast.forwarded(&atom) else {
+                    return Ok(ConnectionReaction::None);
+                };
+                let messages = ChannelBrokerMessage::AtomBroadcast {
+                    direction: AtomDirection::UpToDown,
+                    atom,
+                };
+                mpsc_send(&self.broker_sender, messages);
+                Ok(ConnectionReaction::None)
+            }
         }
     }
 
@@ -512,6 +683,15 @@ enum ConnectionReaction {
     Disconnect,
 }
 
+/// 帯域を使い切っている間はリレー不可(relay full)として伝える
+fn host_flags(relay_full: bool, firewalled: bool) -> HostFlags1 {
+    HostFlags1::NONE
+        .set_relay(!relay_full)
+        .set_direct(true)
+        .set_recv(true)
+        .set_firewalled(firewalled)
+}
+
 async fn connection_reader(
     connection_id: u64,
     mut stream: ReadHalf<TcpStream>,
@@ -581,7 +761,7 @@ async fn connection_writer(
         for atom in send_queue.drain(..) {
             atom.write_bytes(&mut send_buf);
         }
-        stream.write_all_buf(&mut send_buf);
+        stream.write_all_buf(&mut send_buf).await?;
     }
 
     println!("Connection {}: Writer disconnected", connection_id);
@@ -616,12 +796,20 @@ mod t {
             Default::default(),
             Default::default(),
         );
-        let mut task = RelayTask::new(session_id, id, broker_task.sender());
+        let mut task = RelayTask::new(
+            session_id,
+            id,
+            broker_task.sender(),
+            EventBus::new(),
+            Default::default(),
+            UploadLimiter::new(Default::default()),
+        );
 
         task.connect(
             RelayTaskConfig {
                 addr,
                 self_addr: None,
+                token: None,
             }
             .into(),
         );
@@ -630,4 +818,17 @@ mod t {
             tokio::task::yield_now().await;
         }
     }
+
+    #[test]
+    fn test_host_flags() {
+        let flags = host_flags(false, false);
+        assert!(flags.has_relay());
+        assert!(flags.has_recv());
+        assert!(!flags.has_firewalled());
+
+        let flags = host_flags(true, true);
+        assert!(!flags.has_relay());
+        assert!(flags.has_direct());
+        assert!(flags.has_firewalled());
+    }
 }
@@ -2,11 +2,11 @@ use std::net::IpAddr;
 
 use bytes::{Buf, Bytes};
 use ipnet::IpAdd;
-use tracing::{error, info, trace};
+use tracing::{error, info, trace, warn};
 
 use crate::error::AtomParseError;
 
-use super::atom::decode::{PcpChannelInfo, PcpTrackInfo};
+use super::atom::decode::{PcpBroadcast, PcpChannelInfo, PcpTrackInfo};
 use super::util::atom as _in;
 use super::{Atom, ChannelInfo, ChildAtom, GnuId, Id4, TrackInfo};
 
@@ -14,7 +14,7 @@ use super::{Atom, ChannelInfo, ChildAtom, GnuId, Id4, TrackInfo};
 // Parent(PCP_CHAN, vec[
 //  Child(PCP_CHAN_ID, GnuID),      // BroadcastID == ChannelID
 //  Parent(PCP_CHAN_PKT, vec[
-//    Child(PCP_CHAN_PKT_TYPE, PCP_CHAN_PKT_[HEAD|DATA]) // type
+//    Child(PCP_CHAN_PKT_TYPE, PCP_CHAN_PKT_[HEAD|DATA|META]) // type
 //    Child(PCP_CHAN_PKT_POS, u32),
 //    Child(PCP_CHAN_PKT_CONTINUATION, true); // YT only
 //    Child(PCP_CHAN_PKT_DATA, payload)
@@ -35,6 +35,17 @@ pub enum ClassifyAtom {
         pos: u32,
         continuation: Option<bool>,
     },
+    ChanPktMeta {
+        atom: Atom,
+        payload: Bytes,
+        pos: u32,
+        // ICYのメタデータだった場合の曲情報(titleとurlだけ)
+        track: Option<PcpTrackInfo>,
+    },
+    Broadcast {
+        atom: Atom,
+        broadcast: PcpBroadcast,
+    },
     Unknown {
         atom: Atom,
     },
@@ -45,10 +56,12 @@ pub enum ClassifyAtom {
 pub enum ChanPktDataType {
     Head,
     Data,
+    Meta,
 }
 impl ChanPktDataType {
     const HEAD: u32 = Id4::PCP_CHAN_PKT_HEAD.0;
     const DATA: u32 = Id4::PCP_CHAN_PKT_DATA.0;
+    const META: u32 = Id4::PCP_CHAN_PKT_META.0;
 }
 
 impl ClassifyAtom {
@@ -62,7 +75,9 @@ impl ClassifyAtom {
 
                 let id = get_id(atoms);
                 let Some((typ_, pos, data, continuation)) = split_pkt(atoms) else {
-                    panic!("入ってるはず・・・")
+                    // 種類が分からないか、必要な値が足りない
+                    warn!("invalid channel packet: {:?}", &atom);
+                    return Self::Unknown { atom };
                 };
                 let info = get_info(atoms);
                 let track = get_track(atoms);
@@ -89,8 +104,26 @@ impl ClassifyAtom {
                             continuation,
                         }
                     }
+                    //
+                    ChanPktDataType::Meta => {
+                        let track = PcpTrackInfo::parse_icy_metadata(&data);
+                        ClassifyAtom::ChanPktMeta {
+                            atom,
+                            payload: data,
+                            pos,
+                            track,
+                        }
+                    }
                 }
             }
+            // BCST
+            Id4::PCP_BCST => match PcpBroadcast::parse(&atom) {
+                Ok(broadcast) => ClassifyAtom::Broadcast { atom, broadcast },
+                Err(e) => {
+                    error!("invalid bcst atom: {e:?}");
+                    Self::Unknown { atom }
+                }
+            },
             _ => {
                 error!("atom: {:#?}", &atom);
                 Self::Unknown { atom }
@@ -141,11 +174,15 @@ fn get_track(atoms: &Vec<Atom>) -> Option<TrackInfo> {
 // in PKT ATOM
 //
 fn get_pkt_type(atoms: &Vec<Atom>) -> Option<ChanPktDataType> {
-    _get_by_id(Id4::PCP_CHAN_PKT_TYPE, atoms).map(|a| {
+    _get_by_id(Id4::PCP_CHAN_PKT_TYPE, atoms).and_then(|a| {
         match Id4::from(_in::to_u32_be(&a.as_child().payload())) {
-            Id4::PCP_CHAN_PKT_DATA => ChanPktDataType::Data,
-            Id4::PCP_CHAN_PKT_HEAD => ChanPktDataType::Head,
-            _ => panic!("error"),
+            Id4::PCP_CHAN_PKT_DATA => Some(ChanPktDataType::Data),
+            Id4::PCP_CHAN_PKT_HEAD => Some(ChanPktDataType::Head),
+            Id4::PCP_CHAN_PKT_META => Some(ChanPktDataType::Meta),
+            id => {
+                warn!("unknown channel packet type: {id:?}");
+                None
+            }
         }
     })
 }
@@ -167,9 +204,9 @@ fn split_pkt(atoms: &Vec<Atom>) -> Option<(ChanPktDataType, u32, Bytes, Option<b
     };
     let atoms = pkt_atom.as_parent().childs();
 
-    let typ = get_pkt_type(atoms).unwrap();
-    let pos = get_pkt_pos(atoms).unwrap();
-    let data = get_pkt_data(atoms).unwrap();
+    let typ = get_pkt_type(atoms)?;
+    let pos = get_pkt_pos(atoms)?;
+    let data = get_pkt_data(atoms)?;
     let continuing = get_pkt_continuing(atoms);
 
     Some((typ, pos, data, continuing))
@@ -177,11 +214,11 @@ fn split_pkt(atoms: &Vec<Atom>) -> Option<(ChanPktDataType, u32, Bytes, Option<b
 
 #[cfg(test)]
 mod t {
-    use bytes::Buf;
+    use bytes::{Buf, Bytes};
 
-    use crate::pcp::{builder::HelloBuilder, GnuId, Id4};
+    use crate::pcp::{builder::HelloBuilder, Atom, ChildAtom, GnuId, Id4, ParentAtom};
 
-    use super::get_by_id;
+    use super::{get_by_id, ClassifyAtom};
 
     #[test]
     fn test_utils() {
@@ -195,4 +232,62 @@ mod t {
         let sid_u = a.as_child().payload().get_u128();
         assert_eq!(sid.0, sid_u);
     }
+
+    fn chan_pkt(childs: Vec<Atom>) -> Atom {
+        ParentAtom::from((
+            Id4::PCP_CHAN,
+            vec![
+                ChildAtom::from((Id4::PCP_CHAN_ID, GnuId::new())).into(),
+                ParentAtom::from((Id4::PCP_CHAN_PKT, childs)).into(),
+            ],
+        ))
+        .into()
+    }
+
+    #[test]
+    fn test_classify_meta() {
+        let payload = Bytes::from_static(b"StreamTitle='title';StreamUrl='http://example.com/';");
+        let atom = chan_pkt(vec![
+            ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_META)).into(),
+            ChildAtom::from((Id4::PCP_CHAN_PKT_POS, 10_u32)).into(),
+            ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, payload.clone())).into(),
+        ]);
+
+        let ClassifyAtom::ChanPktMeta {
+            payload: p,
+            pos,
+            track,
+            ..
+        } = ClassifyAtom::classify(atom)
+        else {
+            panic!("must be meta");
+        };
+        assert_eq!(p, payload);
+        assert_eq!(pos, 10);
+        let track = track.unwrap();
+        assert_eq!(track.title.as_deref(), Some("title"));
+        assert_eq!(track.url.as_deref(), Some("http://example.com/"));
+    }
+
+    #[test]
+    fn test_classify_invalid_packet() {
+        // 知らない種類のパケットやDATAの無いパケットでpanicしない
+        let atom = chan_pkt(vec![
+            ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_OK)).into(),
+            ChildAtom::from((Id4::PCP_CHAN_PKT_POS, 0_u32)).into(),
+            ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, Bytes::new())).into(),
+        ]);
+        assert!(matches!(
+            ClassifyAtom::classify(atom),
+            ClassifyAtom::Unknown { .. }
+        ));
+        let atom = chan_pkt(vec![
+            ChildAtom::from((Id4::PCP_CHAN_PKT_TYPE, Id4::PCP_CHAN_PKT_DATA)).into(),
+            ChildAtom::from((Id4::PCP_CHAN_PKT_POS, 0_u32)).into(),
+        ]);
+        assert!(matches!(
+            ClassifyAtom::classify(atom),
+            ClassifyAtom::Unknown { .. }
+        ));
+    }
 }
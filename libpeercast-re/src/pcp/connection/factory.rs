@@ -151,6 +151,9 @@ impl PcpConnectionFactory {
     pub fn accept(&self, cid: ConnectionId, stream: TcpStream, remote: SocketAddr) -> PcpHandshake {
         self.impl_.accept(cid, stream, remote, &self)
     }
+    pub fn session_id(&self) -> GnuId {
+        self.impl_.self_session_id
+    }
 }
 
 //--------------------------------------------------------------------------------
@@ -9,6 +9,7 @@ pub(self) mod RawQuitCode {
     pub const PCP_ERROR_UNAVAILABLE: u32 = 3;
     pub const PCP_ERROR_OFFAIR: u32 = 8;
     pub const PCP_ERROR_SHUTDOWN: u32 = 9;
+    pub const PCP_ERROR_BANNED: u32 = 11;
 }
 
 use RawQuitCode::*;
@@ -22,6 +23,7 @@ impl QuitCode {
     pub const UNAVAILABLE_ERROR: u32 = PCP_ERROR_QUIT + PCP_ERROR_UNAVAILABLE;
     pub const NO_HOST_OR_OFFAIR: u32 = PCP_ERROR_QUIT + PCP_ERROR_OFFAIR;
     pub const USER_SHUTDOWN: u32 = PCP_ERROR_QUIT + PCP_ERROR_SHUTDOWN;
+    pub const BANNED: u32 = PCP_ERROR_QUIT + PCP_ERROR_BANNED;
 }
 /*
 https://github.com/kimoto/peercast/blob/master/core/common/pcp.h
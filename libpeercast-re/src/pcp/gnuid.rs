@@ -42,6 +42,37 @@ impl GnuId {
     pub fn zero() -> Self {
         GnuId(0_u128)
     }
+
+    /// 配信者ID(BCID)からチャンネルIDを作る
+    /// PeerCastの `info.id = broadcastID; info.id.encode(NULL, name, genre, bitrate)` と同じ
+    pub fn channel_id(&self, name: &str, genre: &str, bitrate: i32) -> GnuId {
+        self.encode(name.as_bytes(), genre.as_bytes(), bitrate as u8)
+    }
+
+    // PeerCastのGnuID::encode(ホストのIPは使わない)
+    // saltを使い切ったらその桁は混ぜずに先頭に戻る
+    fn encode(&self, salt1: &[u8], salt2: &[u8], salt3: u8) -> GnuId {
+        let mut id = self.0.to_be_bytes();
+        let (mut s1, mut s2) = (0, 0);
+        for b in id.iter_mut() {
+            match salt1.get(s1) {
+                Some(&c) if c != 0 => {
+                    *b ^= c;
+                    s1 += 1;
+                }
+                _ => s1 = 0,
+            }
+            match salt2.get(s2) {
+                Some(&c) if c != 0 => {
+                    *b ^= c;
+                    s2 += 1;
+                }
+                _ => s2 = 0,
+            }
+            *b ^= salt3;
+        }
+        GnuId(u128::from_be_bytes(id))
+    }
 }
 
 impl Default for GnuId {
@@ -239,6 +270,35 @@ mod tests {
         assert_eq!(format!("{:.4}", id), String::from("1A00.."));
     }
 
+    #[test]
+    fn test_channel_id() {
+        let id = |s: &str| GnuId::from_str(s).unwrap();
+        // 名前を使い切ると1桁飛ばして先頭から
+        assert_eq!(
+            GnuId::zero().channel_id("A", "", 0),
+            id("41004100410041004100410041004100")
+        );
+        // PeerCastのGnuID::encode(C++)をそのまま移した別実装で求めた値
+        assert_eq!(
+            id("0123456789ABCDEF0123456789ABCDEF").channel_id("テスト配信", "game", 500),
+            id("71355A15FF81BBF5183E537FF485987C")
+        );
+        assert_eq!(
+            id("F0E1D2C3B4A5968778695A4B3C2D1E0F").channel_id("peercast-re", "", 1500),
+            id("5C586B6D0B18392F89C7E3979094A7A1")
+        );
+        // 同じ入力なら何度作っても同じ
+        let bcid = GnuId::new_random();
+        assert_eq!(
+            bcid.channel_id("ch", "genre", 1000),
+            bcid.channel_id("ch", "genre", 1000)
+        );
+        assert_ne!(
+            bcid.channel_id("ch", "genre", 1000),
+            bcid.channel_id("ch2", "genre", 1000)
+        );
+    }
+
     #[test]
     fn test_for_serde() {
         let g: GnuId = GnuId::from_str("1234567890000000000000000000000A").unwrap();
@@ -1,10 +1,11 @@
-use std::fmt::Write;
+use std::{fmt::Write, str::FromStr};
 
 use bytes::{Buf, BufMut, BytesMut};
 use http::{Request, StatusCode, Version};
+use tokio::{io::AsyncReadExt, net::TcpStream};
 use tracing::debug;
 
-use crate::pcp::GnuId;
+use crate::{error::HandshakeError, pcp::GnuId};
 
 pub(super) struct RequestHead {
     method: http::Method,
@@ -43,10 +44,18 @@ impl From<RequestHead> for bytes::BytesMut {
     }
 }
 
-pub(super) fn create_channel_request(broadcast_id: GnuId) -> BytesMut {
+pub(super) fn create_channel_request(broadcast_id: GnuId, token: Option<&str>) -> BytesMut {
+    let uri = match token {
+        Some(token) => format!(
+            "/channel/{}?auth={}",
+            broadcast_id,
+            urlencoding::encode(token)
+        ),
+        None => format!("/channel/{}", broadcast_id),
+    };
     let req = Request::builder()
         .method("GET")
-        .uri(format!("/channel/{}", broadcast_id))
+        .uri(uri)
         .header("x-peercast-pcp", "1")
         .body(())
         .unwrap();
@@ -92,3 +101,74 @@ pub(super) fn parse_pcp_http_response(
         Err(e) => Err(e.into()),
     }
 }
+
+/// リレーのリクエスト GET /channel/[GnuID]?auth=[TOKEN]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelRequest {
+    pub channel_id: GnuId,
+    /// 限定公開チャンネルの視聴トークン
+    pub token: Option<String>,
+}
+
+/// ヘッダーの終わりまで読む。残りはread_bufに残る
+pub async fn read_channel_request(
+    stream: &mut TcpStream,
+    read_buf: &mut BytesMut,
+) -> Result<ChannelRequest, HandshakeError> {
+    loop {
+        if let Some((req, header_bytes_len)) = parse_channel_request(read_buf)? {
+            read_buf.advance(header_bytes_len);
+            return Ok(req);
+        }
+        if stream.read_buf(read_buf).await? == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+        }
+    }
+}
+
+fn parse_channel_request(buf: &[u8]) -> Result<Option<(ChannelRequest, usize)>, HandshakeError> {
+    let mut parsed_headers = [httparse::EMPTY_HEADER; 64];
+    let mut request = httparse::Request::new(&mut parsed_headers);
+
+    match request.parse(buf) {
+        Ok(httparse::Status::Partial) => Ok(None),
+        Ok(httparse::Status::Complete(header_bytes_len)) => {
+            let path = request.path.unwrap_or_default();
+            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+            let channel_id = path
+                .strip_prefix("/channel/")
+                .and_then(|id| id.split('.').next())
+                .and_then(|id| GnuId::from_str(id).ok())
+                .ok_or(HandshakeError::Failed)?;
+            let token = url::form_urlencoded::parse(query.as_bytes())
+                .find(|(k, _)| k == "auth")
+                .map(|(_, v)| v.into_owned());
+            Ok(Some((
+                ChannelRequest { channel_id, token },
+                header_bytes_len,
+            )))
+        }
+        Err(_) => Err(HandshakeError::Failed),
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_channel_request() {
+        let id = GnuId::new();
+        let buf = create_channel_request(id, Some("1700000000.ab+cd"));
+        let (req, len) = parse_channel_request(&buf).unwrap().unwrap();
+        assert_eq!(len, buf.len());
+        assert_eq!(req.channel_id, id);
+        assert_eq!(req.token.as_deref(), Some("1700000000.ab+cd"));
+
+        let buf = create_channel_request(id, None);
+        let (req, _) = parse_channel_request(&buf).unwrap().unwrap();
+        assert_eq!(req.token, None);
+        assert!(parse_channel_request(&buf[..10]).unwrap().is_none());
+        assert!(parse_channel_request(b"GET /stream/x HTTP/1.0\r\n\r\n").is_err());
+    }
+}
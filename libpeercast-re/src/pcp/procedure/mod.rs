@@ -1,4 +1,9 @@
 mod http_req;
 mod pcp_handshake;
+mod reject;
+mod relay_out;
 
+pub use http_req::{read_channel_request, ChannelRequest};
 pub use pcp_handshake::{HandshakeReturn, PcpHandshake};
+pub use reject::reject_banned;
+pub use relay_out::{reject_relay_full, relay_out};
@@ -71,8 +71,9 @@ impl PcpHandshake {
     pub async fn outgoing(
         mut self,
         broadcast_id: GnuId,
+        token: Option<&str>,
     ) -> Result<HandshakeReturn<TcpStream>, HandshakeError> {
-        let mut req_buf = create_channel_request(broadcast_id);
+        let mut req_buf = create_channel_request(broadcast_id, token);
 
         // ヘッダーの送信
         while req_buf.has_remaining() {
@@ -104,7 +105,7 @@ impl PcpHandshake {
                 // 200: チャンネルはあってリレー可能？
                 // PCPのハンドシェイク接続, PCP_OKが来て、その後PCP_CHAN_PKTでチャンネルの情報とストリームがだらだら来る
                 // あとは適当な間隔でPCP_HOSTをPCP_BCSTにつけて流してあげればよい
-                let oleh = self.send_hello(broadcast_id).await?;
+                let oleh = self.send_hello().await?;
                 let _ = self.recv_ok().await?;
 
                 let Self {
@@ -125,7 +126,7 @@ impl PcpHandshake {
                 // 503: チャンネルはあるけどリレーできない
                 // 次に接続すべきノードがPCP_HOSTで最大8個流れてきてPCP_QUITで終了
                 // Self::send_helo().await;
-                let oleh = self.send_hello(broadcast_id).await?;
+                let oleh = self.send_hello().await?;
                 let (hosts, quit) = self.recv_hosts_and_quit().await?;
 
                 Ok(HandshakeReturn::NextHost { oleh, hosts, quit })
@@ -134,6 +135,10 @@ impl PcpHandshake {
                 // 配信終了後はこれになるっぽいんだよね
                 Err(HandshakeError::ChannelNotFound)
             }
+            403 => {
+                // 限定公開チャンネルでトークンが無いか間違っている
+                Err(HandshakeError::Forbidden)
+            }
             _ => {
                 // something occured
                 todo!()
@@ -217,12 +222,12 @@ impl PcpHandshake {
     }
 
     /// Send Hello then Recv OLEH
-    async fn send_hello(&mut self, broadcast_id: GnuId) -> Result<OlehInfo, HandshakeError> {
+    async fn send_hello(&mut self) -> Result<OlehInfo, HandshakeError> {
         let mut payload = BytesMut::new();
 
         // HELOを送信
-        // FIX: ROOTへの送信か確認せねば・・・
-        let mut builder = HelloBuilder::new(self.self_session_id, broadcast_id.into());
+        // BCIDは配信者がROOTに送るものなので、リレーでは付けない(チャンネルIDを入れてはいけない)
+        let mut builder = HelloBuilder::new(self.self_session_id, None);
         if (self.self_addr.is_some()) {
             let port = self.self_addr.as_ref().unwrap().port();
             builder = builder.port(port).ping(port);
@@ -0,0 +1,43 @@
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tracing::info;
+
+use crate::{
+    error::{AtomParseError, HandshakeError},
+    pcp::{
+        builder::{OlehBuilder, QuitBuilder, QuitReason},
+        decode::PcpHelo,
+        read_atom, GnuId, Id4,
+    },
+};
+
+/// BANしている相手からのPCP接続を断る
+/// PeerCastと同じくHELOを受け取ってから、dis付きのOLEHとQUIT(BANNED)を返して切る
+pub async fn reject_banned(
+    stream: &mut TcpStream,
+    remote: SocketAddr,
+    self_session_id: GnuId,
+) -> Result<(), HandshakeError> {
+    let mut read_buf = BytesMut::with_capacity(4096);
+    let connect = read_atom(stream, &mut read_buf).await?;
+    if connect.id() != Id4::PCP_CONNECT {
+        return Err(AtomParseError::IdError.into());
+    }
+    let helo = read_atom(stream, &mut read_buf).await?;
+    let port = PcpHelo::parse(&helo).ok().and_then(|h| h.port).unwrap_or(0);
+
+    let mut buf = BytesMut::new();
+    OlehBuilder::new(self_session_id, remote.ip(), port)
+        .disable(true)
+        .build()
+        .write_bytes(&mut buf);
+    QuitBuilder::new(QuitReason::Banned)
+        .build()
+        .write_bytes(&mut buf);
+    stream.write_all_buf(&mut buf).await?;
+    stream.shutdown().await?;
+    info!("rejected banned PCP connection from {remote}");
+    Ok(())
+}
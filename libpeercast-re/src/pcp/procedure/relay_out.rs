@@ -0,0 +1,254 @@
+use std::net::SocketAddr;
+
+use bytes::BytesMut;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tracing::{debug, info};
+
+use crate::{
+    bandwidth::Throttle,
+    connection_registry::ConnectionHandle,
+    error::HandshakeError,
+    pcp::{
+        builder::{OkBuilder, OlehBuilder, QuitBuilder, QuitReason},
+        decode::PcpHelo,
+        read_atom, Atom, AtomDirection, ChannelMessage, ChannelReciever, GnuId, Id4,
+    },
+};
+
+const RESPONSE_OK: &[u8] = b"HTTP/1.0 200 OK\r\nContent-Type: application/x-peercast-pcp\r\n\r\n";
+const RESPONSE_UNAVAILABLE: &[u8] =
+    b"HTTP/1.0 503 Service Unavailable\r\nContent-Type: application/x-peercast-pcp\r\n\r\n";
+
+/// 503で渡すHOSTの最大数(PeerCastと同じ)
+const MAX_REDIRECT_HOSTS: usize = 8;
+
+/// HELOを受け取ってOLEHを返す
+async fn accept_helo(
+    stream: &mut TcpStream,
+    read_buf: &mut BytesMut,
+    remote: SocketAddr,
+    self_session_id: GnuId,
+) -> Result<PcpHelo, HandshakeError> {
+    let helo = read_atom(stream, read_buf).await?;
+    let helo = PcpHelo::parse(&helo)?;
+
+    let mut buf = BytesMut::new();
+    OlehBuilder::new(self_session_id, remote.ip(), helo.port.unwrap_or(0))
+        .build()
+        .write_bytes(&mut buf);
+    stream.write_all_buf(&mut buf).await?;
+    Ok(helo)
+}
+
+/// リレーの上限に達している時
+/// PeerCastと同じく503を返し、HELOを受け取ってから次に繋ぐべきHOSTとQUIT(UNAVAILABLE)を送って切る
+pub async fn reject_relay_full(
+    stream: &mut TcpStream,
+    mut read_buf: BytesMut,
+    remote: SocketAddr,
+    self_session_id: GnuId,
+    hosts: Vec<Atom>,
+) -> Result<(), HandshakeError> {
+    stream.write_all(RESPONSE_UNAVAILABLE).await?;
+    accept_helo(stream, &mut read_buf, remote, self_session_id).await?;
+
+    let mut buf = BytesMut::new();
+    for host in hosts.iter().take(MAX_REDIRECT_HOSTS) {
+        host.write_bytes(&mut buf);
+    }
+    QuitBuilder::new(QuitReason::UnavailableError)
+        .build()
+        .write_bytes(&mut buf);
+    stream.write_all_buf(&mut buf).await?;
+    stream.shutdown().await?;
+    info!(
+        "redirected PCP relay from {remote} to {} hosts",
+        hosts.len()
+    );
+    Ok(())
+}
+
+/// リレーを受ける。200とOLEH・OKを返した後、チャンネルのAtomをそのまま下流に流し続ける
+/// 下流から来たBCSTは上流に転送せず読み捨てる
+/// Atomはthrottleで上り帯域を待ってから送る
+pub async fn relay_out(
+    stream: &mut TcpStream,
+    mut read_buf: BytesMut,
+    remote: SocketAddr,
+    self_session_id: GnuId,
+    mut reciever: ChannelReciever,
+    handle: &mut ConnectionHandle,
+    throttle: &Throttle,
+) -> Result<(), HandshakeError> {
+    stream.write_all(RESPONSE_OK).await?;
+    let helo = accept_helo(stream, &mut read_buf, remote, self_session_id).await?;
+    handle.set_user_agent(helo.agent);
+    send_atom(stream, handle, throttle, OkBuilder::new(0).build()).await?;
+
+    loop {
+        tokio::select! {
+            message = reciever.recv() => {
+                let atom = match message {
+                    // チャンネルが終わった
+                    None => {
+                        let quit = QuitBuilder::new(QuitReason::NoHostOrOffAir).build();
+                        send_atom(stream, handle, throttle, quit).await?;
+                        break;
+                    }
+                    Some(ChannelMessage::RelayChannelHead { atom, .. })
+                    | Some(ChannelMessage::RelayChannelData { atom, .. })
+                    | Some(ChannelMessage::RelayChannelMeta { atom, .. })
+                    | Some(ChannelMessage::BroadcastAtom {
+                        direction: AtomDirection::UpToDown,
+                        atom,
+                    }) => atom,
+                    Some(ChannelMessage::BroadcastAtom { .. }) => continue,
+                };
+                send_atom(stream, handle, throttle, atom).await?;
+            }
+            atom = read_atom(stream, &mut read_buf) => {
+                let atom = atom?;
+                if atom.id() == Id4::PCP_QUIT {
+                    debug!("PCP relay {remote} quit");
+                    break;
+                }
+            }
+            // APIから切断を要求された
+            quit_code = handle.disconnect_requested() => {
+                info!("disconnect requested {remote} quit_code:{quit_code}");
+                let quit = Atom::Child((Id4::PCP_QUIT, quit_code).into());
+                send_atom(stream, handle, throttle, quit).await?;
+                break;
+            }
+        }
+    }
+
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn send_atom(
+    stream: &mut TcpStream,
+    handle: &ConnectionHandle,
+    throttle: &Throttle,
+    atom: Atom,
+) -> Result<(), HandshakeError> {
+    let mut buf = BytesMut::new();
+    atom.write_bytes(&mut buf);
+    throttle.acquire(buf.len()).await;
+    handle.add_bytes_out(buf.len());
+    stream.write_all_buf(&mut buf).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use tokio::net::TcpListener;
+
+    use crate::{
+        bandwidth::UploadLimiter,
+        connection_registry::{ConnectionLimits, Protocol},
+        pcp::{
+            builder::HostBuilder,
+            procedure::{read_channel_request, HandshakeReturn, PcpHandshake},
+            ChannelManager, ChannelType,
+        },
+        ConnectionId,
+    };
+
+    use super::*;
+
+    async fn connect(addr: SocketAddr, channel_id: GnuId) -> HandshakeReturn<TcpStream> {
+        let stream = TcpStream::connect(addr).await.unwrap();
+        PcpHandshake::new(
+            ConnectionId::new(),
+            stream,
+            None,
+            addr,
+            BytesMut::new(),
+            GnuId::new(),
+        )
+        .outgoing(channel_id, None)
+        .await
+        .unwrap()
+    }
+
+    #[crate::test]
+    async fn test_reject_relay_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let channel_id = GnuId::new();
+        let next: SocketAddr = "203.0.113.1:7144".parse().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, remote) = listener.accept().await.unwrap();
+            let mut read_buf = BytesMut::new();
+            let req = read_channel_request(&mut stream, &mut read_buf)
+                .await
+                .unwrap();
+            assert_eq!(req.channel_id, channel_id);
+            let hosts = vec![HostBuilder::new(channel_id, next).build()];
+            reject_relay_full(&mut stream, read_buf, remote, GnuId::new(), hosts)
+                .await
+                .unwrap();
+        });
+
+        match connect(addr, channel_id).await {
+            HandshakeReturn::NextHost { hosts, .. } => {
+                assert_eq!(hosts.len(), 1);
+                assert_eq!(hosts[0].global_address, Some(next));
+            }
+            r => panic!("unexpected {r:?}"),
+        }
+        server.await.unwrap();
+    }
+
+    #[crate::test]
+    async fn test_relay_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let session_id = GnuId::new();
+        let manager = ChannelManager::new(&session_id);
+        let ch = manager
+            .create(GnuId::new(), ChannelType::Broadcast, None, None)
+            .unwrap();
+        let channel_id = ch.id();
+        let connections = manager.connections();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, remote) = listener.accept().await.unwrap();
+            let mut read_buf = BytesMut::new();
+            read_channel_request(&mut stream, &mut read_buf)
+                .await
+                .unwrap();
+            let connection_id = ConnectionId::new();
+            let mut handle = connections
+                .try_register(
+                    connection_id,
+                    Protocol::PcpRelayOut,
+                    remote,
+                    channel_id,
+                    &ConnectionLimits::default(),
+                )
+                .unwrap();
+            let reciever = ch.channel_reciever(connection_id);
+            // 下流が切ったら終わる
+            let _ = relay_out(
+                &mut stream,
+                read_buf,
+                remote,
+                session_id,
+                reciever,
+                &mut handle,
+                &UploadLimiter::new(Default::default()).throttle(&channel_id),
+            )
+            .await;
+        });
+
+        let HandshakeReturn::Success { oleh, .. } = connect(addr, channel_id).await else {
+            panic!("relay was not accepted");
+        };
+        assert_eq!(oleh.session_id, session_id);
+        server.await.unwrap();
+    }
+}
@@ -11,7 +11,11 @@ use crate::{
     pcp::{atom, Atom, GnuId, Id4},
 };
 
-use super::{classify::ClassifyAtom, ChannelInfo, TrackInfo};
+use super::{
+    classify::ClassifyAtom,
+    decode::{PcpBroadcast, PcpTrackInfo},
+    ChannelInfo, TrackInfo,
+};
 
 #[derive(Debug, Error)]
 pub enum SessionError {
@@ -93,6 +97,23 @@ impl Session {
                             pos,
                             continuation,
                         }),
+                        ClassifyAtom::ChanPktMeta {
+                            atom,
+                            payload,
+                            pos,
+                            track,
+                        } => SessionResult::RaisedEvent(SessionEvent::ArrivedMetaData {
+                            atom,
+                            meta: payload,
+                            pos,
+                            track,
+                        }),
+                        ClassifyAtom::Broadcast { atom, broadcast } => {
+                            SessionResult::RaisedEvent(SessionEvent::ArrivedBroadcast {
+                                atom,
+                                broadcast,
+                            })
+                        }
                         ClassifyAtom::Unknown { atom } => {
                             continue;
                         }
@@ -138,6 +159,17 @@ pub enum SessionEvent {
         info: Option<ChannelInfo>,
         track: Option<TrackInfo>,
     },
+    ArrivedMetaData {
+        atom: Atom,
+        meta: Bytes,
+        pos: u32,
+        //
+        track: Option<PcpTrackInfo>,
+    },
+    ArrivedBroadcast {
+        atom: Atom,
+        broadcast: PcpBroadcast,
+    },
 }
 
 impl std::fmt::Debug for SessionEvent {
@@ -169,6 +201,22 @@ impl std::fmt::Debug for SessionEvent {
                 .field("info", info)
                 .field("track", track)
                 .finish_non_exhaustive(),
+            Self::ArrivedMetaData {
+                atom,
+                meta,
+                pos,
+                track,
+            } => f
+                .debug_struct("ArrivedMetaData")
+                // .field("atom", atom)
+                // .field("meta", meta)
+                .field("pos", pos)
+                .field("track", track)
+                .finish_non_exhaustive(),
+            Self::ArrivedBroadcast { atom, broadcast } => f
+                .debug_struct("ArrivedBroadcast")
+                .field("broadcast", broadcast)
+                .finish_non_exhaustive(),
         }
     }
 }
@@ -0,0 +1,212 @@
+//! チャンネルの受信内容をファイルに保存する
+//!
+//! ChannelRecieverから受け取ったHead/Dataをそのまま書き出すので、
+//! FLVでもMPEG-TSでも同じように扱える
+
+mod writer;
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    error::RecordError,
+    pcp::{Channel, GnuId},
+    ConnectionId,
+};
+
+use writer::RecordWriter;
+
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub directory: PathBuf,
+    /// {name}, {id}, {time} を置き換える
+    pub filename_template: String,
+    pub rotate_size: Option<u64>,
+    pub rotate_duration: Option<Duration>,
+}
+
+impl RecorderConfig {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            directory: config.record_directory.clone(),
+            filename_template: config.record_filename.clone(),
+            rotate_size: (config.record_rotate_size > 0).then_some(config.record_rotate_size),
+            rotate_duration: (config.record_rotate_duration > 0)
+                .then(|| Duration::from_secs(config.record_rotate_duration)),
+        }
+    }
+
+    /// 拡張子を含めたファイル名を作る
+    fn filename(&self, name: &str, id: &GnuId, time: DateTime<Local>, ext: &str) -> String {
+        let stem = self
+            .filename_template
+            .replace("{name}", &sanitize_filename(name))
+            .replace("{id}", &id.to_string())
+            .replace("{time}", &time.format("%Y%m%d-%H%M%S").to_string());
+        format!("{stem}{ext}")
+    }
+}
+
+// ファイル名に使えない文字を置き換える
+fn sanitize_filename(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+    match name.trim() {
+        "" => "noname".into(),
+        name => name.into(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStatus {
+    pub channel_id: GnuId,
+    pub recording: bool,
+    /// 書き込み中(終了後は最後)のファイル
+    pub path: Option<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub bytes_written: u64,
+    pub started_at: DateTime<Utc>,
+}
+
+impl RecordingStatus {
+    fn new(channel_id: GnuId) -> Self {
+        Self {
+            channel_id,
+            recording: true,
+            path: None,
+            files: vec![],
+            bytes_written: 0,
+            started_at: Utc::now(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Recorder {
+    status: Arc<Mutex<RecordingStatus>>,
+    handle: JoinHandle<()>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// RecorderManager
+//
+#[derive(Debug)]
+pub struct RecorderManager {
+    config: RecorderConfig,
+    recorders: Mutex<HashMap<GnuId, Recorder>>,
+}
+
+impl RecorderManager {
+    pub fn new(config: RecorderConfig) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            recorders: Default::default(),
+        })
+    }
+
+    pub fn start(&self, channel: &Channel) -> Result<RecordingStatus, RecordError> {
+        let mut recorders = self.recorders.lock().unwrap();
+        if let Some(r) = recorders.get(&channel.id()) {
+            if !r.handle.is_finished() {
+                return Err(RecordError::AlreadyRecording);
+            }
+        }
+        std::fs::create_dir_all(&self.config.directory)?;
+
+        let status = Arc::new(Mutex::new(RecordingStatus::new(channel.id())));
+        let writer = RecordWriter::new(self.config.clone(), channel.clone(), Arc::clone(&status));
+        let reciever = channel.channel_reciever(ConnectionId::new());
+        let handle = tokio::spawn(async move {
+            let channel_id = writer.channel_id();
+            if let Err(e) = writer.run(reciever).await {
+                warn!("recorder CID:{channel_id} stopped by error: {e}");
+            }
+        });
+        info!("START recording CID:{}", channel.id());
+
+        let current = status.lock().unwrap().clone();
+        recorders.insert(channel.id(), Recorder { status, handle });
+        Ok(current)
+    }
+
+    pub fn stop(&self, channel_id: &GnuId) -> Result<RecordingStatus, RecordError> {
+        let Some(recorder) = self.recorders.lock().unwrap().remove(channel_id) else {
+            return Err(RecordError::NotRecording);
+        };
+        // abortで止めてもファイルはdropされた時点で閉じられる
+        recorder.handle.abort();
+        info!("STOP recording CID:{channel_id}");
+
+        let mut status = recorder.status.lock().unwrap().clone();
+        status.recording = false;
+        Ok(status)
+    }
+
+    pub fn status(&self, channel_id: &GnuId) -> Option<RecordingStatus> {
+        let recorders = self.recorders.lock().unwrap();
+        recorders.get(channel_id).map(Self::current_status)
+    }
+
+    pub fn list(&self) -> Vec<RecordingStatus> {
+        let recorders = self.recorders.lock().unwrap();
+        recorders.values().map(Self::current_status).collect()
+    }
+
+    fn current_status(recorder: &Recorder) -> RecordingStatus {
+        let mut status = recorder.status.lock().unwrap().clone();
+        status.recording = !recorder.handle.is_finished();
+        status
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_filename() {
+        let config = RecorderConfig {
+            directory: PathBuf::from("rec"),
+            filename_template: "{name}_{time}".into(),
+            rotate_size: None,
+            rotate_duration: None,
+        };
+        let time = Local.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        let id = GnuId::new();
+        assert_eq!(
+            config.filename("予約/テスト: ch", &id, time, ".flv"),
+            "予約_テスト_ ch_20240102-030405.flv"
+        );
+        assert_eq!(
+            config.filename(" ", &id, time, ".ts"),
+            "noname_20240102-030405.ts"
+        );
+
+        let config = RecorderConfig {
+            filename_template: "{id}".into(),
+            ..config
+        };
+        assert_eq!(
+            config.filename("ch", &id, time, ".flv"),
+            format!("{id}.flv")
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use chrono::Local;
+use tokio::{fs::File, io::AsyncWriteExt, time::Instant};
+use tracing::{debug, info};
+
+use crate::{
+    error::RecordError,
+    pcp::{Channel, ChannelMessage, ChannelReciever, GnuId},
+};
+
+use super::{RecorderConfig, RecordingStatus};
+
+const DEFAULT_EXT: &str = ".flv";
+
+struct OpenedFile {
+    file: File,
+    bytes: u64,
+    opened_at: Instant,
+}
+
+/// ChannelMessageを受け取ってファイルに書き出す
+/// 新しいファイルは必ずキーフレームから始まり、先頭にHeadを書く
+pub(super) struct RecordWriter {
+    config: RecorderConfig,
+    channel: Channel,
+    status: Arc<Mutex<RecordingStatus>>,
+    //
+    head: Option<Bytes>,
+    current: Option<OpenedFile>,
+    // Headが変わったので次のキーフレームでファイルを分ける
+    head_changed: bool,
+}
+
+impl RecordWriter {
+    pub(super) fn new(
+        config: RecorderConfig,
+        channel: Channel,
+        status: Arc<Mutex<RecordingStatus>>,
+    ) -> Self {
+        Self {
+            config,
+            channel,
+            status,
+            head: None,
+            current: None,
+            head_changed: false,
+        }
+    }
+
+    pub(super) fn channel_id(&self) -> GnuId {
+        self.channel.id()
+    }
+
+    pub(super) async fn run(mut self, mut reciever: ChannelReciever) -> Result<(), RecordError> {
+        while let Some(message) = reciever.recv().await {
+            self.write_message(message).await?;
+        }
+        debug!("recorder CID:{} channel closed", self.channel.id());
+        self.close().await
+    }
+
+    async fn write_message(&mut self, message: ChannelMessage) -> Result<(), RecordError> {
+        match message {
+            ChannelMessage::RelayChannelHead { payload, .. } => {
+                if self.head.as_ref() != Some(&payload) {
+                    self.head_changed = self.current.is_some();
+                    self.head = Some(payload);
+                }
+            }
+            ChannelMessage::RelayChannelData {
+                payload,
+                continuation,
+                ..
+            } => {
+                let keyframe = !continuation;
+                if keyframe && (self.current.is_none() || self.should_rotate()) {
+                    self.rotate().await?;
+                }
+                // 最初のキーフレームまでは捨てる
+                if self.current.is_some() {
+                    self.write(&payload).await?;
+                }
+            }
+            ChannelMessage::RelayChannelMeta { .. } | ChannelMessage::BroadcastAtom { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn should_rotate(&self) -> bool {
+        let Some(current) = &self.current else {
+            return false;
+        };
+        let over_size = self
+            .config
+            .rotate_size
+            .is_some_and(|size| current.bytes >= size);
+        let over_duration = self
+            .config
+            .rotate_duration
+            .is_some_and(|d| current.opened_at.elapsed() >= d);
+        self.head_changed || over_size || over_duration
+    }
+
+    async fn rotate(&mut self) -> Result<(), RecordError> {
+        // Headが来ていないと再生できないファイルになる
+        let Some(head) = self.head.clone() else {
+            return Ok(());
+        };
+        self.close().await?;
+
+        let path = self.next_path();
+        info!("recorder CID:{} open {:?}", self.channel.id(), path);
+        let file = File::create(&path).await?;
+        self.current = Some(OpenedFile {
+            file,
+            bytes: 0,
+            opened_at: Instant::now(),
+        });
+        self.head_changed = false;
+        {
+            let mut status = self.status.lock().unwrap();
+            status.path = Some(path.clone());
+            status.files.push(path);
+        }
+
+        self.write(&head).await
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> Result<(), RecordError> {
+        let current = self.current.as_mut().unwrap();
+        current.file.write_all(buf).await?;
+        current.bytes += buf.len() as u64;
+        self.status.lock().unwrap().bytes_written += buf.len() as u64;
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), RecordError> {
+        if let Some(mut current) = self.current.take() {
+            current.file.flush().await?;
+        }
+        Ok(())
+    }
+
+    // 同じ秒にローテートしても上書きしないように連番を付ける
+    fn next_path(&self) -> PathBuf {
+        let info = self.channel.info().unwrap_or_default();
+        let ext = match info.stream_ext.as_str() {
+            "" => DEFAULT_EXT,
+            ext => ext,
+        };
+        let filename = self
+            .config
+            .filename(&info.name, &self.channel.id(), Local::now(), ext);
+
+        let path = self.config.directory.join(&filename);
+        if !path.exists() {
+            return path;
+        }
+        let (stem, ext) = filename.split_at(filename.len() - ext.len());
+        (1..)
+            .map(|n| self.config.directory.join(format!("{stem}-{n}{ext}")))
+            .find(|p| !p.exists())
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod t {
+    use crate::pcp::{ChannelInfo, ChannelManager, ChannelType, ChildAtom, Id4};
+
+    use super::*;
+
+    fn head(payload: &'static [u8]) -> ChannelMessage {
+        let payload = Bytes::from_static(payload);
+        ChannelMessage::RelayChannelHead {
+            atom: ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, payload.clone())).into(),
+            pos: 0,
+            payload,
+            info: None,
+            track: None,
+        }
+    }
+
+    fn data(payload: &'static [u8], keyframe: bool) -> ChannelMessage {
+        let payload = Bytes::from_static(payload);
+        ChannelMessage::RelayChannelData {
+            atom: ChildAtom::from((Id4::PCP_CHAN_PKT_DATA, payload.clone())).into(),
+            pos: 0,
+            payload,
+            continuation: !keyframe,
+        }
+    }
+
+    #[crate::test]
+    async fn test_record_writer_rotate() {
+        let directory = std::env::temp_dir().join(format!("peercast-re-rec-{}", GnuId::new()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = RecorderConfig {
+            directory: directory.clone(),
+            filename_template: "{name}".into(),
+            rotate_size: Some(8),
+            rotate_duration: None,
+        };
+
+        let manager = ChannelManager::new(&GnuId::new());
+        let mut info = ChannelInfo::new();
+        info.name = "test".into();
+        info.stream_ext = ".flv".into();
+        let channel = manager
+            .create(GnuId::new(), ChannelType::Broadcast, Some(info), None)
+            .unwrap();
+        let status = Arc::new(Mutex::new(RecordingStatus::new(channel.id())));
+        let mut writer = RecordWriter::new(config, channel, Arc::clone(&status));
+
+        for message in [
+            head(b"HEAD"),
+            // キーフレームまでは書かない
+            data(b"skip", false),
+            data(b"key1", true),
+            data(b"aaaa", false),
+            // 8byteを超えたがキーフレームが来るまでは分けない
+            data(b"bbbb", false),
+            data(b"key2", true),
+        ] {
+            writer.write_message(message).await.unwrap();
+        }
+        writer.close().await.unwrap();
+
+        let status = status.lock().unwrap().clone();
+        assert_eq!(
+            status.files,
+            vec![directory.join("test.flv"), directory.join("test-1.flv")]
+        );
+        assert_eq!(status.bytes_written, 24);
+        assert_eq!(
+            std::fs::read(directory.join("test.flv")).unwrap(),
+            b"HEADkey1aaaabbbb"
+        );
+        assert_eq!(
+            std::fs::read(directory.join("test-1.flv")).unwrap(),
+            b"HEADkey2"
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, sync::Arc};
 
 use bytes::{Bytes, BytesMut};
 use futures_util::FutureExt;
@@ -15,7 +15,11 @@ use tokio::{
     sync::mpsc,
 };
 
-use crate::rtmp::send;
+use crate::{
+    connection_registry::{ConnectionHandle, ConnectionRegistry, Protocol},
+    rtmp::send,
+    ConnectionId,
+};
 
 use super::{
     stream_manager::{ConnectionMessage, StreamManagerMessage},
@@ -27,15 +31,23 @@ pub struct Connection {
     session: Option<ServerSession>,
     stream_manager_sender: mpsc::UnboundedSender<StreamManagerMessage>,
     state: State,
+    connections: Arc<ConnectionRegistry>,
+    conn_handle: Option<ConnectionHandle>,
 }
 
 impl Connection {
-    pub fn new(id: i32, stream_manager: mpsc::UnboundedSender<StreamManagerMessage>) -> Self {
+    pub fn new(
+        id: i32,
+        stream_manager: mpsc::UnboundedSender<StreamManagerMessage>,
+        connections: Arc<ConnectionRegistry>,
+    ) -> Self {
         Connection {
             id,
             session: None,
             stream_manager_sender: stream_manager,
             state: State::Waiting,
+            connections,
+            conn_handle: None,
         }
     }
 
@@ -84,6 +96,13 @@ impl Connection {
         stream: TcpStream,
         received_bytes: Vec<u8>,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let remote = stream.peer_addr()?;
+        let conn_handle =
+            self.connections
+                .register(ConnectionId::from(self.id), Protocol::Rtmp, remote);
+        conn_handle.add_bytes_in(received_bytes.len());
+        self.conn_handle = Some(conn_handle);
+
         let (stream_reader, stream_writer) = tokio::io::split(stream);
         let (read_bytes_sender, mut read_bytes_receiver) = mpsc::unbounded_channel();
         let (mut write_bytes_sender, write_bytes_receiver) = mpsc::unbounded_channel();
@@ -145,6 +164,7 @@ impl Connection {
                     match message {
                         None => break, // Noneが来た時はread_bytes_senderが閉じられた時ってことね・・・
                         Some(bytes) => {
+                           self.conn_handle.as_ref().unwrap().add_bytes_in(bytes.len());
                            results = self.session.as_mut()
                                 .unwrap()
                                 .handle_input(&bytes)
@@ -163,11 +183,18 @@ impl Connection {
                                 ConnectionAction::Disconnect => break,
                                 _ => (),
                             };
+                            self.update_protocol();
 
                             results = new_results;
                         }
                     }
                 }
+
+                // APIから切断を要求された。RTMPにはquitコードが無いのでそのまま切る
+                _ = self.conn_handle.as_mut().unwrap().disconnect_requested() => {
+                    println!("Connection {}: Disconnect requested", self.id);
+                    break;
+                }
             }
         }
 
@@ -336,6 +363,17 @@ impl Connection {
         }
     }
 
+    fn update_protocol(&self) {
+        let protocol = match &self.state {
+            State::Publishing { .. } => Protocol::RtmpPublisher,
+            State::Playing { .. } => Protocol::RtmpPlayer,
+            _ => return,
+        };
+        if let Some(handle) = &self.conn_handle {
+            handle.set_protocol(protocol);
+        }
+    }
+
     fn handle_session_results(
         &mut self,
         results: &mut Vec<ServerSessionResult>,
@@ -350,6 +388,9 @@ impl Connection {
             // println!("handle_session_results: {:?}", &result);
             match result {
                 ServerSessionResult::OutboundResponse(packet) => {
+                    if let Some(handle) = &self.conn_handle {
+                        handle.add_bytes_out(packet.bytes.len());
+                    }
                     if !send(&byte_writer, packet) {
                         break;
                     }
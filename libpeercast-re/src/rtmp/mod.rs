@@ -63,7 +63,8 @@ mod test {
         loop {
             let (stream, connection_info) = listener.accept().await?;
 
-            let connection = connection::Connection::new(current_id, manager_sender.clone());
+            let connection =
+                connection::Connection::new(current_id, manager_sender.clone(), Default::default());
             println!(
                 "Connection {}: Connection received from {}",
                 current_id,
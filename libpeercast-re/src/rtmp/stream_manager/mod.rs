@@ -19,6 +19,7 @@ pub use player_details::PlayerDetails;
 pub use publish_details::PublishDetails;
 pub use stream_manager_message::StreamManagerMessage;
 
+use crate::codec::rtmp::tag_header::{AudioTagHeader, VideoTagHeader};
 use crate::rtmp::send;
 
 pub fn start() -> mpsc::UnboundedSender<StreamManagerMessage> {
@@ -472,19 +473,18 @@ impl StreamManager {
     }
 }
 
+// Enhanced RTMP(HEVC/AV1/Opus)のFourCC形式も見る
 fn is_video_sequence_header(data: &Bytes) -> bool {
-    // This is assuming h264.
-    return data.len() >= 2 && data[0] == 0x17 && data[1] == 0x00;
+    VideoTagHeader::read(data).is_some_and(|h| h.is_sequence_header())
 }
 
 fn is_audio_sequence_header(data: &Bytes) -> bool {
-    // This is assuming aac
-    return data.len() >= 2 && data[0] == 0xaf && data[1] == 0x00;
+    AudioTagHeader::read(data).is_some_and(|h| h.is_sequence_header())
 }
 
 fn is_video_keyframe(data: &Bytes) -> bool {
-    // assumings h264
-    return data.len() >= 2 && data[0] == 0x17 && data[1] != 0x00; // 0x00 is the sequence header, don't count that for now
+    // sequence headerはkeyframeとして数えない
+    VideoTagHeader::read(data).is_some_and(|h| h.is_keyframe())
 }
 
 async fn wait_for_client_disconnection(
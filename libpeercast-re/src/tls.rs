@@ -0,0 +1,133 @@
+//! UI/APIをHTTPSで提供する
+//!
+//! 証明書と秘密鍵を設定していなければ、設定ファイルの隣に自己署名証明書を作って使い回す
+
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tokio_rustls::{
+    rustls::{self, ServerConfig},
+    TlsAcceptor,
+};
+use tracing::info;
+
+use crate::{config::Config, error::TlsError};
+
+const SELF_SIGNED_CERT_FILE: &str = "peercast-re.crt";
+const SELF_SIGNED_KEY_FILE: &str = "peercast-re.key";
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// 0ならHTTPと同じポートで受け付ける
+    pub port: u16,
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+    /// 証明書が無ければ作る
+    pub self_signed: bool,
+}
+
+impl TlsConfig {
+    /// 無効ならNone
+    pub fn from_config(config: &Config, config_path: &Path) -> Option<Self> {
+        if !config.tls_enabled {
+            return None;
+        }
+        let dir = config_path.parent().unwrap_or(Path::new("."));
+        let (cert_file, key_file, self_signed) = match (&config.tls_cert_file, &config.tls_key_file)
+        {
+            (None, None) => (
+                dir.join(SELF_SIGNED_CERT_FILE),
+                dir.join(SELF_SIGNED_KEY_FILE),
+                true,
+            ),
+            // 片方だけなら同じファイルに両方入っているとみなす
+            (Some(cert), None) => (cert.clone(), cert.clone(), false),
+            (None, Some(key)) => (key.clone(), key.clone(), false),
+            (Some(cert), Some(key)) => (cert.clone(), key.clone(), false),
+        };
+        Some(Self {
+            port: config.tls_port,
+            cert_file,
+            key_file,
+            self_signed,
+        })
+    }
+
+    pub fn acceptor(&self) -> Result<TlsAcceptor, TlsError> {
+        if self.self_signed && !(self.cert_file.exists() && self.key_file.exists()) {
+            generate_self_signed(&self.cert_file, &self.key_file)?;
+        }
+
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&self.cert_file)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(TlsError::NoCertificate(self.cert_file.clone()));
+        }
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(&self.key_file)?))?
+            .ok_or_else(|| TlsError::NoPrivateKey(self.key_file.clone()))?;
+
+        // 依存先でaws-lc-rsが有効になっても選べるように明示する
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        // hyperのauto::Builderがどちらも扱える
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn generate_self_signed(cert_file: &Path, key_file: &Path) -> Result<(), TlsError> {
+    let names = vec!["localhost".to_string(), "127.0.0.1".into(), "::1".into()];
+    let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(names)?;
+    fs::write(cert_file, cert.pem())?;
+    fs::write(key_file, key_pair.serialize_pem())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(key_file, fs::Permissions::from_mode(0o600))?;
+    }
+    info!("generated self-signed certificate {cert_file:?}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod t {
+    use crate::pcp::GnuId;
+
+    use super::*;
+
+    #[test]
+    fn test_self_signed() {
+        let dir = std::env::temp_dir().join(format!("peercast-re-tls-{}", GnuId::new()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut config = Config::default();
+        let config_path = dir.join("config.ini");
+        assert!(TlsConfig::from_config(&config, &config_path).is_none());
+
+        config.tls_enabled = true;
+        let tls = TlsConfig::from_config(&config, &config_path).unwrap();
+        assert!(tls.self_signed);
+        assert_eq!(tls.cert_file, dir.join(SELF_SIGNED_CERT_FILE));
+
+        tls.acceptor().unwrap();
+        let pem = fs::read(&tls.cert_file).unwrap();
+        // 2回目は作り直さない
+        tls.acceptor().unwrap();
+        assert_eq!(fs::read(&tls.cert_file).unwrap(), pem);
+
+        // 無い証明書を指定したらエラー
+        config.tls_cert_file = Some(dir.join("missing.pem"));
+        let tls = TlsConfig::from_config(&config, &config_path).unwrap();
+        assert!(!tls.self_signed);
+        assert!(matches!(tls.acceptor(), Err(TlsError::Io(_))));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
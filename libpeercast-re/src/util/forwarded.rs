@@ -0,0 +1,195 @@
+//! リバースプロキシが付けるヘッダーの解釈
+//!
+//! 信頼するプロキシから来た場合だけForwarded(RFC 7239)かX-Forwarded-*を見る
+
+use std::net::{IpAddr, SocketAddr};
+
+use http::{header::FORWARDED, HeaderMap};
+use ipnet::IpNet;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// プロキシの向こうのクライアントのアドレス(ポートが分からなければ0)
+/// peerが信頼するプロキシでなければpeerをそのまま返す
+pub fn client_addr(headers: &HeaderMap, peer: SocketAddr, trusted: &[IpNet]) -> SocketAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(&peer.ip()) {
+        return peer;
+    }
+
+    // 右から辿って最初の信頼していないアドレスがクライアント
+    let mut client = peer;
+    for node in forwarded_for(headers).iter().rev() {
+        match node {
+            Some(addr) => {
+                client = *addr;
+                if !is_trusted(&addr.ip()) {
+                    break;
+                }
+            }
+            // unknownや難読化された値より先は信用できない
+            None => break,
+        }
+    }
+    client
+}
+
+/// プロキシが受けたリクエストのHostとスキーム
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForwardedOrigin {
+    pub host: Option<String>,
+    pub proto: Option<String>,
+}
+
+impl ForwardedOrigin {
+    pub fn from_headers(headers: &HeaderMap, peer: IpAddr, trusted: &[IpNet]) -> Self {
+        if !trusted.iter().any(|net| net.contains(&peer)) {
+            return Self::default();
+        }
+        // 一番最初のプロキシが受けた値を使う
+        let first_param = |name: &str| {
+            forwarded_elements(headers)
+                .first()
+                .and_then(|pairs| pairs.iter().find(|(k, _)| k == name))
+                .map(|(_, v)| v.clone())
+        };
+        let first_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.split(',').next())
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        Self {
+            host: first_param("host").or_else(|| first_value(X_FORWARDED_HOST)),
+            proto: first_param("proto")
+                .or_else(|| first_value(X_FORWARDED_PROTO))
+                .map(|p| p.to_ascii_lowercase()),
+        }
+    }
+}
+
+// Forwardedがあればそちらを優先する
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<SocketAddr>> {
+    let elements = forwarded_elements(headers);
+    if !elements.is_empty() {
+        return elements
+            .iter()
+            .filter_map(|pairs| pairs.iter().find(|(k, _)| k == "for"))
+            .map(|(_, v)| parse_node(v))
+            .collect();
+    }
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|v| parse_node(v.trim()))
+        .collect()
+}
+
+// Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"
+fn forwarded_elements(headers: &HeaderMap) -> Vec<Vec<(String, String)>> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(k, v)| {
+                    (
+                        k.trim().to_ascii_lowercase(),
+                        v.trim().trim_matches('"').to_string(),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// "192.0.2.1", "192.0.2.1:80", "[2001:db8::1]:80", "2001:db8::1"
+fn parse_node(s: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = s.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (k, v) in pairs {
+            headers.append(*k, v.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_client_addr() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let proxy: SocketAddr = "10.0.0.1:50000".parse().unwrap();
+        let other: SocketAddr = "192.0.2.9:50000".parse().unwrap();
+
+        let h = headers(&[("x-forwarded-for", "198.51.100.1, 10.0.0.2")]);
+        assert_eq!(
+            client_addr(&h, proxy, &trusted),
+            "198.51.100.1:0".parse().unwrap()
+        );
+        // 信頼していない相手のヘッダーは無視する
+        assert_eq!(client_addr(&h, other, &trusted), other);
+        // 偽装された左側は見ない
+        let h = headers(&[("x-forwarded-for", "127.0.0.1, 198.51.100.1")]);
+        assert_eq!(
+            client_addr(&h, proxy, &trusted),
+            "198.51.100.1:0".parse().unwrap()
+        );
+
+        let h = headers(&[
+            ("forwarded", r#"for="[2001:db8::1]:4711";proto=https"#),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        assert_eq!(
+            client_addr(&h, proxy, &trusted),
+            "[2001:db8::1]:4711".parse().unwrap()
+        );
+        let h = headers(&[("forwarded", "for=unknown")]);
+        assert_eq!(client_addr(&h, proxy, &trusted), proxy);
+        assert_eq!(client_addr(&HeaderMap::new(), proxy, &trusted), proxy);
+    }
+
+    #[test]
+    fn test_forwarded_origin() {
+        let trusted: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap()];
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let h = headers(&[
+            ("x-forwarded-host", "example.com"),
+            ("x-forwarded-proto", "HTTPS"),
+        ]);
+        let origin = ForwardedOrigin::from_headers(&h, proxy, &trusted);
+        assert_eq!(origin.host.as_deref(), Some("example.com"));
+        assert_eq!(origin.proto.as_deref(), Some("https"));
+        assert_eq!(
+            ForwardedOrigin::from_headers(&h, "192.0.2.1".parse().unwrap(), &trusted),
+            ForwardedOrigin::default()
+        );
+
+        let h = headers(&[(
+            "forwarded",
+            r#"for=192.0.2.1;host="peca.example.com:8443";proto=https, for=10.0.0.2"#,
+        )]);
+        let origin = ForwardedOrigin::from_headers(&h, proxy, &trusted);
+        assert_eq!(origin.host.as_deref(), Some("peca.example.com:8443"));
+        assert_eq!(origin.proto.as_deref(), Some("https"));
+    }
+}
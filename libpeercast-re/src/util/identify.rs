@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use std::str::from_utf8;
+use std::{str::from_utf8, time::Duration};
 
 use thiserror::Error;
 use tokio::net::TcpStream;
@@ -10,6 +10,8 @@ pub enum ConnectionProtocol {
     PeerCast,
     PeerCastHttp,
     Http,
+    /// TLSのClientHello(HTTPS)
+    Tls,
     Unknown,
 }
 
@@ -21,6 +23,21 @@ pub enum IdentifierError {
     IOError(#[from] std::io::Error),
     #[error("parse failed")]
     HttpParseError(#[from] httparse::Error),
+    #[error("timeout")]
+    Timeout,
+}
+
+// 続きが届くまでpeekをやり直す間隔
+const PEEK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 判別できるだけのデータが時間内に届かなければTimeout
+pub async fn identify_protocol_timeout(
+    stream: &TcpStream,
+    timeout: Duration,
+) -> Result<ConnectionProtocol, IdentifierError> {
+    tokio::time::timeout(timeout, identify_protocol(stream))
+        .await
+        .unwrap_or(Err(IdentifierError::Timeout))
 }
 
 pub async fn identify_protocol(stream: &TcpStream) -> Result<ConnectionProtocol, IdentifierError> {
@@ -50,6 +67,9 @@ pub async fn identify_protocol(stream: &TcpStream) -> Result<ConnectionProtocol,
         } else if n == buf.len() {
             // bail!("ProtoclCheck中に確保しているバッファの最大まで使用したが、判別できなかった")
             return Err(IdentifierError::ProtocolIdentifierFailed);
+        } else {
+            // peekは読み残しがあるとすぐ返るので、少し待たないと空回りする
+            tokio::time::sleep(PEEK_INTERVAL).await;
         }
     };
     tracing::log::debug!("ConnectionProto {:?}", &conn_type);
@@ -62,6 +82,13 @@ fn _identify_protocol(buf: &[u8], length: usize) -> Option<ConnectionProtocol> {
     if is_pcp(buf, length) {
         return Some(ConnectionProtocol::PeerCast);
     }
+    if is_tls_client_hello(buf, length) {
+        return Some(ConnectionProtocol::Tls);
+    }
+    if length < 3 && buf.first() == Some(&0x16) {
+        // TLSかどうかまだ分からない
+        return None;
+    }
 
     return http_type(buf, length);
 }
@@ -74,6 +101,15 @@ fn is_pcp(buf: &[u8], length: usize) -> bool {
     &buf[0..4] == b"pcp\n"
 }
 
+// Handshakeレコード(0x16)でバージョンが3.x
+#[inline]
+fn is_tls_client_hello(buf: &[u8], length: usize) -> bool {
+    if length < 3 {
+        return false;
+    }
+    buf[0] == 0x16 && buf[1] == 0x03 && buf[2] <= 0x04
+}
+
 const PCP_HEADER: &[u8; 14] = b"x-peercast-pcp";
 
 #[inline]
@@ -142,6 +178,10 @@ mod t {
         let x = _identify_protocol(buf, buf.len());
         assert_eq!(x, Some(ConnectionProtocol::Http));
 
+        let buf = b"\x16\x03\x01\x02\x00\x01\x00\x01\xfc\x03\x03";
+        let x = _identify_protocol(buf, buf.len());
+        assert_eq!(x, Some(ConnectionProtocol::Tls));
+
         let buf = b"";
         let x: Option<ConnectionProtocol> = _identify_protocol(buf, buf.len());
         assert_eq!(x, None);
@@ -1,3 +1,4 @@
+pub mod forwarded;
 mod identify;
 mod shutdown;
 mod sync;
@@ -7,7 +8,7 @@ pub use sync::mutex_poisoned;
 pub use sync::rwlock_read_poisoned;
 pub use sync::rwlock_write_poisoned;
 pub mod util_mpsc;
-pub use identify::identify_protocol;
+pub use identify::{identify_protocol, identify_protocol_timeout};
 pub use identify::{ConnectionProtocol, IdentifierError};
 pub(crate) use shutdown::Shutdown;
 
@@ -0,0 +1,117 @@
+//! YPのindex.txtの解析
+//!
+//! 1行1チャンネルで`<>`区切り。peercast-rootのcreate_index_lineの逆
+//! name<>id<>tip<>url<>genre<>desc<>listeners<>relays<>bitrate<>type
+//! <>artist<>album<>title<>track_url<>name(url encoded)<>h:mm<>status<>comment<>direct
+
+use std::{net::SocketAddr, str::FromStr};
+
+use html_escape::decode_html_entities;
+
+use crate::pcp::{ChannelInfo, GnuId, TrackInfo};
+
+const MIN_FIELDS: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct IndexTxtLine {
+    pub channel_id: GnuId,
+    pub tracker_addr: Option<SocketAddr>,
+    pub channel_info: ChannelInfo,
+    pub track_info: TrackInfo,
+    /// YP側で非公開にしている場合は負数になる
+    pub number_of_listener: i32,
+    pub number_of_relay: i32,
+    /// 配信時間(分)
+    pub uptime_minutes: u32,
+}
+
+/// 解析できない行(お知らせのチャンネル以外の不正な行など)は読み飛ばす
+pub fn parse_index_txt(text: &str) -> Vec<IndexTxtLine> {
+    text.lines().filter_map(parse_line).collect()
+}
+
+pub fn parse_line(line: &str) -> Option<IndexTxtLine> {
+    let fields = line.trim_end_matches('\r').split("<>").collect::<Vec<_>>();
+    if fields.len() < MIN_FIELDS {
+        return None;
+    }
+    let field = |i: usize| -> String {
+        fields
+            .get(i)
+            .map(|f| decode_html_entities(f).into_owned())
+            .unwrap_or_default()
+    };
+
+    let channel_id = GnuId::from_str(fields[1]).ok()?;
+    let tracker_addr = fields[2].parse::<SocketAddr>().ok();
+    let number = |i: usize| fields.get(i).and_then(|f| f.parse().ok()).unwrap_or(-1);
+
+    let channel_info = ChannelInfo {
+        typ: field(9),
+        name: field(0),
+        genre: field(4),
+        desc: field(5),
+        comment: field(17),
+        url: field(3),
+        bitrate: number(8).max(0),
+        ..Default::default()
+    };
+    let track_info = TrackInfo {
+        creator: field(10),
+        album: field(11),
+        title: field(12),
+        url: field(13),
+        ..Default::default()
+    };
+
+    Some(IndexTxtLine {
+        channel_id,
+        tracker_addr,
+        channel_info,
+        track_info,
+        number_of_listener: number(6),
+        number_of_relay: number(7),
+        uptime_minutes: fields.get(15).and_then(|f| parse_uptime(f)).unwrap_or(0),
+    })
+}
+
+// "h:mm"
+fn parse_uptime(s: &str) -> Option<u32> {
+    let (hour, min) = s.split_once(':')?;
+    Some(hour.trim().parse::<u32>().ok()? * 60 + min.trim().parse::<u32>().ok()?)
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        let line = "テスト&lt;ch&gt;<>0123456789ABCDEF0123456789ABCDEF<>192.168.0.2:7144<>https://example.com/?a=1&amp;b=2<>ゲーム<>説明<>3<>1<>500<>FLV<>artist<>album<>title<>https://example.com/track<>%E3%83%86<>12:05<>click<>コメント<>0";
+        let l = parse_line(line).unwrap();
+        assert_eq!(l.channel_id.to_string(), "0123456789ABCDEF0123456789ABCDEF");
+        assert_eq!(l.tracker_addr, Some("192.168.0.2:7144".parse().unwrap()));
+        assert_eq!(l.channel_info.name, "テスト<ch>");
+        assert_eq!(l.channel_info.url, "https://example.com/?a=1&b=2");
+        assert_eq!(l.channel_info.typ, "FLV");
+        assert_eq!(l.channel_info.bitrate, 500);
+        assert_eq!(l.channel_info.comment, "コメント");
+        assert_eq!(l.track_info.title, "title");
+        assert_eq!((l.number_of_listener, l.number_of_relay), (3, 1));
+        assert_eq!(l.uptime_minutes, 12 * 60 + 5);
+
+        // tipが無い、リスナー数非公開
+        let line = "ch<>0123456789ABCDEF0123456789ABCDEF<><><><><>-1<>-1<>0<>RAW";
+        let l = parse_line(line).unwrap();
+        assert_eq!(l.tracker_addr, None);
+        assert_eq!(l.number_of_listener, -1);
+        assert_eq!(l.uptime_minutes, 0);
+
+        assert!(parse_line("").is_none());
+        assert!(parse_line("ch<>invalid<><><><><>0<>0<>0<>RAW").is_none());
+        assert_eq!(
+            parse_index_txt(&format!("{line}\r\nbroken\r\n{line}\r\n")).len(),
+            2
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! YPのチャンネル一覧
+//!
+//! 設定されたYPのindex.txtを定期的に取得して、同じチャンネルをまとめて持っておく
+
+mod index_txt;
+
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use url::Url;
+
+use crate::{
+    config::Config,
+    connection_registry::{ConnectionRegistry, Protocol},
+    pcp::{ChannelInfo, GnuId, TrackInfo},
+    ConnectionId,
+};
+
+pub use index_txt::{parse_index_txt, parse_line, IndexTxtLine};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct YpConfig {
+    /// index.txtのURL
+    pub urls: Vec<Url>,
+    pub interval: Duration,
+}
+
+impl YpConfig {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            urls: config.yp_urls.clone(),
+            interval: Duration::from_secs(config.yp_update_interval.max(60)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct YpChannel {
+    pub channel_id: GnuId,
+    pub tracker_addr: Option<SocketAddr>,
+    pub channel_info: ChannelInfo,
+    pub track_info: TrackInfo,
+    pub number_of_listener: i32,
+    pub number_of_relay: i32,
+    pub uptime_minutes: u32,
+    /// 掲載されていたYP
+    pub yp: Vec<Url>,
+}
+
+impl YpChannel {
+    fn new(yp: &Url, line: IndexTxtLine) -> Self {
+        Self {
+            channel_id: line.channel_id,
+            tracker_addr: line.tracker_addr,
+            channel_info: line.channel_info,
+            track_info: line.track_info,
+            number_of_listener: line.number_of_listener,
+            number_of_relay: line.number_of_relay,
+            uptime_minutes: line.uptime_minutes,
+            yp: vec![yp.clone()],
+        }
+    }
+
+    // 複数のYPに載っている場合、tipが分かる方・リスナーが多い方の情報を使う
+    fn merge(&mut self, other: YpChannel) {
+        let prefer_other = match (self.tracker_addr, other.tracker_addr) {
+            (None, Some(_)) => true,
+            (Some(_), None) => false,
+            _ => other.number_of_listener > self.number_of_listener,
+        };
+        let mut yp = std::mem::take(&mut self.yp);
+        yp.extend(other.yp.iter().cloned());
+        if prefer_other {
+            *self = other;
+        }
+        yp.dedup();
+        self.yp = yp;
+    }
+
+    fn matches(&self, filter: &YpFilter) -> bool {
+        let contains = |s: &str, q: &str| s.to_lowercase().contains(&q.to_lowercase());
+        let info = &self.channel_info;
+        if let Some(q) = filter.q.as_deref().filter(|q| !q.is_empty()) {
+            let hit = [&info.name, &info.genre, &info.desc, &info.comment]
+                .iter()
+                .any(|s| contains(s, q));
+            if !hit {
+                return false;
+            }
+        }
+        if let Some(genre) = filter.genre.as_deref().filter(|g| !g.is_empty()) {
+            if !contains(&info.genre, genre) {
+                return false;
+            }
+        }
+        if let Some(typ) = filter.typ.as_deref().filter(|t| !t.is_empty()) {
+            if !info.typ.eq_ignore_ascii_case(typ) {
+                return false;
+            }
+        }
+        if let Some(yp) = filter.yp.as_deref().filter(|y| !y.is_empty()) {
+            if !self.yp.iter().any(|u| u.host_str() == Some(yp)) {
+                return false;
+            }
+        }
+        if filter.playable && self.tracker_addr.is_none() {
+            return false;
+        }
+        true
+    }
+}
+
+/// 一覧の絞り込み条件。指定しなかった物は条件にしない
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct YpFilter {
+    /// 名前・ジャンル・詳細・コメントの部分一致
+    pub q: Option<String>,
+    pub genre: Option<String>,
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    /// YPのホスト名
+    pub yp: Option<String>,
+    /// tipが分かっているものだけ
+    #[serde(default)]
+    pub playable: bool,
+}
+
+#[derive(Debug, Default)]
+struct YpState {
+    /// YPごとの最後に取得できた一覧。取得に失敗したYPは前回の物を使い続ける
+    listings: HashMap<Url, Vec<IndexTxtLine>>,
+    channels: HashMap<GnuId, YpChannel>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// YpBrowser
+//
+#[derive(Debug)]
+pub struct YpBrowser {
+    config: YpConfig,
+    state: Mutex<YpState>,
+    connections: Arc<ConnectionRegistry>,
+}
+
+impl YpBrowser {
+    pub fn new(config: YpConfig, connections: Arc<ConnectionRegistry>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            state: Default::default(),
+            connections,
+        })
+    }
+
+    /// 定期取得を始める。YPが設定されていなければ何もしない
+    pub fn start(self: &Arc<Self>) -> Option<JoinHandle<()>> {
+        if self.config.urls.is_empty() {
+            info!("YP is not configured");
+            return None;
+        }
+        let this = Arc::clone(self);
+        Some(tokio::spawn(async move {
+            let client = reqwest::Client::builder()
+                .user_agent(crate::PKG_AGENT)
+                .timeout(FETCH_TIMEOUT)
+                .build()
+                .unwrap();
+            let mut interval = tokio::time::interval(this.config.interval);
+            loop {
+                interval.tick().await;
+                this.update(&client).await;
+            }
+        }))
+    }
+
+    async fn update(&self, client: &reqwest::Client) {
+        let mut fetched = Vec::new();
+        for url in &self.config.urls {
+            match fetch(client, url, &self.connections).await {
+                Ok(lines) => {
+                    debug!("fetched {} channels from {url}", lines.len());
+                    fetched.push((url.clone(), lines));
+                }
+                Err(e) => warn!("failed to fetch {url}: {e}"),
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        state.listings.extend(fetched);
+        state.channels = merge_listings(&self.config.urls, &state.listings);
+        state.updated_at = Some(Utc::now());
+    }
+
+    /// リスナー数の多い順
+    pub fn list(&self, filter: &YpFilter) -> Vec<YpChannel> {
+        let state = self.state.lock().unwrap();
+        let mut list = state
+            .channels
+            .values()
+            .filter(|ch| ch.matches(filter))
+            .cloned()
+            .collect::<Vec<_>>();
+        list.sort_by(|a, b| {
+            b.number_of_listener
+                .cmp(&a.number_of_listener)
+                .then_with(|| a.channel_info.name.cmp(&b.channel_info.name))
+        });
+        list
+    }
+
+    pub fn get(&self, channel_id: &GnuId) -> Option<YpChannel> {
+        self.state.lock().unwrap().channels.get(channel_id).cloned()
+    }
+
+    pub fn updated_at(&self) -> Option<DateTime<Utc>> {
+        self.state.lock().unwrap().updated_at
+    }
+}
+
+fn merge_listings(
+    urls: &[Url],
+    listings: &HashMap<Url, Vec<IndexTxtLine>>,
+) -> HashMap<GnuId, YpChannel> {
+    let mut channels = HashMap::<GnuId, YpChannel>::new();
+    for url in urls {
+        let Some(lines) = listings.get(url) else {
+            continue;
+        };
+        for line in lines {
+            let ch = YpChannel::new(url, line.clone());
+            match channels.get_mut(&ch.channel_id) {
+                Some(exists) => exists.merge(ch),
+                None => {
+                    channels.insert(ch.channel_id, ch);
+                }
+            }
+        }
+    }
+    channels
+}
+
+// 取得している間は接続一覧にYpLinkとして載せる
+async fn fetch(
+    client: &reqwest::Client,
+    url: &Url,
+    connections: &ConnectionRegistry,
+) -> Result<Vec<IndexTxtLine>, reqwest::Error> {
+    let res = client.get(url.clone()).send().await?.error_for_status()?;
+    let remote = res
+        .remote_addr()
+        .unwrap_or_else(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+    let handle = connections.register(ConnectionId::new(), Protocol::YpLink, remote);
+    let text = res.text().await?;
+    handle.add_bytes_in(text.len());
+    Ok(parse_index_txt(&text))
+}
+
+#[cfg(test)]
+mod t {
+    use super::*;
+
+    fn channel(yp: &str, line: &str) -> YpChannel {
+        YpChannel::new(&yp.parse().unwrap(), parse_line(line).unwrap())
+    }
+
+    #[test]
+    fn test_merge_and_filter() {
+        let id = "0123456789ABCDEF0123456789ABCDEF";
+        let mut a = channel(
+            "http://yp1.example.com/index.txt",
+            &format!("ch<>{id}<><><>ゲーム<>詳細<>10<>0<>500<>FLV"),
+        );
+        let b = channel(
+            "http://yp2.example.com/index.txt",
+            &format!("ch<>{id}<>192.168.0.2:7144<><>ゲーム<>詳細<>3<>0<>500<>FLV"),
+        );
+        a.merge(b);
+        // tipが分かる方を優先する
+        assert_eq!(a.tracker_addr, Some("192.168.0.2:7144".parse().unwrap()));
+        assert_eq!(a.yp.len(), 2);
+
+        let filter = |f: YpFilter| a.matches(&f);
+        assert!(filter(YpFilter::default()));
+        assert!(filter(YpFilter {
+            q: Some("詳".into()),
+            ..Default::default()
+        }));
+        assert!(!filter(YpFilter {
+            q: Some("雑談".into()),
+            ..Default::default()
+        }));
+        assert!(filter(YpFilter {
+            typ: Some("flv".into()),
+            yp: Some("yp1.example.com".into()),
+            playable: true,
+            ..Default::default()
+        }));
+        assert!(!filter(YpFilter {
+            genre: Some("音楽".into()),
+            ..Default::default()
+        }));
+    }
+
+    #[test]
+    fn test_merge_listings_keeps_failed_yp() {
+        let yp1: Url = "http://yp1.example.com/index.txt".parse().unwrap();
+        let yp2: Url = "http://yp2.example.com/index.txt".parse().unwrap();
+        let line = |name: &str, id: &str| {
+            parse_line(&format!("{name}<>{id}<><><><><>1<>0<>500<>FLV")).unwrap()
+        };
+        let mut listings = HashMap::new();
+        listings.insert(
+            yp1.clone(),
+            vec![line("a", "0123456789ABCDEF0123456789ABCDEF")],
+        );
+        listings.insert(
+            yp2.clone(),
+            vec![line("b", "FEDCBA9876543210FEDCBA9876543210")],
+        );
+
+        // yp2の取得に失敗した回は、yp1だけ更新して yp2 は前回の物が残る
+        let fetched = vec![(yp1.clone(), vec![])];
+        listings.extend(fetched);
+        let channels = merge_listings(&[yp1, yp2.clone()], &listings);
+        assert_eq!(channels.len(), 1);
+        let ch = channels.values().next().unwrap();
+        assert_eq!(ch.channel_info.name, "b");
+        assert_eq!(ch.yp, vec![yp2]);
+    }
+}
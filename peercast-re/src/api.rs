@@ -1,8 +1,27 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, str::FromStr, sync::Arc};
 
-use axum::{Json, extract::State};
-use serde::Serialize;
-use utoipa::OpenApi;
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use libpeercast_re::{
+    ConnectionId,
+    codec::container::ContainerType,
+    connection_registry::ConnectionLimits,
+    metrics,
+    pcp::{
+        BroadcastTaskConfig, Channel, ChannelInfo, ChannelManager, ChannelType, FileTaskConfig,
+        GnuId, HttpPullTaskConfig, RelayTaskConfig, SourceTaskConfig, TaskStatus, TrackInfo,
+    },
+    rtmp::stream_manager::StreamManagerMessage,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use url::Url;
+use utoipa::{OpenApi, ToSchema};
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 const RE_TAG: &str = "peercast-re";
@@ -16,30 +35,587 @@ const RE_TAG: &str = "peercast-re";
 pub struct ApiDoc;
 
 #[derive(Debug, Clone)]
-pub struct ReStore {}
-
-#[derive(Debug, Serialize)]
-pub struct Channel {}
+pub struct ReStore {
+    pub channel_manager: Arc<ChannelManager>,
+    pub manager_sender: mpsc::UnboundedSender<StreamManagerMessage>,
+    pub connection_limits: ConnectionLimits,
+    /// 配信者ID(BCID), 配信チャンネルのIDを作るのに使う
+    pub broadcast_id: GnuId,
+}
 
 pub fn router(store: Arc<ReStore>) -> (axum::Router, utoipa::openapi::OpenApi) {
-    let open_api_router = OpenApiRouter::new()
-        .routes(routes!(list_channels, create_channel))
-        // .routes(routes!(ip_check))
-        // .routes(routes!(port_check))
-        .with_state(store);
+    let (router, api) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .nest("/api", api_router())
+        .split_for_parts();
+    let router = router.route("/metrics", axum::routing::get(get_metrics));
+    (router.with_state(store), api)
+}
+
+/// Prometheus形式のメトリクス。OpenAPIには載せない
+async fn get_metrics(State(store): State<Arc<ReStore>>) -> impl IntoResponse {
+    metrics::observe_channels(&store.channel_manager);
+    metrics::observe_connections(&store.channel_manager.connections());
+    (
+        [(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics::gather(),
+    )
+}
+
+/// gen-openapi用。Storeが無くても仕様だけ取り出せる
+pub fn openapi() -> utoipa::openapi::OpenApi {
+    let (_, api) = OpenApiRouter::<Arc<ReStore>>::with_openapi(ApiDoc::openapi())
+        .nest("/api", api_router())
+        .split_for_parts();
+    api
+}
+
+fn api_router() -> OpenApiRouter<Arc<ReStore>> {
+    OpenApiRouter::new()
+        .routes(routes!(list_channels))
+        .routes(routes!(get_channel, patch_channel, delete_channel))
+        .routes(routes!(create_broadcast_channel))
+        .routes(routes!(create_relay_channel))
+        .routes(routes!(stop_channel))
+        .routes(routes!(reconnect_channel))
+    // .routes(routes!(ip_check))
+    // .routes(routes!(port_check))
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Error
+//
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("channel not found")]
+    NotFound,
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Conflict(String),
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self {
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+        };
+        let body = ErrorResponse {
+            error: self.to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+fn parse_id(id: &str) -> Result<GnuId, ApiError> {
+    GnuId::from_str(id).map_err(|e| ApiError::BadRequest(format!("invalid channel id: {e}")))
+}
+
+fn find_channel(store: &ReStore, id: &str) -> Result<Channel, ApiError> {
+    let id = parse_id(id)?;
+    store.channel_manager.get(&id).ok_or(ApiError::NotFound)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Schemas
+//
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelKind {
+    Broadcast,
+    Relay,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelStatus {
+    Init,
+    Searching,
+    Receiving,
+    Idle,
+    Finish,
+    Error,
+}
+
+impl From<TaskStatus> for ChannelStatus {
+    fn from(value: TaskStatus) -> Self {
+        match value {
+            TaskStatus::Init => Self::Init,
+            TaskStatus::Searching { .. } => Self::Searching,
+            TaskStatus::Receiving => Self::Receiving,
+            TaskStatus::Idle => Self::Idle,
+            TaskStatus::Finish => Self::Finish,
+            TaskStatus::Error => Self::Error,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ChannelInfoSchema {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub name: String,
+    pub genre: String,
+    pub desc: String,
+    pub comment: String,
+    pub url: String,
+    pub stream_type: String,
+    pub stream_ext: String,
+    pub bitrate: i32,
+}
+
+impl From<ChannelInfo> for ChannelInfoSchema {
+    fn from(i: ChannelInfo) -> Self {
+        Self {
+            typ: i.typ,
+            name: i.name,
+            genre: i.genre,
+            desc: i.desc,
+            comment: i.comment,
+            url: i.url,
+            stream_type: i.stream_type,
+            stream_ext: i.stream_ext,
+            bitrate: i.bitrate,
+        }
+    }
+}
+
+impl From<ChannelInfoSchema> for ChannelInfo {
+    fn from(i: ChannelInfoSchema) -> Self {
+        Self {
+            typ: i.typ,
+            name: i.name,
+            genre: i.genre,
+            desc: i.desc,
+            comment: i.comment,
+            url: i.url,
+            stream_type: i.stream_type,
+            stream_ext: i.stream_ext,
+            bitrate: i.bitrate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct TrackInfoSchema {
+    pub title: String,
+    pub creator: String,
+    pub url: String,
+    pub album: String,
+    pub genre: String,
+}
+
+impl From<TrackInfo> for TrackInfoSchema {
+    fn from(t: TrackInfo) -> Self {
+        Self {
+            title: t.title,
+            creator: t.creator,
+            url: t.url,
+            album: t.album,
+            genre: t.genre,
+        }
+    }
+}
+
+impl From<TrackInfoSchema> for TrackInfo {
+    fn from(t: TrackInfoSchema) -> Self {
+        Self {
+            title: t.title,
+            creator: t.creator,
+            url: t.url,
+            album: t.album,
+            genre: t.genre,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChannelResponse {
+    /// GnuId (32桁の16進数)
+    pub id: String,
+    pub kind: ChannelKind,
+    pub status: ChannelStatus,
+    pub info: ChannelInfoSchema,
+    pub track: TrackInfoSchema,
+    /// RFC3339
+    pub created_at: String,
+    pub listeners: ConnectionCount,
+    pub relays: ConnectionCount,
+    /// 限定公開(視聴・リレーにトークンが要る)
+    pub private: bool,
+}
+
+/// 接続数と上限
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConnectionCount {
+    pub current: u32,
+    /// 0で無制限
+    pub max: u32,
+}
+
+impl ChannelResponse {
+    fn new(ch: &Channel, store: &ReStore) -> Self {
+        let conns = store
+            .channel_manager
+            .connections()
+            .channel_connections(&ch.id(), &store.connection_limits);
+        Self {
+            id: ch.id().to_string(),
+            kind: match ch.channel_type() {
+                ChannelType::Broadcast => ChannelKind::Broadcast,
+                ChannelType::Relay => ChannelKind::Relay,
+            },
+            status: ch.status().into(),
+            info: ch.info().unwrap_or_default().into(),
+            track: ch.track().unwrap_or_default().into(),
+            created_at: ch.created_at().to_rfc3339(),
+            listeners: ConnectionCount {
+                current: conns.listeners,
+                max: conns.max_listeners,
+            },
+            relays: ConnectionCount {
+                current: conns.relays,
+                max: conns.max_relays,
+            },
+            private: ch.is_private(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateBroadcastRequest {
+    /// RTMPのapp名。sourceを指定した時は使わない
+    #[serde(default)]
+    pub app_key: String,
+    #[serde(default)]
+    pub stream_key: String,
+    /// 省略するとRTMPで受ける
+    #[serde(default)]
+    pub source: Option<BroadcastSource>,
+    pub info: ChannelInfoSchema,
+    #[serde(default)]
+    pub track: Option<TrackInfoSchema>,
+}
 
-    OpenApiRouter::with_openapi(ApiDoc::openapi())
-        .nest("/api", open_api_router)
-        .split_for_parts()
+/// RTMP以外の配信元
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BroadcastSource {
+    /// HTTPで配信されているFLV/MPEG-TS/MKVを取りに行く
+    HttpPull {
+        url: String,
+        /// "FLV", "TS", "MKV", "WEBM"。省略するとContent-Typeなどから判別する
+        #[serde(default)]
+        container: Option<String>,
+    },
+    /// ローカルのFLVファイルを実時間で流す
+    File {
+        path: String,
+        /// 終端まで読んだら先頭に戻る
+        #[serde(default)]
+        looping: bool,
+    },
 }
 
-#[utoipa::path(get, path = "/channels")]
-async fn list_channels(State(store): State<Arc<ReStore>>) -> Json<Vec<Channel>> {
-    let channels = vec![Channel {}];
+impl BroadcastSource {
+    fn task_config(self) -> Result<SourceTaskConfig, ApiError> {
+        match self {
+            BroadcastSource::HttpPull { url, container } => {
+                let url = url
+                    .parse::<Url>()
+                    .map_err(|e| ApiError::BadRequest(format!("invalid url: {e}")))?;
+                let container = container
+                    .map(|c| {
+                        ContainerType::from_type_name(&c)
+                            .ok_or_else(|| ApiError::BadRequest(format!("unknown container: {c}")))
+                    })
+                    .transpose()?;
+                Ok(HttpPullTaskConfig { url, container }.into())
+            }
+            BroadcastSource::File { path, looping } => {
+                let path = std::path::PathBuf::from(path);
+                if !path.is_file() {
+                    return Err(ApiError::BadRequest(format!(
+                        "file not found: {}",
+                        path.display()
+                    )));
+                }
+                Ok(FileTaskConfig { path, looping }.into())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CreateRelayRequest {
+    /// GnuId (32桁の16進数)
+    pub id: String,
+    /// 接続先 "host:port"
+    pub tip: String,
+}
+
+/// 指定したフィールドだけ書き換える
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct ChannelInfoPatch {
+    #[serde(rename = "type")]
+    pub typ: Option<String>,
+    pub name: Option<String>,
+    pub genre: Option<String>,
+    pub desc: Option<String>,
+    pub comment: Option<String>,
+    pub url: Option<String>,
+    pub bitrate: Option<i32>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct TrackInfoPatch {
+    pub title: Option<String>,
+    pub creator: Option<String>,
+    pub url: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct PatchChannelRequest {
+    pub info: Option<ChannelInfoPatch>,
+    pub track: Option<TrackInfoPatch>,
+}
+
+macro_rules! patch_fields {
+    ($target:ident, $patch:ident, $($field:ident),+) => {
+        $(
+            if let Some(v) = $patch.$field {
+                $target.$field = v;
+            }
+        )+
+    };
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Handlers
+//
+#[utoipa::path(
+    get,
+    path = "/channels",
+    tag = RE_TAG,
+    responses(
+        (status = 200, body = Vec<ChannelResponse>)
+    )
+)]
+async fn list_channels(State(store): State<Arc<ReStore>>) -> Json<Vec<ChannelResponse>> {
+    let channels = store
+        .channel_manager
+        .map_collect(|(_, ch)| ChannelResponse::new(ch, &store));
     Json(channels)
 }
 
-#[utoipa::path(post, path = "/channels/create")]
-async fn create_channel() -> Json<Channel> {
-    Json(Channel {})
+#[utoipa::path(
+    get,
+    path = "/channels/{id}",
+    tag = RE_TAG,
+    params(("id" = String, Path, description = "Channel ID")),
+    responses(
+        (status = 200, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn get_channel(
+    State(store): State<Arc<ReStore>>,
+    Path(id): Path<String>,
+) -> Result<Json<ChannelResponse>, ApiError> {
+    let ch = find_channel(&store, &id)?;
+    Ok(Json(ChannelResponse::new(&ch, &store)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/channels/broadcast",
+    tag = RE_TAG,
+    request_body = CreateBroadcastRequest,
+    responses(
+        (status = 201, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+async fn create_broadcast_channel(
+    State(store): State<Arc<ReStore>>,
+    Json(req): Json<CreateBroadcastRequest>,
+) -> Result<(StatusCode, Json<ChannelResponse>), ApiError> {
+    if req.info.name.is_empty() {
+        return Err(ApiError::BadRequest("info.name is empty".into()));
+    }
+    let config = match req.source {
+        Some(source) => source.task_config()?,
+        None if req.app_key.is_empty() => {
+            return Err(ApiError::BadRequest("app_key is empty".into()));
+        }
+        None => BroadcastTaskConfig {
+            app_key: req.app_key,
+            stream_key: req.stream_key,
+            rtmp_manager: store.manager_sender.clone(),
+        }
+        .into(),
+    };
+
+    // 同じ名前・ジャンルなら配信し直しても同じIDになる
+    let id = store
+        .broadcast_id
+        .channel_id(&req.info.name, &req.info.genre, req.info.bitrate);
+    let ch = store
+        .channel_manager
+        .create(
+            id,
+            ChannelType::Broadcast,
+            Some(req.info.into()),
+            Some(req.track.unwrap_or_default().into()),
+        )
+        .ok_or_else(|| ApiError::Conflict("channel already exists".into()))?;
+    ch.connect(ConnectionId::new(), config);
+
+    Ok((StatusCode::CREATED, Json(ChannelResponse::new(&ch, &store))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/channels/relay",
+    tag = RE_TAG,
+    request_body = CreateRelayRequest,
+    responses(
+        (status = 201, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 409, body = ErrorResponse),
+    )
+)]
+async fn create_relay_channel(
+    State(store): State<Arc<ReStore>>,
+    Json(req): Json<CreateRelayRequest>,
+) -> Result<(StatusCode, Json<ChannelResponse>), ApiError> {
+    let id = parse_id(&req.id)?;
+    let addr = req
+        .tip
+        .parse::<SocketAddr>()
+        .map_err(|e| ApiError::BadRequest(format!("invalid tip: {e}")))?;
+
+    let ch = store
+        .channel_manager
+        .create(id, ChannelType::Relay, None, None)
+        .ok_or_else(|| ApiError::Conflict("channel already exists".into()))?;
+    let config = RelayTaskConfig {
+        addr,
+        self_addr: None,
+        token: None,
+    };
+    ch.connect(ConnectionId::new(), config.into());
+
+    Ok((StatusCode::CREATED, Json(ChannelResponse::new(&ch, &store))))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/channels/{id}",
+    tag = RE_TAG,
+    params(("id" = String, Path, description = "Channel ID")),
+    request_body = PatchChannelRequest,
+    responses(
+        (status = 200, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn patch_channel(
+    State(store): State<Arc<ReStore>>,
+    Path(id): Path<String>,
+    Json(req): Json<PatchChannelRequest>,
+) -> Result<Json<ChannelResponse>, ApiError> {
+    let ch = find_channel(&store, &id)?;
+
+    if let Some(patch) = req.info {
+        let mut info = ch.info().unwrap_or_default();
+        patch_fields!(info, patch, typ, name, genre, desc, comment, url, bitrate);
+        ch.set_info(info);
+    }
+    if let Some(patch) = req.track {
+        let mut track = ch.track().unwrap_or_default();
+        patch_fields!(track, patch, title, creator, url, album, genre);
+        ch.set_track(track);
+    }
+
+    Ok(Json(ChannelResponse::new(&ch, &store)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/channels/{id}/stop",
+    tag = RE_TAG,
+    params(("id" = String, Path, description = "Channel ID")),
+    responses(
+        (status = 200, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn stop_channel(
+    State(store): State<Arc<ReStore>>,
+    Path(id): Path<String>,
+) -> Result<Json<ChannelResponse>, ApiError> {
+    let ch = find_channel(&store, &id)?;
+    ch.stop();
+    Ok(Json(ChannelResponse::new(&ch, &store)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/channels/{id}/reconnect",
+    tag = RE_TAG,
+    params(("id" = String, Path, description = "Channel ID")),
+    responses(
+        (status = 200, body = ChannelResponse),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+        (status = 409, body = ErrorResponse, description = "Channel is stopped"),
+    )
+)]
+async fn reconnect_channel(
+    State(store): State<Arc<ReStore>>,
+    Path(id): Path<String>,
+) -> Result<Json<ChannelResponse>, ApiError> {
+    let ch = find_channel(&store, &id)?;
+    if !ch.retry() {
+        return Err(ApiError::Conflict(
+            "channel has no source to reconnect".into(),
+        ));
+    }
+    Ok(Json(ChannelResponse::new(&ch, &store)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/channels/{id}",
+    tag = RE_TAG,
+    params(("id" = String, Path, description = "Channel ID")),
+    responses(
+        (status = 204),
+        (status = 400, body = ErrorResponse),
+        (status = 404, body = ErrorResponse),
+    )
+)]
+async fn delete_channel(
+    State(store): State<Arc<ReStore>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let ch = find_channel(&store, &id)?;
+    ch.stop();
+    store.channel_manager.delete(&ch.id());
+    Ok(StatusCode::NO_CONTENT)
 }
@@ -1,9 +1,9 @@
-use peercast_re::api::{router, ReStore};
+use peercast_re::api::openapi;
 
 
 
 fn main() {
-    let (_,  api ) = router(ReStore{}.into());
+    let api = openapi();
 
     let j = api.to_pretty_json().unwrap();
     println!("{j}");
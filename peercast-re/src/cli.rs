@@ -1,6 +1,15 @@
-use std::path::PathBuf;
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    path::PathBuf,
+};
 
-use clap::{Parser, command};
+use anyhow::bail;
+use clap::{Parser, Subcommand, command};
+use libpeercast_re::{
+    config::{Config as LibConfig, ConfigPassword, ConfigTrait},
+    pcp::GnuId,
+};
+use tracing::{info, warn};
 
 use crate::config::{Config, ConfigAddress};
 
@@ -38,6 +47,23 @@ pub struct Args {
         value_parser = clap::value_parser!(u16).range(5000..)
     )]
     pub server_port: Option<u16>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Hash the admin password read from stdin and save it to the config file
+    Password {
+        /// Admin username (leave empty to check the password only)
+        #[clap(short = 'u', long = "username")]
+        username: Option<String>,
+
+        /// Print the hashed password without writing the config file
+        #[clap(long = "print-only")]
+        print_only: bool,
+    },
 }
 
 impl Args {
@@ -55,3 +81,69 @@ impl Args {
         config
     }
 }
+
+/// `password` subcommand
+pub fn run_password(
+    config_file: Option<PathBuf>,
+    username: Option<String>,
+    print_only: bool,
+) -> anyhow::Result<()> {
+    let password = read_password()?;
+    let hashed = ConfigPassword::Plain(password).to_hashed();
+    if print_only {
+        println!("{}", String::from(&hashed));
+        return Ok(());
+    }
+
+    let Some(path) = config_file else {
+        bail!("config file is not specified. use --config or PEERCAST_RE_CONFIG");
+    };
+    let mut config = match path.exists() {
+        true => LibConfig::load_file(&path)?,
+        false => LibConfig::default(),
+    };
+    if username.is_some() {
+        config.username = username;
+    }
+    config.password = Some(hashed);
+    config.save_file(&path)?;
+
+    println!("password saved to {}", path.display());
+    Ok(())
+}
+
+// コマンドライン引数はpsや履歴から見えてしまうので、パスワードは標準入力から読む
+fn read_password() -> anyhow::Result<String> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        std::io::stderr().flush()?;
+    }
+    let mut line = String::new();
+    stdin.lock().read_line(&mut line)?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        bail!("password is empty");
+    }
+    Ok(password.to_string())
+}
+
+/// 配信者ID(BCID)を設定ファイルから読む。無ければ作って保存する
+pub fn load_broadcast_id(config_file: Option<PathBuf>) -> anyhow::Result<GnuId> {
+    let Some(path) = config_file else {
+        warn!("config file is not specified. broadcast channel ids change on every start");
+        return Ok(GnuId::new_random());
+    };
+    let mut config = match path.exists() {
+        true => LibConfig::load_file(&path)?,
+        false => LibConfig::default(),
+    };
+    if let Some(id) = config.broadcast_id {
+        return Ok(id);
+    }
+    let id = GnuId::new_random();
+    config.broadcast_id = Some(id);
+    config.save_file(&path)?;
+    info!("broadcast id saved to {}", path.display());
+    Ok(id)
+}
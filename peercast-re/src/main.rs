@@ -1,5 +1,9 @@
 use api::{router, ReStore};
 use clap::Parser;
+use libpeercast_re::{
+    pcp::{ChannelManager, GnuId},
+    rtmp::stream_manager,
+};
 use std::net::SocketAddr;
 use tracing::info;
 
@@ -16,7 +20,14 @@ mod ui;
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = cli::Args::parse();
-    dbg!(&cli);
+
+    if let Some(cli::Command::Password {
+        username,
+        print_only,
+    }) = cli.command
+    {
+        return cli::run_password(cli.config_file, username, print_only);
+    }
 
     // let Ok((config_path, config)) = config::load_config(cli.config_file.clone()) else {
     //     std::process::exit(exitcode::CONFIG);
@@ -24,7 +35,15 @@ async fn main() -> anyhow::Result<()> {
     // let config = cli.merge_with(&config);
 
     logging_init();
-    let (router, api) = router(ReStore{}.into());
+    let broadcast_id = cli::load_broadcast_id(cli.config_file.clone())?;
+    let store = ReStore {
+        channel_manager: ChannelManager::new(&GnuId::new()),
+        manager_sender: stream_manager::start(),
+        // 設定の読み込みが出来るまでは無制限
+        connection_limits: Default::default(),
+        broadcast_id,
+    };
+    let (router, api) = router(store.into());
     let app = router.merge(ui::router());
 
     let listener = tokio::net::TcpListener::bind(("127.0.0.1", 17145))
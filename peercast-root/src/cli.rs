@@ -1,6 +1,11 @@
-use std::process::exit;
+use std::{process::exit, sync::Arc, time::Duration};
 
 use clap::{Parser, Subcommand};
+use ipnet::IpNet;
+use libpeercast_re::{
+    ban::{BanEntry, BanList, parse_net},
+    flood::FloodConfig,
+};
 
 /// Simple Daemon Program
 #[derive(Parser, Debug, Clone)]
@@ -120,6 +125,34 @@ pub struct Args {
     #[arg(long, value_parser, default_value_t=0)]
     pub cache_max_age: u32,
 
+    /// Maximum concurrent PCP connections (0 = unlimited)
+    #[arg(long, default_value_t = 1000)]
+    pub max_connections: usize,
+
+    /// Maximum accepted connections per IP per minute (0 = unlimited)
+    #[arg(long, default_value_t = 120)]
+    pub accepts_per_minute: u32,
+
+    /// Seconds to wait for protocol identification and PCP handshake
+    #[arg(long, default_value_t = 10)]
+    pub handshake_timeout: u64,
+
+    /// Ban an IP after this many failed handshakes in a minute (0 = never)
+    #[arg(long, default_value_t = 10)]
+    pub ban_threshold: u32,
+
+    /// Seconds an IP stays banned
+    #[arg(long, default_value_t = 600)]
+    pub ban_duration: u64,
+
+    /// Refuse connections from these addresses (example: 192.0.2.1,198.51.100.0/24)
+    #[arg(long, value_delimiter = ',', value_parser = parse_net)]
+    pub ban: Vec<IpNet>,
+
+    /// Reverse proxies whose X-Forwarded-For/Forwarded headers are trusted (example: 127.0.0.1,10.0.0.0/8)
+    #[arg(long, value_delimiter = ',', value_parser = parse_net)]
+    pub trusted_proxies: Vec<IpNet>,
+
     #[command(flatten)]
     pub verbose: clap_verbosity_flag::Verbosity<clap_verbosity_flag::InfoLevel>,
 
@@ -135,6 +168,32 @@ pub enum Commands {
     },
 }
 
+impl Args {
+    pub fn flood_config(&self) -> FloodConfig {
+        FloodConfig {
+            max_connections: self.max_connections,
+            accepts_per_minute: self.accepts_per_minute,
+            handshake_timeout: Duration::from_secs(self.handshake_timeout.max(1)),
+            ban_threshold: self.ban_threshold,
+            ban_duration: Duration::from_secs(self.ban_duration),
+            exempt: vec![],
+        }
+    }
+
+    pub fn ban_list(&self) -> Arc<BanList> {
+        BanList::new(
+            self.ban
+                .iter()
+                .map(|&net| BanEntry {
+                    net,
+                    expires_at: None,
+                    comment: String::new(),
+                })
+                .collect(),
+        )
+    }
+}
+
 pub fn version_print(args: &Args) -> anyhow::Result<()> {
     match args.command {
         Some(Commands::Version { json }) => {
@@ -2,7 +2,7 @@
 use std::{
     net::{IpAddr, SocketAddr},
     path::PathBuf,
-    sync::{Arc, Mutex, OnceLock, RwLock},
+    sync::{Arc, LazyLock, Mutex, OnceLock, RwLock},
     time::{Duration, Instant},
 };
 
@@ -10,24 +10,30 @@ use anyhow::Context;
 use axum::{
     extract::Query, http::{HeaderValue, Method}, response::IntoResponse, routing, serve::Listener, Json, Router
 };
+use axum::extract::ConnectInfo;
 use axum_extra::headers::Header;
 use bytes::BytesMut;
 use chrono::{DateTime, TimeZone, Utc};
 use clap::Parser;
 use futures_util::{FutureExt, SinkExt, StreamExt, future::BoxFuture};
+use ipnet::IpNet;
 use itertools::concat;
 use libpeercast_re::{
     ConnectionId, config,
+    connection_registry::{ConnectionRegistry, Protocol},
+    error::HandshakeError,
+    flood::{AcceptPermit, FloodGuard},
+    metrics,
     pcp::{
         ChannelInfo, GnuId, Id4, ParentAtom, PcpConnectionFactory, TrackInfo,
         builder::{QuitBuilder, QuitReason, RootBuilder},
         connection::PcpConnection,
         decode::{PcpBroadcast, PcpChannel, PcpHost},
-        procedure::PcpHandshake,
+        procedure::{PcpHandshake, reject_banned},
     },
     util::{
-        ConnectionProtocol, identify_protocol, mutex_poisoned, rwlock_read_poisoned,
-        rwlock_write_poisoned,
+        ConnectionProtocol, forwarded::client_addr, identify_protocol_timeout, mutex_poisoned,
+        rwlock_read_poisoned, rwlock_write_poisoned,
     },
 };
 use peercast_root::{FooterToml, IndexInfo};
@@ -64,6 +70,8 @@ static _CONN_FACTORY: OnceLock<PcpConnectionFactory> = OnceLock::new();
 static _HTTP_API: OnceLock<Router> = OnceLock::new();
 // Don't use directly. SEE: INDEX_TXT_FOOTER()
 static _INDEX_TXT_FOOTER: OnceLock<Vec<IndexInfo>> = OnceLock::new();
+// Don't use directly. SEE: CONNECTIONS()
+static _CONNECTIONS: OnceLock<ConnectionRegistry> = OnceLock::new();
 
 #[derive(Debug, Clone)]
 struct ApiState {}
@@ -92,7 +100,15 @@ pub fn INDEX_TXT_FOOTER() -> &'static Vec<IndexInfo> {
     _INDEX_TXT_FOOTER.get().unwrap()
 }
 
+#[inline]
+#[allow(non_snake_case)]
+pub fn CONNECTIONS() -> &'static ConnectionRegistry {
+    _CONNECTIONS.get().unwrap()
+}
+
 fn init_app(args: &cli::Args, self_session_id: GnuId, self_socket: SocketAddr) {
+    _CONNECTIONS.get_or_init(ConnectionRegistry::new);
+    //
     _REPOSITORY.get_or_init(|| ChannelRepository::new(&self_session_id));
     //
     _CONN_FACTORY.get_or_init(|| PcpConnectionFactory::new(self_session_id, self_socket));
@@ -189,6 +205,8 @@ async fn server_peercast(
     // スレッドの終了を検知するためのチャンネル
     let (closed_tx, closed_rx) = tokio::sync::watch::channel(());
     let tracker = tokio_util::task::TaskTracker::new();
+    let flood_guard = FloodGuard::new(args.flood_config());
+    let ban_list = args.ban_list();
     info!("START PCP SERVER");
 
     'accept: loop {
@@ -207,7 +225,16 @@ async fn server_peercast(
                 match accept {
                     Ok((stream, addr)) => {
                         println!("{}: accept connection from {}", &name, &addr);
-                        let _handle = spawner.spawn(tracker.track_future(serve_peercast( cid, stream, addr, child_graceful_shutdown, closed_rx.clone())));
+                        // 断る場合は何も返さずに閉じる
+                        let permit = match flood_guard.accept(addr.ip()) {
+                            Ok(permit) => permit,
+                            Err(e) => {
+                                debug!(?cid, ?addr, "reject connection: {e}");
+                                continue 'accept;
+                            }
+                        };
+                        let banned = ban_list.check(addr.ip());
+                        let _handle = spawner.spawn(tracker.track_future(serve_peercast( cid, stream, addr, banned, Arc::clone(&flood_guard), permit, child_graceful_shutdown, closed_rx.clone())));
                         // let _handle = spawner.spawn(serve_peercast( cid, stream, addr, child_graceful_shutdown, closed_rx));
                     }
                     Err(e) => {
@@ -240,13 +267,35 @@ async fn serve_peercast(
     cid: ConnectionId,
     mut stream: TcpStream,
     remote: SocketAddr,
+    banned: bool,
+    flood_guard: Arc<FloodGuard>,
+    _permit: AcceptPermit,
     graceful_shutdown: CancellationToken,
     closed_send: watch::Receiver<()>,
 ) {
     info!(?cid, ?remote, "SPAWN SERVE");
-    match identify_protocol(&stream).await {
+    let timeout = flood_guard.handshake_timeout();
+    match identify_protocol_timeout(&stream, timeout).await {
+        Ok(ConnectionProtocol::PeerCast) if banned => {
+            let session_id = CONN_FACTORY().session_id();
+            match tokio::time::timeout(timeout, reject_banned(&mut stream, remote, session_id))
+                .await
+            {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!(?cid, ?remote, "failed to reject banned: {e}"),
+                Err(_) => warn!(?cid, ?remote, "reject banned timeout"),
+            }
+        }
         Ok(ConnectionProtocol::PeerCast) => {
-            serve_root(cid, stream, remote, graceful_shutdown, closed_send).await
+            serve_root(
+                cid,
+                stream,
+                remote,
+                &flood_guard,
+                graceful_shutdown,
+                closed_send,
+            )
+            .await
         }
         Ok(ConnectionProtocol::PeerCastHttp) => {
             error!("PeerCastHttp is not allowed");
@@ -257,12 +306,17 @@ async fn serve_peercast(
             // serve_http(cid, stream, remote, graceful_shutdown, force_shutdown).await
             let _ = stream.shutdown().await;
         }
+        Ok(ConnectionProtocol::Tls) => {
+            warn!(?cid, ?remote, "STREAM is TLS (not supported)");
+            let _ = stream.shutdown().await;
+        }
         Ok(ConnectionProtocol::Unknown) => {
             warn!(?cid, ?remote, "STREAM is Unkwon Protocol");
             let _ = stream.shutdown().await;
         }
         Err(e) => {
             error!(?cid, ?remote, "Failed: identify_protocol: {}", e);
+            flood_guard.handshake_failed(remote.ip());
             let _ = stream.shutdown().await;
         }
     }
@@ -276,6 +330,7 @@ async fn serve_root(
     cid: ConnectionId,
     mut stream: TcpStream,
     remote: SocketAddr,
+    flood_guard: &FloodGuard,
     graceful_shutdown: CancellationToken,
     closed_send: watch::Receiver<()>,
 ) {
@@ -291,14 +346,26 @@ async fn serve_root(
         .set_next_update_interval(10)
         .build();
 
-    let mut conn = match handshake.incoming(root_atom.into()).await {
-        Err(e) => {
-            todo!();
+    let timeout = flood_guard.handshake_timeout();
+    let mut conn = match tokio::time::timeout(timeout, handshake.incoming(root_atom.into())).await {
+        Err(_) => {
+            warn!(?cid, ?remote, "handshake timeout");
+            metrics::handshake_failed(&HandshakeError::Timeout);
+            flood_guard.handshake_failed(remote.ip());
             return;
         }
-        Ok(HandshakeType::Ping) => return,
-        Ok(HandshakeType::YellowPage(conn)) => conn,
+        Ok(Err(e)) => {
+            // incoming()はPcpErrorしか返さないので種類はFailedとして数える
+            warn!(?cid, ?remote, "handshake failed: {e}");
+            metrics::handshake_failed(&HandshakeError::Failed);
+            flood_guard.handshake_failed(remote.ip());
+            return;
+        }
+        Ok(Ok(HandshakeType::Ping)) => return,
+        Ok(Ok(HandshakeType::YellowPage(conn))) => conn,
     };
+    // 掲載している間は接続一覧に載せる
+    let _handle = CONNECTIONS().register(cid, Protocol::YpLink, remote);
 
     // RootならTrackerに次の情報を送って、情報のアップデートを求める(Broadcastを遅らせる)
     let root_atom = RootBuilder::build_update_request();
@@ -307,13 +374,13 @@ async fn serve_root(
     // 最初のAtomはBroadcastが確定する
     let first_atom = match conn.read_atom().await {
         Ok(a) => a,
-        Err(_) => return,
+        Err(_) => return metrics::yp_announced(false),
     };
     dbg!(&first_atom);
 
     let bcst = match PcpBroadcast::parse(&first_atom) {
         Ok(b) => b,
-        Err(_) => return,
+        Err(_) => return metrics::yp_announced(false),
     };
     dbg!(&bcst);
 
@@ -326,7 +393,7 @@ async fn serve_root(
     } = &bcst;
     let (channel_id_in_bcst, channel_packet) = match (channel_id, channel_packet) {
         (Some(chid), Some(chpkt)) => (chid, chpkt),
-        _ => return,
+        _ => return metrics::yp_announced(false),
     };
     // TODO: HostのIPチェックを行う？
 
@@ -345,12 +412,12 @@ async fn serve_root(
 
     let (channel_id_in_chpkt, braodcast_id) = match (channel_id, broadcast_id) {
         (Some(chid), Some(bcid)) => (chid, bcid),
-        _ => return,
+        _ => return metrics::yp_announced(false),
     };
 
     // 不正チェック
     if channel_id_in_bcst != channel_id_in_chpkt {
-        return;
+        return metrics::yp_announced(false);
     }
 
     // チャンネル情報の変換
@@ -363,6 +430,8 @@ async fn serve_root(
     let repo = REPOSITORY();
     let ch = repo.create_or_get(*channel_id_in_bcst, channel_info, track_info, Some(config));
 
+    metrics::yp_announced(true);
+
     // Channelにコネクションを接続
     let attach_task = ch.attach_connection(conn, graceful_shutdown, closed_send);
     attach_task.await;
@@ -615,10 +684,7 @@ async fn server_http(
     graceful_shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
     use axum::routing::any;
-    use tower_http::{
-        services::ServeDir,
-        trace::{DefaultMakeSpan, TraceLayer},
-    };
+    use tower_http::{services::ServeDir, trace::TraceLayer};
 
     let assets_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
     info!("asset_dir: {:?}", &assets_dir);
@@ -640,10 +706,22 @@ async fn server_http(
         .fallback_service(ServeDir::new(assets_dir).append_index_html_on_directories(true))
         .route("/index.txt", routing::get(index_txt))
         .route("/api/index.json", routing::get(index_json))
+        .route("/metrics", routing::get(get_metrics))
         // logging so we can see what's going on
         .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(DefaultMakeSpan::default().include_headers(true)),
+            TraceLayer::new_for_http().make_span_with(|req: &axum::extract::Request| {
+                let client = req
+                    .extensions()
+                    .get::<ConnectInfo<SocketAddr>>()
+                    .map(|ConnectInfo(addr)| *addr);
+                tracing::debug_span!(
+                    "request",
+                    method = %req.method(),
+                    uri = %req.uri(),
+                    client = ?client,
+                    headers = ?req.headers(),
+                )
+            }),
         )
         .layer(
             CorsLayer::new()
@@ -654,6 +732,11 @@ async fn server_http(
         .layer(SetResponseHeaderLayer::if_not_present(
             axum::http::header::CACHE_CONTROL,
             HeaderValue::from_bytes(cache_control_value.as_bytes()).unwrap()
+        ))
+        // TraceLayerより先にクライアントのアドレスを確定させる
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(args.trusted_proxies.clone()),
+            forwarded_client,
         ));
 
     axum::serve(
@@ -667,6 +750,19 @@ async fn server_http(
     Ok(())
 }
 
+/// 信頼するプロキシから来た場合は、ConnectInfoをプロキシの向こうのクライアントに置き換える
+async fn forwarded_client(
+    axum::extract::State(trusted): axum::extract::State<Arc<Vec<IpNet>>>,
+    mut req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    if let Some(ConnectInfo(addr)) = req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        let client = client_addr(req.headers(), *addr, &trusted);
+        req.extensions_mut().insert(ConnectInfo(client));
+    }
+    next.run(req).await
+}
+
 fn shutdown_signal(
     graceful_shutdown: CancellationToken,
 ) -> BoxFuture<'static, ()> {
@@ -701,6 +797,24 @@ async fn index_txt(
     itertools::join(channels, "\n")
 }
 
+// Repositoryのチャンネル数はlibpeercast_re::metricsと同じレジストリに登録する
+static REPOSITORY_SIZE: LazyLock<prometheus::IntGauge> = LazyLock::new(|| {
+    prometheus::register_int_gauge!(
+        "peercast_root_repository_channels",
+        "Number of channels in the root repository"
+    )
+    .unwrap()
+});
+
+async fn get_metrics() -> impl IntoResponse {
+    REPOSITORY_SIZE.set(REPOSITORY().get_channels().len() as i64);
+    metrics::observe_connections(CONNECTIONS());
+    (
+        [(axum::http::header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        metrics::gather(),
+    )
+}
+
 async fn index_json() -> Json<Vec<JsonChannel>> {
     merged_channels().into()
 }